@@ -3,22 +3,29 @@ use std::net::{UdpSocket, SocketAddr};
 
 use std::io;
 
-use twinstick_logic::{BUFFER_SIZE, VERSION, DataType};
+use twinstick_logic::{BUFFER_SIZE, DataType, Handshake, Features, ConnectionRejection, TwinstickError};
 
 pub struct TwinstickClient {
   udp: UdpSocket,
   server: String,
   disconnected: bool,
+  protocol_version: Option<u32>,
+  features: Features,
+  rejection: Option<ConnectionRejection>,
+  server_error: Option<String>,
+  closed_reason: Option<String>,
 }
 
 impl Drop for TwinstickClient {
   fn drop(&mut self) {
-    self.disconnect();
+    if let Err(e) = self.disconnect() {
+      println!("Failed to notify server of disconnect: {}", e);
+    }
   }
 }
 
 impl TwinstickClient {
-  pub fn new(ip: &str) -> TwinstickClient {
+  pub fn new(ip: &str) -> Result<TwinstickClient, TwinstickError> {
     println!("Attempting to connect to server {}", ip);
     let addrs = [
       /*SocketAddr::from(([127, 0, 0, 1], 8010)),
@@ -38,94 +45,137 @@ impl TwinstickClient {
       SocketAddr::from(([0, 0, 0, 0], 8016)),
       SocketAddr::from(([0, 0, 0, 0], 8017)),
     ];
-    let udp = UdpSocket::bind(&addrs[..]).unwrap();
-    udp.set_nonblocking(true).unwrap();
+    let udp = UdpSocket::bind(&addrs[..])?;
+    udp.set_nonblocking(true)?;
     
-    TwinstickClient {
+    Ok(TwinstickClient {
       udp,
       server: ip.to_string(),
       disconnected: true,
-    }
+      protocol_version: None,
+      features: Features::NONE,
+      rejection: None,
+      server_error: None,
+      closed_reason: None,
+    })
   }
   
   pub fn disconnected(&self) -> bool {
     self.disconnected
   }
   
-  pub fn connect(&mut self) {
-    if !self.disconnected {
-      return;
+  pub fn protocol_version(&self) -> Option<u32> {
+    self.protocol_version
+  }
+  
+  pub fn features(&self) -> Features {
+    self.features
+  }
+  
+  // Set when the server refused the connection, the client stops retrying
+  pub fn rejection(&self) -> Option<&ConnectionRejection> {
+    self.rejection.as_ref()
+  }
+  
+  pub fn server_error(&self) -> Option<&String> {
+    self.server_error.as_ref()
+  }
+  
+  // Set when the server kicked us or shut down, the client stops retrying
+  pub fn closed_reason(&self) -> Option<&String> {
+    self.closed_reason.as_ref()
+  }
+  
+  pub fn connect(&mut self) -> Result<(), TwinstickError> {
+    if let Some(rejection) = &self.rejection {
+      return Err(TwinstickError::Rejected(rejection.clone()));
     }
     
-    match self.udp.connect(self.server.clone()) {
-      Ok(_) => {
-        //self.disconnected = false;
-        if self.disconnected {
-          self.send_datatype(DataType::TryConnect(VERSION));
-        }
-      },
-      Err(_e) => {
-        //self.disconnected = true;
-      },
+    if self.closed_reason.is_some() {
+      return Err(TwinstickError::NotConnected);
     }
     
+    if !self.disconnected {
+      return Ok(());
+    }
     
+    self.udp.connect(self.server.clone())?;
+    self.send_datatype(DataType::Handshake(Handshake::new()))
   }
   
-  pub fn send_datatype(&mut self, data_type: DataType) {
-    let _ = self.udp.send(&data_type.serialise()).is_ok();
+  pub fn send_datatype(&mut self, data_type: DataType) -> Result<(), TwinstickError> {
+    self.udp.send(&data_type.serialise()?)?;
+    
+    Ok(())
   }
   
-  pub fn send(&mut self) {
+  pub fn send(&mut self) -> Result<(), TwinstickError> {
     if self.disconnected {
-      return;
+      return Err(TwinstickError::NotConnected);
     }
     
     let resposne = [10, 9, 8, 7, 6, 5, 4, 3, 2, 1];
-    match self.udp.send(&resposne) {
-      Ok(_) => { },
-      Err(_e) => { self.disconnected = true; }, //println!("{:?}",e);},
+    if let Err(e) = self.udp.send(&resposne) {
+      self.disconnected = true;
+      return Err(e.into());
     }
+    
+    Ok(())
   }
   
-  pub fn recieve(&mut self) -> Option<DataType> {
+  // Ok(None) when there is nothing waiting on the socket
+  pub fn recieve(&mut self) -> Result<Option<DataType>, TwinstickError> {
     let mut buffer = [0; BUFFER_SIZE];
     
     match self.udp.recv_from(&mut buffer) {
       Ok((number_of_bytes, _src_addr)) => {
         let filled_buf = &mut buffer[..number_of_bytes];
-        let dt = DataType::deserialise(filled_buf);
-        if dt.is_some() {
-          match dt.clone().unwrap() {
-            DataType::ConfirmConnect(v) => {
-              println!("Confrim connection {}", v);
-              self.disconnected = false;
-            },
-            DataType::Err(e) => { panic!("{}", e); },
-            _ => {},
-          }
+        let dt = DataType::deserialise(filled_buf)?;
+        match &dt {
+          DataType::HandshakeAccepted(accepted) => {
+            println!("Confrim connection {}", accepted.version);
+            self.protocol_version = Some(accepted.version);
+            self.features = accepted.features;
+            self.disconnected = false;
+          },
+          DataType::Rejected(rejection) => {
+            println!("Connection rejected: {}", rejection);
+            self.rejection = Some(rejection.clone());
+            self.disconnected = true;
+          },
+          DataType::Kicked(reason) => {
+            println!("Kicked from server: {}", reason);
+            self.closed_reason = Some(format!("Kicked: {}", reason));
+            self.disconnected = true;
+          },
+          DataType::ServerShutdown(reason) => {
+            println!("Server shutting down: {}", reason);
+            self.closed_reason = Some(format!("Server closed: {}", reason));
+            self.disconnected = true;
+          },
+          DataType::Err(e) => {
+            println!("Server error: {}", e);
+            self.server_error = Some(e.to_string());
+          },
+          _ => {},
         }
         
-        return dt;
+        Ok(Some(dt))
       },
       Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
-            // wait until network socket is ready, typically implemented
-            // via platform-specific APIs such as epoll or IOCP
-            //wait_for_fd();
+        Ok(None)
       },
-      Err(_e) => {}, //panic!("encountered IO error: {}", e),
+      Err(e) => Err(e.into()),
     }
-    
-    None
   }
   
-  pub fn disconnect(&mut self) {
+  pub fn disconnect(&mut self) -> Result<(), TwinstickError> {
     if self.disconnected {
-      return;
+      return Ok(());
     }
     
-    self.udp.send(&DataType::Exit.serialise()).unwrap();
     self.disconnected = true;
+    self.send_datatype(DataType::Exit)
   }
 }
 
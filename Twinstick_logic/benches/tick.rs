@@ -0,0 +1,105 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+
+use twinstick_logic::{TwinstickGame, TickObjects, World, CollisionGrid, CollisionMatrix, GenericObject, Character, Enemy, Bullet,
+                      Vector3, SECTION_SIZE};
+
+// Cheap deterministic spread so every run benches the same layout
+fn scatter(seed: &mut u64, extent: f64) -> f64 {
+  *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+  ((*seed >> 33) as f64 / (1u64 << 31) as f64 - 0.5) * extent
+}
+
+struct Scene {
+  players: Vec<Box<dyn GenericObject>>,
+  enemies: Vec<Box<dyn GenericObject>>,
+  player_bullets: Vec<Box<dyn GenericObject>>,
+  static_objects: Vec<Box<dyn GenericObject>>,
+  grid: CollisionGrid,
+  single_cell: CollisionGrid,
+}
+
+fn scene(range: u32, bullets: usize) -> Scene {
+  let mut world = World::new(SECTION_SIZE);
+  world.load_area(0, 0, 0, range);
+  let static_objects = world.objects().clone();
+
+  // Everything in one cell is the same as testing every pair
+  let mut single_cell = CollisionGrid::new(f64::MAX);
+  for object in &static_objects {
+    single_cell.insert(&object.collision_data());
+  }
+
+  let extent = range as f64 * SECTION_SIZE;
+  let mut seed = 1;
+  let mut players: Vec<Box<dyn GenericObject>> = Vec::new();
+  let mut enemies: Vec<Box<dyn GenericObject>> = Vec::new();
+  for _ in 0..4 {
+    players.push(Box::new(Character::new(Vector3::new(scatter(&mut seed, extent), 10.0, scatter(&mut seed, extent)),
+                                         Vector3::new_same(1.0))));
+    enemies.push(Box::new(Enemy::new(Vector3::new(scatter(&mut seed, extent), 10.0, scatter(&mut seed, extent)),
+                                     Vector3::new_same(2.0), "enemy".to_string())));
+  }
+
+  let mut player_bullets: Vec<Box<dyn GenericObject>> = Vec::new();
+  for _ in 0..bullets {
+    let pos = Vector3::new(scatter(&mut seed, extent), 12.0, scatter(&mut seed, extent));
+    let rotation = scatter(&mut seed, 360.0);
+    player_bullets.push(Box::new(Bullet::new(pos, Vector3::new_same(1.0), rotation, 16.0, "bullet".to_string())));
+  }
+
+  Scene {
+    players,
+    enemies,
+    player_bullets,
+    grid: world.static_grid().clone(),
+    static_objects,
+    single_cell,
+  }
+}
+
+fn tick(scene: &Scene, grid: &CollisionGrid) -> impl FnMut() {
+  let mut players = scene.players.clone();
+  let mut enemies = scene.enemies.clone();
+  let mut player_bullets = scene.player_bullets.clone();
+  let mut static_objects = scene.static_objects.clone();
+  let grid = grid.clone();
+
+  move || {
+    let objects = TickObjects {
+      players: &mut players,
+      enemies: &mut enemies,
+      player_bullets: &mut player_bullets,
+      enemy_bullets: &mut Vec::new(),
+      static_objects: &mut static_objects,
+      static_grid: &grid,
+      interactives: &mut Vec::new(),
+      platforms: &mut Vec::new(),
+      dynamic_objects: &mut Vec::new(),
+    };
+    TwinstickGame::update(objects, &CollisionMatrix::new(), None, 1.0/60.0);
+  }
+}
+
+fn tick_time(c: &mut Criterion) {
+  let mut group = c.benchmark_group("tick");
+  group.sample_size(20);
+
+  for range in [5, 11, 21].iter() {
+    for bullets in [100, 1000].iter() {
+      let scene = scene(*range, *bullets);
+      let label = format!("{}_sections/{}_bullets", range*range, bullets);
+
+      group.bench_with_input(BenchmarkId::new("grid", &label), &scene, |b, scene| {
+        b.iter_batched(|| tick(scene, &scene.grid), |mut update| update(), BatchSize::SmallInput)
+      });
+      group.bench_with_input(BenchmarkId::new("all_pairs", &label), &scene, |b, scene| {
+        b.iter_batched(|| tick(scene, &scene.single_cell), |mut update| update(), BatchSize::SmallInput)
+      });
+    }
+  }
+
+  group.finish();
+}
+
+criterion_group!(benches, tick_time);
+criterion_main!(benches);
@@ -0,0 +1,89 @@
+// Commands an operator can run against a live server, either typed into the
+// server console or sent by a remote client holding the admin token.
+
+pub const ADMIN_USAGE: &str = "commands: players | kick <addr> | ban <addr> | unban <addr> | tickrate <hz> | \
+spawn <x> <y> <z> | reload | say <message> | shutdown [reason]";
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum AdminCommand {
+  ListPlayers,
+  Kick(String),
+  Ban(String),
+  Unban(String),
+  TickRate(u32),
+  SpawnEnemy(f64, f64, f64),
+  ReloadMap,
+  Broadcast(String),
+  Shutdown(String),
+}
+
+impl AdminCommand {
+  pub fn parse(line: &str) -> Result<AdminCommand, String> {
+    let line = line.trim();
+    let (command, args) = match line.find(char::is_whitespace) {
+      Some(i) => (&line[..i], line[i..].trim()),
+      None => (line, ""),
+    };
+
+    let require_arg = |name: &str| -> Result<String, String> {
+      if args.is_empty() {
+        Err(format!("{} needs an argument", name))
+      } else {
+        Ok(args.to_string())
+      }
+    };
+
+    match command.to_lowercase().as_str() {
+      "players" | "list" => Ok(AdminCommand::ListPlayers),
+      "kick" => Ok(AdminCommand::Kick(require_arg("kick")?)),
+      "ban" => Ok(AdminCommand::Ban(require_arg("ban")?)),
+      "unban" => Ok(AdminCommand::Unban(require_arg("unban")?)),
+      "tickrate" => {
+        let rate = require_arg("tickrate")?;
+        rate.parse::<u32>().map(AdminCommand::TickRate).map_err(|_| format!("invalid tick rate '{}'", rate))
+      },
+      "spawn" => {
+        let coords = args.split_whitespace().map(|c| c.parse::<f64>()).collect::<Result<Vec<f64>, _>>();
+        match coords {
+          Ok(ref c) if c.len() == 3 => Ok(AdminCommand::SpawnEnemy(c[0], c[1], c[2])),
+          _ => Err("spawn needs a position: spawn <x> <y> <z>".to_string()),
+        }
+      },
+      "reload" => Ok(AdminCommand::ReloadMap),
+      "say" | "broadcast" => Ok(AdminCommand::Broadcast(require_arg("say")?)),
+      "shutdown" | "quit" => {
+        if args.is_empty() {
+          Ok(AdminCommand::Shutdown("Server shutting down".to_string()))
+        } else {
+          Ok(AdminCommand::Shutdown(args.to_string()))
+        }
+      },
+      "" => Err(ADMIN_USAGE.to_string()),
+      unknown => Err(format!("unknown command '{}', {}", unknown, ADMIN_USAGE)),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parses_console_commands() {
+    assert_eq!(AdminCommand::parse("players"), Ok(AdminCommand::ListPlayers));
+    assert_eq!(AdminCommand::parse("kick 127.0.0.1:8010"), Ok(AdminCommand::Kick("127.0.0.1:8010".to_string())));
+    assert_eq!(AdminCommand::parse("  TICKRATE 30 "), Ok(AdminCommand::TickRate(30)));
+    assert_eq!(AdminCommand::parse("spawn 1 20 -3.5"), Ok(AdminCommand::SpawnEnemy(1.0, 20.0, -3.5)));
+    assert_eq!(AdminCommand::parse("say hello  there"), Ok(AdminCommand::Broadcast("hello  there".to_string())));
+    assert_eq!(AdminCommand::parse("shutdown"), Ok(AdminCommand::Shutdown("Server shutting down".to_string())));
+    assert_eq!(AdminCommand::parse("shutdown back in 5"), Ok(AdminCommand::Shutdown("back in 5".to_string())));
+  }
+
+  #[test]
+  fn rejects_bad_arguments() {
+    assert!(AdminCommand::parse("kick").is_err());
+    assert!(AdminCommand::parse("tickrate fast").is_err());
+    assert!(AdminCommand::parse("spawn 1 2").is_err());
+    assert!(AdminCommand::parse("explode").is_err());
+  }
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use crate::{GenericObject, CollisionInfo};
+use crate::narrow_phase::rotated_half_extents;
+
+// Uniform grid over the xz plane using the same cells as World sections, so an
+// object at section (x, z) lands in cell (x, z). Height is ignored, the world is flat.
+#[derive(Clone)]
+pub struct CollisionGrid {
+  cell_size: f64,
+  cells: HashMap<(i32, i32), Vec<usize>>,
+  len: usize,
+}
+
+// Min and max corners on the xz plane
+pub fn xz_bounds(info: &CollisionInfo) -> ((f64, f64), (f64, f64)) {
+  match info {
+    CollisionInfo::AABB(pos, size, rotation) => {
+      let half = rotated_half_extents(size, rotation);
+      ((pos.x - half.x, pos.z - half.z), (pos.x + half.x, pos.z + half.z))
+    },
+    CollisionInfo::Sphere(sphere) => {
+      ((sphere.x - sphere.w, sphere.z - sphere.w), (sphere.x + sphere.w, sphere.z + sphere.w))
+    },
+    CollisionInfo::Point(point) => {
+      ((point.x, point.z), (point.x, point.z))
+    },
+    CollisionInfo::Compound(parts) => {
+      parts.iter().map(|(part, _)| xz_bounds(part)).fold(None, |bounds: Option<((f64, f64), (f64, f64))>, (min, max)| match bounds {
+        Some((b_min, b_max)) => Some(((b_min.0.min(min.0), b_min.1.min(min.1)), (b_max.0.max(max.0), b_max.1.max(max.1)))),
+        None => Some((min, max)),
+      }).unwrap_or(((0.0, 0.0), (0.0, 0.0)))
+    },
+  }
+}
+
+impl CollisionGrid {
+  pub fn new(cell_size: f64) -> CollisionGrid {
+    CollisionGrid {
+      cell_size,
+      cells: HashMap::new(),
+      len: 0,
+    }
+  }
+  
+  // For things that move every tick it is cheaper to rebuild than to track them
+  pub fn build(cell_size: f64, objects: &Vec<Box<dyn GenericObject>>) -> CollisionGrid {
+    let mut grid = CollisionGrid::new(cell_size);
+    for object in objects {
+      grid.insert(&object.collision_data());
+    }
+    
+    grid
+  }
+  
+  pub fn cell_size(&self) -> f64 {
+    self.cell_size
+  }
+  
+  // Number of objects inserted, indexs handed out are 0..len in insert order
+  pub fn len(&self) -> usize {
+    self.len
+  }
+  
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+  
+  pub fn occupied_cells(&self) -> usize {
+    self.cells.len()
+  }
+  
+  pub fn clear(&mut self) {
+    self.cells.clear();
+    self.len = 0;
+  }
+  
+  pub fn cell_index(&self, x: f64, z: f64) -> (i32, i32) {
+    ((x / self.cell_size + 0.5).floor() as i32, (z / self.cell_size + 0.5).floor() as i32)
+  }
+  
+  fn cells_overlapping(&self, min: (f64, f64), max: (f64, f64)) -> Vec<(i32, i32)> {
+    let (min_x, min_z) = self.cell_index(min.0, min.1);
+    let (max_x, max_z) = self.cell_index(max.0, max.1);
+    
+    let mut cells = Vec::new();
+    for x in min_x..=max_x {
+      for z in min_z..=max_z {
+        cells.push((x, z));
+      }
+    }
+    
+    cells
+  }
+  
+  // Returns the index the object was given, matching its position in the caller's Vec
+  pub fn insert(&mut self, info: &CollisionInfo) -> usize {
+    let index = self.len;
+    let (min, max) = xz_bounds(info);
+    for cell in self.cells_overlapping(min, max) {
+      self.cells.entry(cell).or_default().push(index);
+    }
+    self.len += 1;
+    
+    index
+  }
+  
+  // Everything sharing a cell with the shape, sorted and without duplicates
+  pub fn query(&self, info: &CollisionInfo) -> Vec<usize> {
+    let (min, max) = xz_bounds(info);
+    
+    let mut found = Vec::new();
+    for cell in self.cells_overlapping(min, max) {
+      if let Some(indexs) = self.cells.get(&cell) {
+        found.extend_from_slice(indexs);
+      }
+    }
+    
+    found.sort_unstable();
+    found.dedup();
+    found
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Vector3, Vector4};
+  
+  fn aabb(x: f64, z: f64, size: f64) -> CollisionInfo {
+    CollisionInfo::AABB(Vector3::new(x, 0.0, z), Vector3::new_same(size), Vector4::new(1.0, 0.0, 0.0, 0.0))
+  }
+  
+  #[test]
+  fn cells_match_world_sections() {
+    let grid = CollisionGrid::new(40.0);
+    
+    assert_eq!(grid.cell_index(0.0, 0.0), (0, 0));
+    assert_eq!(grid.cell_index(19.9, -19.9), (0, 0));
+    assert_eq!(grid.cell_index(-20.1, 20.1), (-1, 1));
+    assert_eq!(grid.cell_index(-80.0, 120.0), (-2, 3));
+  }
+  
+  #[test]
+  fn only_nearby_objects_are_returned() {
+    let mut grid = CollisionGrid::new(40.0);
+    let near = grid.insert(&aabb(0.0, 0.0, 2.0));
+    let far = grid.insert(&aabb(400.0, 400.0, 2.0));
+    let point = grid.insert(&CollisionInfo::Point(Vector3::new(5.0, 3.0, 5.0)));
+    
+    let found = grid.query(&CollisionInfo::Sphere(Vector4::new(1.0, 0.0, 1.0, 1.0)));
+    assert_eq!(found, vec!(near, point));
+    assert!(!found.contains(&far));
+    assert_eq!(grid.len(), 3);
+  }
+  
+  #[test]
+  fn large_objects_are_found_from_every_cell_they_cover() {
+    let mut grid = CollisionGrid::new(40.0);
+    // Spans sections -1..=1 along x
+    let wall = grid.insert(&aabb(0.0, 0.0, 100.0));
+    
+    assert_eq!(grid.query(&CollisionInfo::Point(Vector3::new(-45.0, 0.0, 0.0))), vec!(wall));
+    assert_eq!(grid.query(&CollisionInfo::Point(Vector3::new(45.0, 0.0, 45.0))), vec!(wall));
+    assert!(grid.query(&CollisionInfo::Point(Vector3::new(90.0, 0.0, 0.0))).is_empty());
+  }
+  
+  #[test]
+  fn rotated_boxes_cover_their_ends() {
+    let mut grid = CollisionGrid::new(40.0);
+    // 20 long along z, turned a quarter it runs along x past the cell edge at 20
+    let wall = grid.insert(&CollisionInfo::AABB(Vector3::new(15.0, 0.0, 0.0), Vector3::new(1.0, 1.0, 20.0),
+                                                crate::narrow_phase::quaternion_from_euler(&Vector3::new(0.0, 90.0, 0.0))));
+    
+    assert_eq!(grid.query(&CollisionInfo::Point(Vector3::new(23.0, 0.0, 0.0))), vec!(wall));
+    assert_eq!(grid.occupied_cells(), 2);
+  }
+  
+  #[test]
+  fn objects_on_a_cell_edge_are_returned_once() {
+    let mut grid = CollisionGrid::new(40.0);
+    let edge = grid.insert(&aabb(20.0, 20.0, 4.0));
+    
+    assert_eq!(grid.occupied_cells(), 4);
+    assert_eq!(grid.query(&aabb(20.0, 20.0, 4.0)), vec!(edge));
+  }
+}
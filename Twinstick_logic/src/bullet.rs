@@ -1,16 +1,21 @@
-use crate::{Vector2, Vector3, ObjectData, GenericObject};
+use crate::{Vector3, ObjectData, GenericObject, Contact, Categories};
 use crate::{math, DrawCall};
 
+// Speed given to whatever gets hit, along the bullet's path
+const KNOCKBACK: f64 = 4.0;
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Bullet {
   pub data: ObjectData,
   duration: f64,
   speed: f64,
+  start: Vector3,
 }
 
 impl Bullet {
   pub fn new(pos: Vector3, size: Vector3, rotation: f64, additional_speed: f64, model: String) -> Bullet {
-    let mut data = ObjectData::new(pos, size, model);
+    // Player bullets unless told otherwise
+    let mut data = ObjectData::new(pos.clone(), size, model).dynamic_physics().layer(Categories::PLAYER_BULLETS);
     data.rotation.y = rotation;
     
     data.vel.x = 1.0*math::to_radians(rotation).sin();
@@ -20,8 +25,15 @@ impl Bullet {
       data,
       duration: 3.0,
       speed: 28.0 + additional_speed,
+      start: pos,
     }
   }
+  
+  pub fn layer(mut self, layer: Categories) -> Bullet {
+    self.data = self.data.layer(layer);
+    
+    self
+  }
 }
 
 impl GenericObject for Bullet {
@@ -33,12 +45,14 @@ impl GenericObject for Bullet {
     &mut self.data
   }
   
-  fn collided_with_static_object(&mut self, static_object: &mut Box<dyn GenericObject>) {
+  fn collided_with_static_object(&mut self, static_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
+    static_object.shot(self.damage());
     self.mut_data().life = 0;
   }
   
-  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>) {
-    dynamic_object.take_damage(self.damage());
+  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>, contact: &Contact) {
+    dynamic_object.take_damage(self.damage()*contact.other_region.damage_multiplier());
+    dynamic_object.apply_impulse(&Vector3::new(self.data().vel.x, 0.0, self.data().vel.z).scale(KNOCKBACK));
     self.mut_data().life = 0;
   }
   
@@ -57,6 +71,7 @@ impl GenericObject for Bullet {
   fn physics_update(&mut self, delta_time: f64) {
    // self.mut_data().pos.x += self.data().rel_vel.x*math::to_radians(self.rotation().y).cos() * delta_time;
     //self.mut_data().pos.z += self.data().rel_vel.z*math::to_radians(self.rotation().y).cos() * delta_time;
+    self.start = self.data().pos.clone();
     self.mut_data().pos.x += self.data().vel.x * self.speed * delta_time;
     self.mut_data().pos.z += self.data().vel.z * self.speed * delta_time;
   }
@@ -64,6 +79,10 @@ impl GenericObject for Bullet {
   fn additional_draws(&self, draw_calls: &mut Vec<DrawCall>) {
     
   }
+  
+  fn swept_from(&self) -> Option<Vector3> {
+    Some(self.start.clone())
+  }
 }
 
 
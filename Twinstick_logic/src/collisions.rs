@@ -1,288 +1,555 @@
-use maat_graphics::math;
+use crate::{GenericObject, CollisionGrid, CollisionInfo, Contact, Vector3, Categories, EntityId, ObjectData, ObjectPhysicsType};
+use crate::narrow_phase;
 
-use crate::{GenericObject, CollisionInfo};
+// Which layers interact with which, always both ways round. On top of this each
+// object's mask has to include the other's layer, so single objects can opt out
+// of things the rest of their layer touches.
+#[derive(PartialEq, Debug, Clone)]
+pub struct CollisionMatrix {
+  masks: [Categories; 32],
+}
 
-pub fn collide_dynamic_with_dynamic(dyn_objects_a: &mut Vec<Box<dyn GenericObject>>,
-                                    dyn_objects_b: &mut Vec<Box<dyn GenericObject>>) {
-  for i in 0..dyn_objects_a.len() {
-    for j in 0..dyn_objects_b.len() {
-      let player_collision_data = dyn_objects_a[i].collision_data();
-      let static_collision_data = dyn_objects_b[j].collision_data();
-      let did_collide;
+impl CollisionMatrix {
+  // Walls stop everything, bullets hit whoever didn't fire them and triggers
+  // notice players and enemies
+  pub fn new() -> CollisionMatrix {
+    let everything_but_statics = Categories::PLAYERS.with(Categories::ENEMIES)
+                                                   .with(Categories::PLAYER_BULLETS)
+                                                   .with(Categories::ENEMY_BULLETS);
+    CollisionMatrix::empty()
+      .allow(Categories::STATIC, everything_but_statics)
+      .allow(Categories::ENEMIES, Categories::PLAYER_BULLETS)
+      .allow(Categories::PLAYERS, Categories::ENEMY_BULLETS)
+      .allow(Categories::TRIGGERS, Categories::PLAYERS.with(Categories::ENEMIES))
+  }
+  
+  // Nothing interacts
+  pub fn empty() -> CollisionMatrix {
+    CollisionMatrix {
+      masks: [Categories::NONE; 32],
+    }
+  }
+  
+  pub fn allow(mut self, a: Categories, b: Categories) -> CollisionMatrix {
+    self.set(a, b, true);
+    self
+  }
+  
+  pub fn set(&mut self, a: Categories, b: Categories, allowed: bool) {
+    for bit in 0..32 {
+      for (from, to) in [(a, b), (b, a)].iter() {
+        if from.contains(Categories(1 << bit)) {
+          self.masks[bit] = if allowed { self.masks[bit].with(*to) } else { self.masks[bit].without(*to) };
+        }
+      }
+    }
+  }
+  
+  pub fn allows(&self, a: Categories, b: Categories) -> bool {
+    (0..32).any(|bit| a.contains(Categories(1 << bit)) && self.masks[bit].intersects(b))
+  }
+  
+  pub fn interacts(&self, a: &ObjectData, b: &ObjectData) -> bool {
+    a.physics_type != ObjectPhysicsType::Decorative &&
+    b.physics_type != ObjectPhysicsType::Decorative &&
+    self.allows(a.layer, b.layer) &&
+    a.mask.intersects(b.layer) &&
+    b.mask.intersects(a.layer)
+  }
+}
+
+impl Default for CollisionMatrix {
+  fn default() -> CollisionMatrix {
+    CollisionMatrix::new()
+  }
+}
+
+// Something touched a trigger this tick, ids are into the Vecs handed to the collision pass
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TriggerOverlap {
+  pub trigger: EntityId,
+  pub other: EntityId,
+}
+
+// One category's objects as a single Vec
+pub struct CollisionGroup<'a> {
+  pub category: Categories,
+  pub objects: &'a mut Vec<Box<dyn GenericObject>>,
+}
+
+impl<'a> CollisionGroup<'a> {
+  pub fn new(category: Categories, objects: &'a mut Vec<Box<dyn GenericObject>>) -> CollisionGroup<'a> {
+    CollisionGroup {
+      category,
+      objects,
+    }
+  }
+}
+
+// Every layer present, so whole groups can be skipped when the matrix rules them out
+fn layers_of(objects: &Vec<Box<dyn GenericObject>>) -> Categories {
+  objects.iter()
+         .filter(|o| o.data().physics_type != ObjectPhysicsType::Decorative)
+         .fold(Categories::NONE, |layers, o| layers.with(o.data().layer))
+}
+
+// Box around everything a swept object passed through this tick, for the broad phase
+fn sweep_bounds(start: &Vector3, end: &Vector3, half: &Vector3) -> CollisionInfo {
+  let centre = start.add(end).scale(0.5);
+  let d = end.sub(start);
+  let size = Vector3::new(d.x.abs(), d.y.abs(), d.z.abs()).add(&half.scale(2.0));
+  CollisionInfo::AABB(centre, size, narrow_phase::identity())
+}
+
+// Nearest solid target along the path a swept object took this tick, the contact
+// is seen from the swept object. Triggers don't stop anything.
+fn first_hit(swept: &dyn GenericObject,
+             start: &Vector3,
+             targets: &[Box<dyn GenericObject>],
+             grid: &CollisionGrid,
+             matrix: &CollisionMatrix) -> Option<(usize, Contact)> {
+  let end = swept.position();
+  let half = swept.hitbox_size().scale(0.5);
+  
+  let mut first: Option<(usize, f64, Contact)> = None;
+  for i in grid.query(&sweep_bounds(start, end, &half)) {
+    if i >= targets.len() || targets[i].data().trigger || !matrix.interacts(swept.data(), targets[i].data()) {
+      continue;
+    }
+    
+    if let Some((t, contact)) = narrow_phase::sweep(start, end, &half, &targets[i].collision_data()) {
+      if first.as_ref().map(|(_, best, _)| t < *best).unwrap_or(true) {
+        first = Some((i, t, contact));
+      }
+    }
+  }
+  
+  first.map(|(i, _, contact)| (i, contact.flipped()))
+}
+
+// Both sides of a dynamic pair are told, unless one is a trigger
+fn respond(a: &mut Box<dyn GenericObject>, a_id: EntityId,
+           b: &mut Box<dyn GenericObject>, b_id: EntityId,
+           contact: &Contact,
+           overlaps: &mut Vec<TriggerOverlap>) {
+  if a.data().trigger {
+    overlaps.push(TriggerOverlap { trigger: a_id, other: b_id });
+  } else if b.data().trigger {
+    overlaps.push(TriggerOverlap { trigger: b_id, other: a_id });
+  } else {
+    a.collided_with_dynamic_object(b, contact);
+    b.collided_with_dynamic_object(a, &contact.flipped());
+  }
+}
+
+// Swept objects in a find the first thing in b along their path
+fn sweep_group_into(a: &mut CollisionGroup, b: &mut CollisionGroup, grid_b: &CollisionGrid,
+                    matrix: &CollisionMatrix, overlaps: &mut Vec<TriggerOverlap>) {
+  for i in 0..a.objects.len() {
+    if let Some(start) = a.objects[i].swept_from() {
+      if a.objects[i].is_dead() {
+        continue;
+      }
       
-      match player_collision_data {
-        CollisionInfo::AABB(box_a_location, box_a_size, _box_a_rotation) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::intersect_AABB(box_a_location.to_cgmath(), box_a_size.to_cgmath(),
-                                                         box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::sphere_intersect_AABB(sphere_b.to_cgmath(), 
-                                                        box_a_location.to_cgmath(), box_a_size.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = math::is_point_inside_AABB(point_b.to_cgmath(), 
-                                                       box_a_location.to_cgmath(), box_a_size.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-          }
-        },
-        CollisionInfo::Sphere(sphere_a) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::sphere_intersect_AABB(sphere_a.to_cgmath(), 
-                                                        box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::intersect_sphere(sphere_a.to_cgmath(),
-                                                   sphere_b.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = math::is_point_inside_sphere(point_b.to_cgmath(), 
-                                                         sphere_a.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-          }
-        },
-        CollisionInfo::Point(point_a) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::is_point_inside_AABB(point_a.to_cgmath(), 
-                                                       box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::is_point_inside_sphere(point_a.to_cgmath(), 
-                                                         sphere_b.to_cgmath());
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = point_a == point_b;
-              if did_collide {
-                dyn_objects_b[j].collided_with_dynamic_object(&mut dyn_objects_a[i]);
-              }
-            },
-          }
-        },
+      if let Some((j, contact)) = first_hit(a.objects[i].as_ref(), &start, b.objects, grid_b, matrix) {
+        a.objects[i].set_position(contact.point.clone());
+        respond(&mut a.objects[i], EntityId::new(a.category, i), &mut b.objects[j], EntityId::new(b.category, j), &contact, overlaps);
       }
     }
   }
 }
 
-pub fn collide_static_with_dynamic(static_objects: &mut Vec<Box<dyn GenericObject>>,
-                                   dyn_objects: &mut Vec<Box<dyn GenericObject>>) {
-  for i in 0..dyn_objects.len() {
-    for j in 0..static_objects.len() {
-      let player_collision_data = dyn_objects[i].collision_data();
-      let static_collision_data = static_objects[j].collision_data();
-      let did_collide;
+// Neither side is tracked between ticks, so both get throwaway grids
+pub fn collide_dynamic_with_dynamic(a: &mut CollisionGroup,
+                                    b: &mut CollisionGroup,
+                                    cell_size: f64,
+                                    matrix: &CollisionMatrix,
+                                    overlaps: &mut Vec<TriggerOverlap>) {
+  if a.objects.is_empty() || b.objects.is_empty() {
+    return;
+  }
+  
+  let grid_a = CollisionGrid::build(cell_size, a.objects);
+  let grid_b = CollisionGrid::build(cell_size, b.objects);
+  sweep_group_into(a, b, &grid_b, matrix, overlaps);
+  sweep_group_into(b, a, &grid_a, matrix, overlaps);
+  
+  for i in 0..a.objects.len() {
+    if a.objects[i].swept_from().is_some() {
+      continue;
+    }
+    
+    for j in grid_b.query(&a.objects[i].collision_data()) {
+      if b.objects[j].swept_from().is_some() || !matrix.interacts(a.objects[i].data(), b.objects[j].data()) {
+        continue;
+      }
       
-      match player_collision_data {
-        CollisionInfo::AABB(box_a_location, box_a_size, _box_a_rotation) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::intersect_AABB(box_a_location.to_cgmath(), box_a_size.to_cgmath(),
-                                                         box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::sphere_intersect_AABB(sphere_b.to_cgmath(), 
-                                                        box_a_location.to_cgmath(), box_a_size.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = math::is_point_inside_AABB(point_b.to_cgmath(), 
-                                                       box_a_location.to_cgmath(), box_a_size.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-          }
-        },
-        CollisionInfo::Sphere(sphere_a) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::sphere_intersect_AABB(sphere_a.to_cgmath(), 
-                                                        box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::intersect_sphere(sphere_a.to_cgmath(),
-                                                   sphere_b.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = math::is_point_inside_sphere(point_b.to_cgmath(), 
-                                                         sphere_a.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-          }
-        },
-        CollisionInfo::Point(point_a) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::is_point_inside_AABB(point_a.to_cgmath(), 
-                                                       box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::is_point_inside_sphere(point_a.to_cgmath(), 
-                                                         sphere_b.to_cgmath());
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = point_a == point_b;
-              if did_collide {
-                static_objects[j].collided_with_dynamic_object(&mut dyn_objects[i]);
-              }
-            },
-          }
-        },
+      if let Some(contact) = narrow_phase::contact(&a.objects[i].collision_data(), &b.objects[j].collision_data()) {
+        respond(&mut a.objects[i], EntityId::new(a.category, i), &mut b.objects[j], EntityId::new(b.category, j), &contact, overlaps);
       }
     }
   }
 }
 
-pub fn collide_dynamic_with_static(dyn_objects: &mut Vec<Box<dyn GenericObject>>,
-                                   static_objects: &mut Vec<Box<dyn GenericObject>>) {
-  for i in 0..dyn_objects.len() {
-    for j in 0..static_objects.len() {
-      let dyn_collision_data = dyn_objects[i].collision_data();
-      let static_collision_data = static_objects[j].collision_data();
-      let did_collide;
+// Pairs inside one group, just checked one against another
+pub fn collide_within(group: &mut CollisionGroup, matrix: &CollisionMatrix, overlaps: &mut Vec<TriggerOverlap>) {
+  for j in 1..group.objects.len() {
+    let (before, after) = group.objects.split_at_mut(j);
+    let b = &mut after[0];
+    for (i, a) in before.iter_mut().enumerate() {
+      if !matrix.interacts(a.data(), b.data()) {
+        continue;
+      }
       
-      match dyn_collision_data {
-        CollisionInfo::AABB(box_a_location, box_a_size, _box_a_rotation) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::intersect_AABB(box_a_location.to_cgmath(), box_a_size.to_cgmath(),
-                                                         box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::sphere_intersect_AABB(sphere_b.to_cgmath(), 
-                                                        box_a_location.to_cgmath(), box_a_size.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = math::is_point_inside_AABB(point_b.to_cgmath(), 
-                                                       box_a_location.to_cgmath(), box_a_size.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-          }
-        },
-        CollisionInfo::Sphere(sphere_a) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::sphere_intersect_AABB(sphere_a.to_cgmath(), 
-                                                        box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::intersect_sphere(sphere_a.to_cgmath(),
-                                                   sphere_b.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = math::is_point_inside_sphere(point_b.to_cgmath(), 
-                                                         sphere_a.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-          }
-        },
-        CollisionInfo::Point(point_a) => {
-          match static_collision_data {
-            CollisionInfo::AABB(box_b_location, box_b_size, _box_b_rotation) => {
-              did_collide = math::is_point_inside_AABB(point_a.to_cgmath(), 
-                                                       box_b_location.to_cgmath(), box_b_size.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-            CollisionInfo::Sphere(sphere_b) => {
-              did_collide = math::is_point_inside_sphere(point_a.to_cgmath(), 
-                                                         sphere_b.to_cgmath());
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-            CollisionInfo::Point(point_b) => {
-              did_collide = point_a == point_b;
-              if did_collide {
-                dyn_objects[i].collided_with_static_object(&mut static_objects[j]);
-              }
-            },
-          }
-        },
+      if let Some(contact) = narrow_phase::contact(&a.collision_data(), &b.collision_data()) {
+        respond(a, EntityId::new(group.category, i), b, EntityId::new(group.category, j), &contact, overlaps);
       }
     }
   }
 }
 
-pub fn calculate_collisions(player_objects: &mut Vec<Box<dyn GenericObject>>,//dynamic_objects: &mut Vec<Box<dyn GenericObject>>,
+// Statics push things out of themselves, except swept objects which are told
+// about the first thing they ran into and left to deal with it
+pub fn collide_with_static(group: &mut CollisionGroup,
+                           static_objects: &mut Vec<Box<dyn GenericObject>>,
+                           static_grid: &CollisionGrid,
+                           matrix: &CollisionMatrix,
+                           overlaps: &mut Vec<TriggerOverlap>) {
+  collide_with_fixed(group, Categories::STATIC, static_objects, static_grid, matrix, overlaps);
+}
+
+// The same for any objects that don't move, overlaps with them are reported under static_category
+pub fn collide_with_fixed(group: &mut CollisionGroup,
+                          static_category: Categories,
+                          static_objects: &mut Vec<Box<dyn GenericObject>>,
+                          static_grid: &CollisionGrid,
+                          matrix: &CollisionMatrix,
+                          overlaps: &mut Vec<TriggerOverlap>) {
+  debug_assert_eq!(static_grid.len(), static_objects.len());
+  
+  for i in 0..group.objects.len() {
+    if let Some(start) = group.objects[i].swept_from() {
+      if group.objects[i].is_dead() {
+        continue;
+      }
+      
+      // Stopping at the first wall also cuts the path short for the dynamic checks after
+      if let Some((j, contact)) = first_hit(group.objects[i].as_ref(), &start, static_objects, static_grid, matrix) {
+        group.objects[i].set_position(contact.point.clone());
+        group.objects[i].collided_with_static_object(&mut static_objects[j], &contact);
+      }
+      continue;
+    }
+    
+    for j in static_grid.query(&group.objects[i].collision_data()) {
+      if j >= static_objects.len() || !matrix.interacts(static_objects[j].data(), group.objects[i].data()) {
+        continue;
+      }
+      
+      if let Some(contact) = narrow_phase::contact(&static_objects[j].collision_data(), &group.objects[i].collision_data()) {
+        if static_objects[j].data().trigger {
+          overlaps.push(TriggerOverlap { trigger: EntityId::new(static_category, j), other: EntityId::new(group.category, i) });
+        } else if group.objects[i].data().trigger {
+          overlaps.push(TriggerOverlap { trigger: EntityId::new(group.category, i), other: EntityId::new(static_category, j) });
+        } else {
+          static_objects[j].collided_with_dynamic_object(&mut group.objects[i], &contact);
+        }
+      }
+    }
+  }
+}
+
+// Runs every pair of groups the matrix allows. Groups go in order, so bullets
+// listed first stop at walls before reaching anything behind them.
+pub fn collide_groups(groups: &mut [CollisionGroup],
+                      static_objects: &mut Vec<Box<dyn GenericObject>>,
+                      static_grid: &CollisionGrid,
+                      matrix: &CollisionMatrix) -> Vec<TriggerOverlap> {
+  let mut overlaps = Vec::new();
+  
+  let static_layers = layers_of(static_objects);
+  for group in groups.iter_mut() {
+    if matrix.allows(layers_of(group.objects), static_layers) {
+      collide_with_static(group, static_objects, static_grid, matrix, &mut overlaps);
+    }
+  }
+  
+  for j in 0..groups.len() {
+    let (before, after) = groups.split_at_mut(j);
+    let b = &mut after[0];
+    let b_layers = layers_of(b.objects);
+    
+    if matrix.allows(b_layers, b_layers) {
+      collide_within(b, matrix, &mut overlaps);
+    }
+    
+    for a in before.iter_mut() {
+      if matrix.allows(layers_of(a.objects), b_layers) {
+        collide_dynamic_with_dynamic(a, b, static_grid.cell_size(), matrix, &mut overlaps);
+      }
+    }
+  }
+  
+  overlaps
+}
+
+// Doors and the like come after everything else, so bullets that were stopped by a
+// wall don't go on to hit a switch behind it. There are few enough of them to build
+// a grid over each time.
+pub fn collide_with_interactives(player_objects: &mut Vec<Box<dyn GenericObject>>,
+                                 interactives: &mut Vec<Box<dyn GenericObject>>,
+                                 cell_size: f64,
+                                 enemy_objects: &mut Vec<Box<dyn GenericObject>>,
+                                 player_bullets: &mut Vec<Box<dyn GenericObject>>,
+                                 enemy_bullets: &mut Vec<Box<dyn GenericObject>>,
+                                 matrix: &CollisionMatrix) -> Vec<TriggerOverlap> {
+  collide_with_ungridded(CollisionGroup::new(Categories::INTERACTIVES, interactives), player_objects, cell_size, enemy_objects, player_bullets, enemy_bullets, matrix)
+}
+
+// Platforms move every tick, so they get a fresh grid the same way
+pub fn collide_with_platforms(player_objects: &mut Vec<Box<dyn GenericObject>>,
+                              platforms: &mut Vec<Box<dyn GenericObject>>,
+                              cell_size: f64,
+                              enemy_objects: &mut Vec<Box<dyn GenericObject>>,
+                              player_bullets: &mut Vec<Box<dyn GenericObject>>,
+                              enemy_bullets: &mut Vec<Box<dyn GenericObject>>,
+                              matrix: &CollisionMatrix) -> Vec<TriggerOverlap> {
+  collide_with_ungridded(CollisionGroup::new(Categories::PLATFORMS, platforms), player_objects, cell_size, enemy_objects, player_bullets, enemy_bullets, matrix)
+}
+
+fn collide_with_ungridded(fixed: CollisionGroup,
+                          player_objects: &mut Vec<Box<dyn GenericObject>>,
+                          cell_size: f64,
+                          enemy_objects: &mut Vec<Box<dyn GenericObject>>,
+                          player_bullets: &mut Vec<Box<dyn GenericObject>>,
+                          enemy_bullets: &mut Vec<Box<dyn GenericObject>>,
+                          matrix: &CollisionMatrix) -> Vec<TriggerOverlap> {
+  let mut overlaps = Vec::new();
+  if fixed.objects.is_empty() {
+    return overlaps;
+  }
+  
+  let grid = CollisionGrid::build(cell_size, fixed.objects);
+  let mut groups = [
+    CollisionGroup::new(Categories::PLAYER_BULLETS, player_bullets),
+    CollisionGroup::new(Categories::ENEMY_BULLETS, enemy_bullets),
+    CollisionGroup::new(Categories::PLAYERS, player_objects),
+    CollisionGroup::new(Categories::ENEMIES, enemy_objects),
+  ];
+  for group in groups.iter_mut() {
+    collide_with_fixed(group, fixed.category, fixed.objects, &grid, matrix, &mut overlaps);
+  }
+  
+  overlaps
+}
+
+pub fn calculate_collisions(player_objects: &mut Vec<Box<dyn GenericObject>>,
                             static_objects: &mut Vec<Box<dyn GenericObject>>,
+                            static_grid: &CollisionGrid,
                             enemy_objects: &mut Vec<Box<dyn GenericObject>>,
                             player_bullets: &mut Vec<Box<dyn GenericObject>>,
-                            enemy_bullets: &mut Vec<Box<dyn GenericObject>>) {//static_objects: &mut Vec<Box<dyn GenericObject>>) {
+                            enemy_bullets: &mut Vec<Box<dyn GenericObject>>,
+                            matrix: &CollisionMatrix) -> Vec<TriggerOverlap> {
+  let mut groups = [
+    CollisionGroup::new(Categories::PLAYER_BULLETS, player_bullets),
+    CollisionGroup::new(Categories::ENEMY_BULLETS, enemy_bullets),
+    CollisionGroup::new(Categories::PLAYERS, player_objects),
+    CollisionGroup::new(Categories::ENEMIES, enemy_objects),
+  ];
+  
+  collide_groups(&mut groups, static_objects, static_grid, matrix)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Section, Bullet, Enemy, Character, StaticObject, Hitbox, HitRegion, SECTION_SIZE};
+  
+  const TICK_RATES: [f64; 5] = [10.0, 20.0, 30.0, 60.0, 144.0];
+  // Extra speed on top of the bullet's own 28, up to far faster than any weapon
+  const EXTRA_SPEEDS: [f64; 3] = [16.0, 200.0, 1000.0];
+  
+  fn walls(objects: Vec<Box<dyn GenericObject>>) -> (Vec<Box<dyn GenericObject>>, CollisionGrid) {
+    let grid = CollisionGrid::build(SECTION_SIZE, &objects);
+    (objects, grid)
+  }
   
-  // Dynamic vs Dynamic 
+  fn enemy(x: f64) -> Box<dyn GenericObject> {
+    Box::new(Enemy::new(Vector3::new(x, 7.5, 0.0), Vector3::new_same(2.0), "enemy".to_string()))
+  }
+  
+  // Heading along +x at wall height
+  fn bullet(extra_speed: f64) -> Vec<Box<dyn GenericObject>> {
+    vec!(Box::new(Bullet::new(Vector3::new(0.0, 7.5, 0.0), Vector3::new_same(1.0), 90.0, extra_speed, "bullet".to_string())))
+  }
+  
+  fn fire(bullets: &mut Vec<Box<dyn GenericObject>>,
+          enemies: &mut Vec<Box<dyn GenericObject>>,
+          static_objects: &mut Vec<Box<dyn GenericObject>>,
+          grid: &CollisionGrid,
+          tick_rate: f64) {
+    let delta_time = 1.0/tick_rate;
+    for _ in 0..(2.0*tick_rate) as usize {
+      bullets[0].update(true, delta_time);
+      calculate_collisions(&mut Vec::new(), static_objects, grid, enemies, bullets, &mut Vec::new(), &CollisionMatrix::new());
+      if bullets[0].is_dead() {
+        return;
+      }
+    }
+  }
+  
+  #[test]
+  fn bullets_stop_at_section_walls() {
+    // 1 thick, its near face is at x = 19
+    let mut section = Section::new(0, 0, SECTION_SIZE).left_wall();
+    let (mut static_objects, grid) = walls(section.static_objects());
+    
+    for tick_rate in TICK_RATES.iter() {
+      for extra_speed in EXTRA_SPEEDS.iter() {
+        let mut bullets = bullet(*extra_speed);
+        fire(&mut bullets, &mut Vec::new(), &mut static_objects, &grid, *tick_rate);
+        
+        let x = bullets[0].position().x;
+        assert!(bullets[0].is_dead(), "{} hz {} speed never hit", tick_rate, extra_speed);
+        assert!((x - 18.5).abs() < 1e-9, "{} hz {} speed stopped at {}", tick_rate, extra_speed, x);
+      }
+    }
+  }
+  
+  #[test]
+  fn bullets_hit_only_the_first_enemy_in_line() {
+    for tick_rate in TICK_RATES.iter() {
+      for extra_speed in EXTRA_SPEEDS.iter() {
+        let (mut static_objects, grid) = walls(Vec::new());
+        let mut enemies = vec!(enemy(10.0), enemy(13.0));
+        let mut bullets = bullet(*extra_speed);
+        fire(&mut bullets, &mut enemies, &mut static_objects, &grid, *tick_rate);
+        
+        assert!(bullets[0].is_dead());
+        assert_eq!(enemies[0].data().life, 39, "{} hz {} speed", tick_rate, extra_speed);
+        assert_eq!(enemies[1].data().life, 40, "{} hz {} speed", tick_rate, extra_speed);
+      }
+    }
+  }
   
-  // bullet vs Static
-  collide_dynamic_with_static(player_bullets, static_objects);
-  collide_dynamic_with_static(enemy_bullets, static_objects);
+  #[test]
+  fn walls_shield_enemies_behind_them() {
+    let wall = StaticObject::new(Vector3::new(8.0, 7.5, 0.0), Vector3::new(1.0, 5.0, 10.0), "unit_floor".to_string());
+    
+    for tick_rate in TICK_RATES.iter() {
+      for extra_speed in EXTRA_SPEEDS.iter() {
+        let (mut static_objects, grid) = walls(vec!(Box::new(wall.clone()) as Box<dyn GenericObject>));
+        let mut enemies = vec!(enemy(11.0));
+        let mut bullets = bullet(*extra_speed);
+        fire(&mut bullets, &mut enemies, &mut static_objects, &grid, *tick_rate);
+        
+        assert!(bullets[0].is_dead());
+        assert_eq!(enemies[0].data().life, 40, "{} hz {} speed", tick_rate, extra_speed);
+      }
+    }
+  }
+  
+  #[test]
+  fn matrix_is_symmetric_and_configurable() {
+    let mut matrix = CollisionMatrix::new();
+    assert!(matrix.allows(Categories::PLAYER_BULLETS, Categories::ENEMIES));
+    assert!(matrix.allows(Categories::ENEMIES, Categories::PLAYER_BULLETS));
+    assert!(!matrix.allows(Categories::PLAYERS, Categories::ENEMIES));
+    assert!(!matrix.allows(Categories::PLAYER_BULLETS, Categories::PLAYERS));
+    
+    matrix.set(Categories::PLAYERS, Categories::ENEMIES, true);
+    assert!(matrix.allows(Categories::ENEMIES, Categories::PLAYERS));
+    matrix.set(Categories::STATIC, Categories::PLAYER_BULLETS.with(Categories::ENEMY_BULLETS), false);
+    assert!(!matrix.allows(Categories::ENEMY_BULLETS, Categories::STATIC));
+    assert!(matrix.allows(Categories::STATIC, Categories::PLAYERS));
+    
+    assert!(!CollisionMatrix::empty().allows(Categories::ALL, Categories::ALL));
+  }
   
-  // Static vs Player
-  collide_static_with_dynamic(static_objects, player_objects);
-  collide_static_with_dynamic(static_objects, enemy_objects);
+  #[test]
+  fn masks_and_the_matrix_decide_what_is_hit() {
+    let (mut static_objects, grid) = walls(Vec::new());
+    
+    // This enemy ignores player bullets even though enemies in general don't
+    let mut enemies = vec!(enemy(10.0), enemy(13.0));
+    enemies[0].mut_data().mask = Categories::ALL.without(Categories::PLAYER_BULLETS);
+    let mut bullets = bullet(0.0);
+    fire(&mut bullets, &mut enemies, &mut static_objects, &grid, 60.0);
+    assert_eq!(enemies[0].data().life, 40);
+    assert_eq!(enemies[1].data().life, 39);
+    
+    // With the pair taken out of the matrix nothing gets hit at all
+    let matrix = CollisionMatrix::new();
+    let mut matrix_without = matrix.clone();
+    matrix_without.set(Categories::ENEMIES, Categories::PLAYER_BULLETS, false);
+    let mut enemies = vec!(enemy(10.0));
+    let mut bullets = bullet(0.0);
+    for _ in 0..60 {
+      bullets[0].update(true, 1.0/60.0);
+      calculate_collisions(&mut Vec::new(), &mut static_objects, &grid, &mut enemies, &mut bullets, &mut Vec::new(), &matrix_without);
+    }
+    assert!(!bullets[0].is_dead());
+    assert_eq!(enemies[0].data().life, 40);
+  }
   
-  collide_dynamic_with_dynamic(enemy_objects, player_bullets);
-  collide_dynamic_with_dynamic(player_objects, enemy_bullets);
+  #[test]
+  fn decorative_objects_are_ignored() {
+    let mut scenery = StaticObject::new(Vector3::new(8.0, 7.5, 0.0), Vector3::new(1.0, 5.0, 10.0), "unit_floor".to_string());
+    scenery.mut_data().physics_type = ObjectPhysicsType::Decorative;
+    let wall = StaticObject::new(Vector3::new(12.0, 7.5, 0.0), Vector3::new(1.0, 5.0, 10.0), "unit_floor".to_string());
+    let (mut static_objects, grid) = walls(vec!(Box::new(scenery) as Box<dyn GenericObject>, Box::new(wall)));
+    
+    let mut bullets = bullet(0.0);
+    fire(&mut bullets, &mut Vec::new(), &mut static_objects, &grid, 60.0);
+    assert!(bullets[0].is_dead());
+    assert!((bullets[0].position().x - 11.0).abs() < 1e-9);
+  }
+  
+  #[test]
+  fn triggers_report_overlaps_without_pushing() {
+    let zone = StaticObject::new(Vector3::new(0.0, 7.5, 0.0), Vector3::new_same(6.0), "unit_floor".to_string()).trigger();
+    let (mut static_objects, grid) = walls(vec!(Box::new(zone) as Box<dyn GenericObject>));
+    
+    let start = Vector3::new(1.0, 7.0, 0.5);
+    let mut players = vec!(Box::new(Character::new(start.clone(), Vector3::new_same(1.0))) as Box<dyn GenericObject>);
+    // A pickup sitting in the enemies Vec, players aren't meant to bump into enemies
+    let mut pickup = Enemy::new(Vector3::new(1.5, 7.0, 0.0), Vector3::new_same(1.0), "pickup".to_string());
+    pickup.mut_data().layer = Categories::TRIGGERS;
+    pickup.mut_data().trigger = true;
+    let mut enemies = vec!(Box::new(pickup) as Box<dyn GenericObject>, enemy(30.0));
+    
+    let overlaps = calculate_collisions(&mut players, &mut static_objects, &grid, &mut enemies,
+                                        &mut Vec::new(), &mut Vec::new(), &CollisionMatrix::new());
+    
+    assert_eq!(overlaps, vec!(
+      TriggerOverlap { trigger: EntityId::new(Categories::STATIC, 0), other: EntityId::new(Categories::PLAYERS, 0) },
+      TriggerOverlap { trigger: EntityId::new(Categories::ENEMIES, 0), other: EntityId::new(Categories::PLAYERS, 0) },
+    ));
+    assert_eq!(players[0].position(), &start);
+    assert!(!players[0].data().grounded);
+  }
+  
+  #[test]
+  fn headshots_do_more_damage() {
+    // Heads stick out towards -x or +x, bullets come from -x
+    let lopsided = |x: f64, head: f64| {
+      let mut enemy = enemy(x);
+      enemy.mut_data().hitboxes = vec!(
+        Hitbox::new(Vector3::new_same(0.0), Vector3::new_same(2.0)),
+        Hitbox::new(Vector3::new(head, 0.0, 0.0), Vector3::new_same(1.0)).region(HitRegion::Head),
+      );
+      enemy
+    };
+    
+    for tick_rate in TICK_RATES.iter() {
+      let (mut static_objects, grid) = walls(Vec::new());
+      let mut enemies = vec!(lopsided(10.0, -1.5), lopsided(30.0, 1.5));
+      fire(&mut bullet(0.0), &mut enemies, &mut static_objects, &grid, *tick_rate);
+      assert_eq!(enemies[0].data().life, 38, "{} hz", tick_rate);
+      
+      enemies.remove(0);
+      fire(&mut bullet(0.0), &mut enemies, &mut static_objects, &grid, *tick_rate);
+      assert_eq!(enemies[0].data().life, 39, "{} hz", tick_rate);
+    }
+  }
 }
-
@@ -0,0 +1,481 @@
+use crate::{Section, Wall};
+
+// The world is cut into chunks of chunk_size by chunk_size sections, each one
+// is generated on its own from the seed and its position so sections can be
+// streamed in any order. Inside a chunk a maze joins every section, rooms are
+// carved over it and dead ends are trimmed back, which never disconnects
+// anything. Neighbouring chunks share one door on every edge, picked from the
+// seed and the edge so both sides agree on where it is. Floors are stacked the
+// same way, every chunk has an elevator up to the floor above it.
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Opening {
+  Wall,
+  // A wall with a gap in it, between corridors or into a room
+  Door,
+  // Nothing at all, between two parts of the same room
+  Open,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct DungeonCell {
+  pub room: bool,
+  // Elevators to the floor above and below, they are always in the same place on both
+  pub up: bool,
+  pub down: bool,
+  // In the order of Wall::ALL
+  pub sides: [Opening; 4],
+}
+
+impl DungeonCell {
+  pub fn side(&self, wall: Wall) -> Opening {
+    self.sides[side_index(wall)]
+  }
+}
+
+fn side_index(wall: Wall) -> usize {
+  match wall {
+    Wall::Left => 0,
+    Wall::Right => 1,
+    Wall::Front => 2,
+    Wall::Back => 3,
+  }
+}
+
+fn default_chunk_size() -> u32 {
+  8
+}
+
+fn default_difficulty_step() -> f64 {
+  4.0
+}
+
+fn default_prune_passes() -> u32 {
+  3
+}
+
+fn default_floors() -> u32 {
+  1
+}
+
+fn default_floor_difficulty() -> u32 {
+  2
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Dungeon {
+  pub seed: u64,
+  #[serde(default = "default_chunk_size")]
+  pub chunk_size: u32,
+  // Sections from spawn for each level of difficulty
+  #[serde(default = "default_difficulty_step")]
+  pub difficulty_step: f64,
+  // How far dead ends are cut back, each pass takes one section off every one
+  #[serde(default = "default_prune_passes")]
+  pub prune_passes: u32,
+  // Floor 0 up to floors - 1, there is nothing above or below
+  #[serde(default = "default_floors")]
+  pub floors: u32,
+  // Added to the difficulty for each floor up
+  #[serde(default = "default_floor_difficulty")]
+  pub floor_difficulty: u32,
+}
+
+// Small and good enough to scatter rooms, the same on every platform
+struct SplitMix(u64);
+
+impl SplitMix {
+  fn next(&mut self) -> u64 {
+    self.0 = self.0.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = self.0;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+  }
+  
+  fn below(&mut self, n: u32) -> u32 {
+    (self.next() % n as u64) as u32
+  }
+}
+
+fn hash(seed: u64, a: i32, level: i32, b: i32, salt: u64) -> u64 {
+  let mut rng = SplitMix(seed ^ (a as u64).wrapping_mul(0x9E3779B1) ^ (b as u64).wrapping_mul(0x85EBCA77C2B2AE63) ^
+                         (level as u64).wrapping_mul(0x27D4EB2F165667C5) ^ salt.wrapping_mul(0xC2B2AE3D27D4EB4F));
+  rng.next()
+}
+
+const EDGE_X: u64 = 1;
+const EDGE_Z: u64 = 2;
+const CHUNK: u64 = 3;
+const ELEVATOR: u64 = 4;
+
+impl Dungeon {
+  pub fn new(seed: u64) -> Dungeon {
+    Dungeon {
+      seed,
+      chunk_size: default_chunk_size(),
+      difficulty_step: default_difficulty_step(),
+      prune_passes: default_prune_passes(),
+      floors: default_floors(),
+      floor_difficulty: default_floor_difficulty(),
+    }
+  }
+  
+  pub fn floors(mut self, floors: u32) -> Dungeon {
+    self.floors = floors;
+    self
+  }
+  
+  pub fn chunk_size(mut self, chunk_size: u32) -> Dungeon {
+    self.chunk_size = chunk_size;
+    self
+  }
+  
+  pub fn difficulty_step(mut self, difficulty_step: f64) -> Dungeon {
+    self.difficulty_step = difficulty_step;
+    self
+  }
+  
+  pub fn difficulty(&self, x: i32, level: i32, z: i32) -> u32 {
+    ((x as f64).hypot(z as f64) / self.difficulty_step).floor() as u32 + level.max(0) as u32*self.floor_difficulty
+  }
+  
+  pub fn chunk_of(&self, x: i32, z: i32) -> (i32, i32) {
+    let n = self.chunk_size as i32;
+    (x.div_euclid(n), z.div_euclid(n))
+  }
+  
+  // Where (x, z) is in the Vec chunk returns
+  fn index_in_chunk(&self, x: i32, z: i32) -> usize {
+    let n = self.chunk_size as i32;
+    (x.rem_euclid(n) + n*z.rem_euclid(n)) as usize
+  }
+  
+  // None where the dungeon has been trimmed away
+  pub fn cell(&self, x: i32, level: i32, z: i32) -> Option<DungeonCell> {
+    let (cx, cz) = self.chunk_of(x, z);
+    let mut chunk = self.chunk(cx, level, cz);
+    chunk.swap_remove(self.index_in_chunk(x, z))
+  }
+  
+  // Generates the whole chunk for one section, see section_in_chunk to share one between them
+  pub fn section(&self, x: i32, level: i32, z: i32, section_size: f64) -> Section {
+    let (cx, cz) = self.chunk_of(x, z);
+    self.section_in_chunk(&self.chunk(cx, level, cz), x, level, z, section_size)
+  }
+  
+  // The chunk has to be the one chunk_of gives for (x, z) on this floor
+  pub fn section_in_chunk(&self, chunk: &[Option<DungeonCell>], x: i32, level: i32, z: i32, section_size: f64) -> Section {
+    let mut section = Section::new_on_floor(x, level, z, section_size).difficulty(self.difficulty(x, level, z));
+    if let Some(cell) = &chunk[self.index_in_chunk(x, z)] {
+      section = section.floor();
+      for wall in Wall::ALL.iter() {
+        section = match cell.side(*wall) {
+          Opening::Wall => section.wall(*wall),
+          Opening::Door => section.door(*wall),
+          Opening::Open => section,
+        };
+      }
+      if cell.up {
+        section = section.elevator(1);
+      }
+      if cell.down {
+        section = section.elevator(-1);
+      }
+    }
+    
+    section
+  }
+  
+  // Where the door through an edge between two chunks is, counted along the edge
+  fn edge_door(&self, cx: i32, level: i32, cz: i32, edge: u64) -> i32 {
+    (hash(self.seed, cx, level, cz, edge) % self.chunk_size as u64) as i32
+  }
+  
+  // The cell in chunk (cx, cz) with the elevator from this floor to the one above
+  fn elevator_cell(&self, cx: i32, level: i32, cz: i32) -> (i32, i32) {
+    let n = self.chunk_size as u64;
+    let h = hash(self.seed, cx, level, cz, ELEVATOR);
+    ((h % n) as i32, ((h / n) % n) as i32)
+  }
+  
+  // Every cell of chunk (cx, cz) on a floor, row by row
+  pub fn chunk(&self, cx: i32, level: i32, cz: i32) -> Vec<Option<DungeonCell>> {
+    let n = self.chunk_size as i32;
+    if level < 0 || level >= self.floors as i32 {
+      return vec!(None; (n*n) as usize);
+    }
+    
+    let index = |i: i32, j: i32| (i + n*j) as usize;
+    let inside = |i: i32, j: i32| i >= 0 && j >= 0 && i < n && j < n;
+    let mut rng = SplitMix(hash(self.seed, cx, level, cz, CHUNK));
+    
+    let mut sides = vec!([Opening::Wall; 4]; (n*n) as usize);
+    let mut room = vec!(false; (n*n) as usize);
+    let mut alive = vec!(true; (n*n) as usize);
+    
+    fn open(sides: &mut [[Opening; 4]], n: i32, i: i32, j: i32, wall: Wall, opening: Opening) {
+      let (di, dj) = wall.offset();
+      sides[(i + n*j) as usize][side_index(wall)] = opening;
+      sides[(i + di + n*(j + dj)) as usize][side_index(wall.opposite())] = opening;
+    }
+    
+    // A maze through every cell so they are all joined to start with
+    let mut visited = vec!(false; (n*n) as usize);
+    let start = (rng.below(n as u32) as i32, rng.below(n as u32) as i32);
+    let mut stack = vec!(start);
+    visited[index(start.0, start.1)] = true;
+    while let Some(&(i, j)) = stack.last() {
+      let mut options = Wall::ALL.iter().filter(|w| {
+        let (di, dj) = w.offset();
+        inside(i + di, j + dj) && !visited[index(i + di, j + dj)]
+      }).cloned().collect::<Vec<Wall>>();
+      
+      if options.is_empty() {
+        stack.pop();
+        continue;
+      }
+      
+      let wall = options.swap_remove(rng.below(options.len() as u32) as usize);
+      let (di, dj) = wall.offset();
+      open(&mut sides, n, i, j, wall, Opening::Door);
+      visited[index(i + di, j + dj)] = true;
+      stack.push((i + di, j + dj));
+    }
+    
+    // Rooms only add ways through, so everything stays joined
+    if n >= 3 {
+      for _ in 0..(n*n/16).max(1) {
+        let width = 2 + rng.below(2) as i32;
+        let depth = 2 + rng.below(2) as i32;
+        let (i0, j0) = (rng.below((n - width + 1).max(1) as u32) as i32, rng.below((n - depth + 1).max(1) as u32) as i32);
+        for i in i0..(i0 + width).min(n) {
+          for j in j0..(j0 + depth).min(n) {
+            room[index(i, j)] = true;
+            if i + 1 < (i0 + width).min(n) {
+              open(&mut sides, n, i, j, Wall::Left, Opening::Open);
+            }
+            if j + 1 < (j0 + depth).min(n) {
+              open(&mut sides, n, i, j, Wall::Front, Opening::Open);
+            }
+          }
+        }
+      }
+    }
+    
+    // Doors out to the neighbouring chunks, these cells are never trimmed
+    let mut keep = room.clone();
+    let doors = [
+      (n - 1, self.edge_door(cx, level, cz, EDGE_X), Wall::Left),
+      (0, self.edge_door(cx - 1, level, cz, EDGE_X), Wall::Right),
+      (self.edge_door(cx, level, cz, EDGE_Z), n - 1, Wall::Front),
+      (self.edge_door(cx, level, cz - 1, EDGE_Z), 0, Wall::Back),
+    ];
+    for (i, j, wall) in doors.iter() {
+      sides[index(*i, *j)][side_index(*wall)] = Opening::Door;
+      keep[index(*i, *j)] = true;
+    }
+    // Spawn is always somewhere to stand
+    if (cx, level, cz) == (0, 0, 0) {
+      keep[index(0, 0)] = true;
+    }
+    // As are both ends of every elevator
+    let mut up = vec!(false; (n*n) as usize);
+    let mut down = vec!(false; (n*n) as usize);
+    if level + 1 < self.floors as i32 {
+      let (i, j) = self.elevator_cell(cx, level, cz);
+      up[index(i, j)] = true;
+      keep[index(i, j)] = true;
+    }
+    if level > 0 {
+      let (i, j) = self.elevator_cell(cx, level - 1, cz);
+      down[index(i, j)] = true;
+      keep[index(i, j)] = true;
+    }
+    
+    // Trimming a dead end can't cut anything else off, there is only one way in
+    for _ in 0..self.prune_passes {
+      let mut dead_ends = Vec::new();
+      for i in 0..n {
+        for j in 0..n {
+          let ways = Wall::ALL.iter().filter(|w| {
+            let (di, dj) = w.offset();
+            inside(i + di, j + dj) && sides[index(i, j)][side_index(**w)] != Opening::Wall
+          }).count();
+          if alive[index(i, j)] && !keep[index(i, j)] && ways <= 1 {
+            dead_ends.push((i, j));
+          }
+        }
+      }
+      
+      for (i, j) in dead_ends {
+        alive[index(i, j)] = false;
+        for wall in Wall::ALL.iter() {
+          let (di, dj) = wall.offset();
+          if inside(i + di, j + dj) {
+            open(&mut sides, n, i, j, *wall, Opening::Wall);
+          }
+        }
+      }
+    }
+    
+    // A few extra doors so there is more than one way round
+    for i in 0..n {
+      for j in 0..n {
+        for wall in [Wall::Left, Wall::Front].iter() {
+          let (di, dj) = wall.offset();
+          if inside(i + di, j + dj) && alive[index(i, j)] && alive[index(i + di, j + dj)] &&
+             sides[index(i, j)][side_index(*wall)] == Opening::Wall && rng.below(10) == 0 {
+            open(&mut sides, n, i, j, *wall, Opening::Door);
+          }
+        }
+      }
+    }
+    
+    (0..(n*n) as usize).map(|i| {
+      if alive[i] {
+        Some(DungeonCell { room: room[i], up: up[i], down: down[i], sides: sides[i] })
+      } else {
+        None
+      }
+    }).collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::{HashMap, HashSet, VecDeque};
+  use crate::{World, SectionLayout, Categories, Vector3};
+  
+  const FLOORS: i32 = 3;
+  
+  // Every cell in chunks -2..2 both ways on every floor, 32 by 32 sections around spawn
+  fn area(dungeon: &Dungeon) -> HashMap<(i32, i32, i32), DungeonCell> {
+    let n = dungeon.chunk_size as i32;
+    let mut cells = HashMap::new();
+    for level in 0..FLOORS {
+      for cx in -2..2 {
+        for cz in -2..2 {
+          for (k, cell) in dungeon.chunk(cx, level, cz).into_iter().enumerate() {
+            if let Some(cell) = cell {
+              cells.insert((cx*n + k as i32 % n, level, cz*n + k as i32 / n), cell);
+            }
+          }
+        }
+      }
+    }
+    
+    cells
+  }
+  
+  #[test]
+  fn every_seed_joins_up() {
+    let mut rooms = 0;
+    let mut trimmed = 0;
+    for seed in 0..100 {
+      let dungeon = Dungeon::new(seed).floors(FLOORS as u32);
+      let cells = area(&dungeon);
+      assert!(cells.contains_key(&(0, 0, 0)), "seed {} has nowhere to spawn", seed);
+      
+      // Both sides of every edge and both ends of every elevator agree, so nothing is a one way trip
+      for (&(x, level, z), cell) in &cells {
+        for wall in Wall::ALL.iter() {
+          let (dx, dz) = wall.offset();
+          if let Some(other) = cells.get(&(x + dx, level, z + dz)) {
+            assert_eq!(cell.side(*wall), other.side(wall.opposite()), "seed {} ({}, {}, {}) {:?}", seed, x, level, z, wall);
+          } else if (-16..16).contains(&(x + dx)) && (-16..16).contains(&(z + dz)) {
+            assert_eq!(cell.side(*wall), Opening::Wall, "seed {} ({}, {}, {}) opens onto nothing", seed, x, level, z);
+          }
+        }
+        if cell.up {
+          assert!(cells.get(&(x, level + 1, z)).map(|c| c.down).unwrap_or(false), "seed {} ({}, {}, {}) goes up to nothing", seed, x, level, z);
+        }
+        if cell.down {
+          assert!(cells.get(&(x, level - 1, z)).map(|c| c.up).unwrap_or(false), "seed {} ({}, {}, {}) goes down to nothing", seed, x, level, z);
+        }
+      }
+      
+      let mut reached = HashSet::new();
+      let mut queue = VecDeque::new();
+      reached.insert((0, 0, 0));
+      queue.push_back((0, 0, 0));
+      while let Some((x, level, z)) = queue.pop_front() {
+        let cell = &cells[&(x, level, z)];
+        let mut next = Wall::ALL.iter().filter(|w| cell.side(**w) != Opening::Wall).map(|w| {
+          let (dx, dz) = w.offset();
+          (x + dx, level, z + dz)
+        }).collect::<Vec<(i32, i32, i32)>>();
+        if cell.up {
+          next.push((x, level + 1, z));
+        }
+        if cell.down {
+          next.push((x, level - 1, z));
+        }
+        
+        for location in next {
+          if cells.contains_key(&location) && reached.insert(location) {
+            queue.push_back(location);
+          }
+        }
+      }
+      assert_eq!(reached.len(), cells.len(), "seed {} has sections that can't be reached", seed);
+      
+      rooms += cells.values().filter(|c| c.room).count();
+      trimmed += (32*32*FLOORS) as usize - cells.len();
+    }
+    
+    // Actually a dungeon rather than an open field or a single corridor
+    assert!(rooms > 0);
+    assert!(trimmed > 0);
+  }
+  
+  #[test]
+  fn seeds_give_the_same_dungeon_every_time() {
+    let dungeon = Dungeon::new(7).floors(2);
+    assert_eq!(dungeon.chunk(1, 1, -3), Dungeon::new(7).floors(2).chunk(1, 1, -3));
+    assert_eq!(dungeon.section(9, 0, -20, 40.0), Dungeon::new(7).floors(2).section(9, 0, -20, 40.0));
+    assert_ne!(area(&dungeon), area(&Dungeon::new(8).floors(2)));
+    // Floors are laid out differently from each other
+    assert_ne!(dungeon.chunk(0, 0, 0), dungeon.chunk(0, 1, 0));
+    assert_eq!(dungeon.chunk(0, 2, 0), vec!(None; 64));
+  }
+  
+  #[test]
+  fn difficulty_grows_with_distance_from_spawn_and_height() {
+    let dungeon = Dungeon::new(1).floors(4);
+    assert_eq!(dungeon.difficulty(0, 0, 0), 0);
+    assert_eq!(dungeon.difficulty(3, 0, 0), 0);
+    assert_eq!(dungeon.difficulty(-4, 0, 0), 1);
+    assert_eq!(dungeon.difficulty(0, 0, 40), 10);
+    assert_eq!(dungeon.difficulty(0, 3, 0), 6);
+    assert_eq!(dungeon.section(30, 1, 40, 40.0).difficulty_level(), 14);
+  }
+  
+  #[test]
+  fn layouts_reuse_chunks_without_changing_sections() {
+    let dungeon = Dungeon::new(5).floors(2).chunk_size(4);
+    let mut layout = SectionLayout::dungeon(dungeon.clone(), 40.0);
+    // More chunks than are cached, so some are thrown away and generated again
+    for level in 0..2 {
+      for x in -20..20 {
+        for z in -20..20 {
+          assert_eq!(layout.get_section(x, level, z), dungeon.section(x, level, z, 40.0));
+        }
+      }
+    }
+    assert_eq!(layout.get_section(3, 0, -7), dungeon.section(3, 0, -7, 40.0));
+  }
+  
+  #[test]
+  fn doors_can_be_walked_through() {
+    let section_size = 40.0;
+    let layout = SectionLayout::dungeon(Dungeon::new(1), section_size);
+    let world = World::from_sections(section_size, vec!(Section::new(0, 0, section_size).floor().door(Wall::Left)), layout);
+    
+    let through_door = world.raycast(&Vector3::new(0.0, 7.5, 0.0), &Vector3::new(1.0, 0.0, 0.0), 30.0, Categories::STATIC);
+    assert_eq!(through_door, None);
+    let beside_door = world.raycast(&Vector3::new(0.0, 7.5, 10.0), &Vector3::new(1.0, 0.0, 0.0), 30.0, Categories::STATIC);
+    assert_eq!(beside_door.unwrap().distance, 19.0);
+  }
+}
@@ -1,4 +1,6 @@
-use crate::{ObjectData, GenericObject, DrawCall, Vector3};
+use crate::{ObjectData, GenericObject, DrawCall, Vector3, Contact, Categories, STEP_HEIGHT, physics};
+
+const LIFE: i32 = 40;
 
 #[derive(Clone)]
 pub struct Enemy {
@@ -9,10 +11,23 @@ impl Enemy {
   pub fn new(pos: Vector3, size: Vector3, model: String) -> Enemy {
     
     Enemy {
-      data: ObjectData::new(pos, size, model).set_life(40),
+      data: ObjectData::new(pos, size, model).dynamic_physics().layer(Categories::ENEMIES).set_life(LIFE).step_height(STEP_HEIGHT),
     }
   }
   
+  pub fn from_data(data: ObjectData) -> Enemy {
+    Enemy {
+      data,
+    }
+  }
+  
+  // Each level is another half of the starting life
+  pub fn difficulty(mut self, level: u32) -> Enemy {
+    self.data.life = LIFE + LIFE*level as i32/2;
+    
+    self
+  }
+  
   pub fn set_hitbox_size(mut self, size: Vector3) -> Enemy {
     self.data = self.data.hitbox_size(size);
     
@@ -29,11 +44,11 @@ impl GenericObject for Enemy {
     &mut self.data
   }
   
-  fn collided_with_static_object(&mut self, static_object: &mut Box<dyn GenericObject>) {
+  fn collided_with_static_object(&mut self, _static_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
     
   }
   
-  fn collided_with_dynamic_object(&mut self, _dynamic_object: &mut Box<dyn GenericObject>) {
+  fn collided_with_dynamic_object(&mut self, _dynamic_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
     
   }
   
@@ -44,19 +59,10 @@ impl GenericObject for Enemy {
   }
   
   fn physics_update(&mut self, delta_time: f64) {
-    if !self.data().grounded {
-      self.mut_data().vel.y -= 9.8;
-    }
-    
-    if self.data().vel.y < -9.8 {
-      self.mut_data().vel.y = -9.8;
-    }
-    
-    if self.data().pos.y < self.hitbox_size().y*0.5 {
-      self.mut_data().pos.y = self.hitbox_size().y*0.5;
-      self.mut_data().grounded = true;
+    // Enemies stand still, anything pushing them wears off
+    if physics::integrate(self.mut_data(), &Vector3::new_same(0.0), delta_time) {
+      self.mut_data().life = 0;
     }
-    self.axis_movement(delta_time);
   }
   
   fn additional_draws(&self, draw_calls: &mut Vec<DrawCall>) {
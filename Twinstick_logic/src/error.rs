@@ -0,0 +1,58 @@
+use std::fmt;
+use std::io;
+
+use crate::ConnectionRejection;
+
+#[derive(Debug)]
+pub enum TwinstickError {
+  Io(io::Error),
+  Serialise(bincode::Error),
+  Deserialise(bincode::Error),
+  Rejected(ConnectionRejection),
+  NotConnected,
+  IncompatibleSave(u32),
+  InvalidModel(String),
+  IncompatibleMap(u32),
+  InvalidMap(String),
+  InvalidPlatform(String),
+}
+
+impl fmt::Display for TwinstickError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      TwinstickError::Io(e) => write!(f, "network error: {}", e),
+      TwinstickError::Serialise(e) => write!(f, "failed to serialise message: {}", e),
+      TwinstickError::Deserialise(e) => write!(f, "failed to deserialise message: {}", e),
+      TwinstickError::Rejected(rejection) => write!(f, "connection rejected: {}", rejection),
+      TwinstickError::NotConnected => write!(f, "not connected to a server"),
+      TwinstickError::IncompatibleSave(version) => write!(f, "save file is version {}, expected {}", version, crate::SAVE_VERSION),
+      TwinstickError::InvalidModel(reason) => write!(f, "invalid model shape {}", reason),
+      TwinstickError::IncompatibleMap(version) => write!(f, "map file is version {}, expected {}", version, crate::MAP_VERSION),
+      TwinstickError::InvalidMap(reason) => write!(f, "invalid map {}", reason),
+      TwinstickError::InvalidPlatform(reason) => write!(f, "invalid platform {}", reason),
+    }
+  }
+}
+
+impl std::error::Error for TwinstickError {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    match self {
+      TwinstickError::Io(e) => Some(e),
+      TwinstickError::Serialise(e) => Some(e),
+      TwinstickError::Deserialise(e) => Some(e),
+      _ => None,
+    }
+  }
+}
+
+impl From<io::Error> for TwinstickError {
+  fn from(e: io::Error) -> TwinstickError {
+    TwinstickError::Io(e)
+  }
+}
+
+impl From<ConnectionRejection> for TwinstickError {
+  fn from(rejection: ConnectionRejection) -> TwinstickError {
+    TwinstickError::Rejected(rejection)
+  }
+}
@@ -1,9 +1,28 @@
-use crate::{Vector3, Section, Character, Enemy, Input, World, GenericObject};
-use crate::collisions;
-use crate::ENEMY_RESPAWN_TIMER;
+use crate::{Vector3, CollisionGrid, Section, Character, Enemy, Input, World, GenericObject, ObjectData, SaveGame, SAVE_VERSION};
+use crate::{CollisionInfo, Vector4, Categories, QueryHit, QuerySet, CollisionMatrix, TriggerOverlap, ModelShapes};
+use crate::{collisions, query, narrow_phase};
+use crate::{Map, InterestSet, InteractiveKind, ENEMY_RESPAWN_TIMER, INTEREST_RADIUS, INTERACT_RANGE};
+use crate::interest;
+
+// Everything one tick moves and collides, the server passes its own objects and
+// clients their copies of what they have been sent
+pub struct TickObjects<'a> {
+  pub players: &'a mut Vec<Box<dyn GenericObject>>,
+  pub enemies: &'a mut Vec<Box<dyn GenericObject>>,
+  pub player_bullets: &'a mut Vec<Box<dyn GenericObject>>,
+  pub enemy_bullets: &'a mut Vec<Box<dyn GenericObject>>,
+  pub static_objects: &'a mut Vec<Box<dyn GenericObject>>,
+  pub static_grid: &'a CollisionGrid,
+  pub interactives: &'a mut Vec<Box<dyn GenericObject>>,
+  pub platforms: &'a mut Vec<Box<dyn GenericObject>>,
+  pub dynamic_objects: &'a mut Vec<Box<dyn GenericObject>>,
+}
 
 pub struct TwinstickGame {
   players: Vec<Box<dyn GenericObject>>,
+  // Stay with a player while indexes into players shift as others die or leave
+  player_ids: Vec<u64>,
+  next_player_id: u64,
   //static_objects: Vec<Box<dyn GenericObject>>,
   dynamic_objects: Vec<Box<dyn GenericObject>>,
   enemies: Vec<Box<dyn GenericObject>>,
@@ -11,38 +30,174 @@ pub struct TwinstickGame {
   enemy_bullets: Vec<Box<dyn GenericObject>>,
   world: World,
   enemy_tick: f32,
+  saved_players: Vec<ObjectData>,
+  collision_matrix: CollisionMatrix,
+  trigger_overlaps: Vec<TriggerOverlap>,
+  unloaded_sections: Vec<(i32, i32, i32)>,
+  map: Map,
+  spawner_ticks: Vec<f32>,
+  interactives: Vec<Box<dyn GenericObject>>,
+  platforms: Vec<Box<dyn GenericObject>>,
+  // Players that pressed Input::Interact since the last tick
+  interact_requests: Vec<usize>,
 }
 
 impl TwinstickGame {
   pub fn new() -> TwinstickGame {
+    TwinstickGame::from_map(Map::default_map())
+  }
+  
+  // Each spawner starts out with an enemy already waiting
+  pub fn from_map(map: Map) -> TwinstickGame {
+    let world = TwinstickGame::build_world(&map);
     
-    let section_size = 40.0;
-    
-    // -x = right
-    // +x = left
-    // -z = up/away
-    // +z = down/towards
-    
-    let pos = Vector3::new(-4.0 * section_size, 20.0, 0.0 * section_size);
-    let size = Vector3::new_same(2.0);
-    let enemy = Enemy::new(pos, size, "enemy".to_string());
+    let mut game = TwinstickGame {
+      players: Vec::new(),
+      player_ids: Vec::new(),
+      next_player_id: 0,
+      dynamic_objects: Vec::new(),
+      enemies: Vec::new(),
+      player_bullets: Vec::new(),
+      enemy_bullets: Vec::new(),
+      world,
+      enemy_tick: 0.0,
+      saved_players: Vec::new(),
+      collision_matrix: CollisionMatrix::new(),
+      trigger_overlaps: Vec::new(),
+      unloaded_sections: Vec::new(),
+      spawner_ticks: vec!(0.0; map.enemy_spawners.len()),
+      interactives: map.interactives(),
+      platforms: map.platforms(),
+      interact_requests: Vec::new(),
+      map,
+    };
     
-    let mut world = World::new(section_size);
-    for i in 0..5 {
-      for j in 0..5 {
-        world.load_section(i as i32-2, j as i32-2);
+    for spawner in game.map.enemy_spawners.clone() {
+      if game.enemies.len() < spawner.max_enemies {
+        game.spawn_enemy(spawner.pos);
       }
     }
     
+    game
+  }
+  
+  // Saves don't say which map they came from, the default one is used until set_map is given the right one
+  pub fn from_save(save: SaveGame) -> TwinstickGame {
+    let enemies = save.enemies.into_iter().map(|data| Box::new(Enemy::from_data(data)) as Box<dyn GenericObject>).collect();
+    let mut map = Map::default_map();
+    map.section_size = save.section_size;
+    // Sections streamed in around players were saved too, only the map's own stay put
+    let mut world = World::from_sections(save.section_size, save.sections, map.layout());
+    world.keep_map_sections(&map);
+    
     TwinstickGame {
       players: Vec::new(),
+      player_ids: Vec::new(),
+      next_player_id: 0,
       dynamic_objects: Vec::new(),
-      enemies: vec!(Box::new(enemy)),
+      enemies,
       player_bullets: Vec::new(),
       enemy_bullets: Vec::new(),
       world,
-      enemy_tick: 0.0,
+      enemy_tick: save.enemy_tick,
+      saved_players: save.players,
+      collision_matrix: CollisionMatrix::new(),
+      trigger_overlaps: Vec::new(),
+      unloaded_sections: Vec::new(),
+      spawner_ticks: vec!(0.0; map.enemy_spawners.len()),
+      interactives: map.interactives(),
+      platforms: map.platforms(),
+      interact_requests: Vec::new(),
+      map,
+    }
+  }
+  
+  // Bullets are left out, they are gone within a few seconds anyway
+  pub fn save(&self) -> SaveGame {
+    let mut players = self.players.iter().map(|p| p.data().clone()).collect::<Vec<ObjectData>>();
+    // Players that haven't reconnected since the last restore keep their place
+    players.append(&mut self.saved_players.clone());
+    for player in &mut players {
+      player.inputs.clear();
+    }
+    
+    SaveGame {
+      version: SAVE_VERSION,
+      section_size: self.world.section_size(),
+      sections: self.world.sections(),
+      enemies: self.enemies.iter().map(|e| e.data().clone()).collect(),
+      players,
+      enemy_tick: self.enemy_tick,
+    }
+  }
+  
+  fn build_world(map: &Map) -> World {
+    let mut world = World::from_map(map);
+    for i in -2..3 {
+      for j in -2..3 {
+        world.load_section(i, 0, j);
+      }
+    }
+    
+    world
+  }
+  
+  // Throws away every loaded section and starts again from the map
+  pub fn reload_world(&mut self) {
+    let model_shapes = self.world.model_shapes().clone();
+    self.world = TwinstickGame::build_world(&self.map);
+    self.world.set_model_shapes(model_shapes);
+  }
+  
+  // Picks up an edited map while the game is running. Returns the loaded sections that
+  // changed, or None if the section size changed and the whole world was rebuilt.
+  pub fn change_map(&mut self, map: Map) -> Option<Vec<(i32, i32, i32)>> {
+    if map.section_size != self.world.section_size() {
+      self.set_map(map);
+      self.reload_world();
+      return None;
     }
+    
+    let changed = self.world.apply_map(&map);
+    self.set_map(map);
+    
+    Some(changed)
+  }
+  
+  // Spawn points, spawners, interactives, platforms and which sections are kept loaded change
+  // straight away, the sections themselves only once the world is reloaded. Interactives and
+  // platforms left alone in the map carry on as they were, changed ones start again closed and
+  // unbroken or from the start of their paths.
+  pub fn set_map(&mut self, map: Map) {
+    self.world.set_layout(map.layout());
+    self.world.keep_map_sections(&map);
+    self.spawner_ticks = vec!(0.0; map.enemy_spawners.len());
+    self.interactives = TwinstickGame::keep_unchanged(&self.map.interactives, &map.interactives,
+                                                      std::mem::take(&mut self.interactives), map.interactives());
+    self.platforms = TwinstickGame::keep_unchanged(&self.map.platforms, &map.platforms,
+                                                   std::mem::take(&mut self.platforms), map.platforms());
+    self.map = map;
+  }
+  
+  // Swaps the live objects back in wherever the map entry at the same index is the same.
+  // Entries the map couldn't build were skipped, and then the indexes don't line up.
+  fn keep_unchanged<T: PartialEq>(old: &[T], new: &[T], live: Vec<Box<dyn GenericObject>>,
+                                  mut rebuilt: Vec<Box<dyn GenericObject>>) -> Vec<Box<dyn GenericObject>> {
+    if live.len() != old.len() || rebuilt.len() != new.len() {
+      return rebuilt;
+    }
+    
+    for (i, object) in live.into_iter().enumerate() {
+      if i < new.len() && old.get(i) == new.get(i) {
+        rebuilt[i] = object;
+      }
+    }
+    
+    rebuilt
+  }
+  
+  pub fn map(&self) -> &Map {
+    &self.map
   }
   
   pub fn players(&self) -> &Vec<Box<dyn GenericObject>> {
@@ -53,10 +208,92 @@ impl TwinstickGame {
     &self.enemies
   }
   
+  pub fn player_bullets(&self) -> &Vec<Box<dyn GenericObject>> {
+    &self.player_bullets
+  }
+  
+  pub fn enemy_bullets(&self) -> &Vec<Box<dyn GenericObject>> {
+    &self.enemy_bullets
+  }
+  
+  pub fn interactives(&self) -> &Vec<Box<dyn GenericObject>> {
+    &self.interactives
+  }
+  
+  pub fn platforms(&self) -> &Vec<Box<dyn GenericObject>> {
+    &self.platforms
+  }
+  
   pub fn static_objects(&self) -> &Vec<Box<dyn GenericObject>> {
     self.world.objects()
   }
   
+  // Static objects using these models collide with the model's boxes
+  pub fn set_model_shapes(&mut self, model_shapes: ModelShapes) {
+    self.world.set_model_shapes(model_shapes);
+  }
+  
+  pub fn collision_matrix(&self) -> &CollisionMatrix {
+    &self.collision_matrix
+  }
+  
+  pub fn set_collision_matrix(&mut self, matrix: CollisionMatrix) {
+    self.collision_matrix = matrix;
+  }
+  
+  // Triggers touched during the last update_server
+  pub fn trigger_overlaps(&self) -> &Vec<TriggerOverlap> {
+    &self.trigger_overlaps
+  }
+  
+  // Sections dropped during the last update_server, clients should forget their objects
+  pub fn unloaded_sections(&self) -> &Vec<(i32, i32, i32)> {
+    &self.unloaded_sections
+  }
+  
+  pub fn world(&self) -> &World {
+    &self.world
+  }
+  
+  // The loaded sections within INTEREST_RADIUS of a player and whoever is standing in them,
+  // a player is always interested in themselves
+  pub fn interest_set(&self, player: usize) -> Option<InterestSet> {
+    let centre = self.world.section_containing(self.players.get(player)?.position());
+    let near = |object: &dyn GenericObject| {
+      interest::in_range(centre, self.world.section_containing(object.position()), INTEREST_RADIUS)
+    };
+    
+    Some(InterestSet {
+      centre,
+      sections: self.world.locations().into_iter().filter(|location| interest::in_range(centre, *location, INTEREST_RADIUS)).collect(),
+      players: (0..self.players.len()).filter(|i| *i == player || near(self.players[*i].as_ref())).collect(),
+      enemies: (0..self.enemies.len()).filter(|i| near(self.enemies[*i].as_ref())).collect(),
+    })
+  }
+  
+  fn query_sets(&self) -> Vec<QuerySet<'_>> {
+    vec!(
+      QuerySet::new(Categories::PLAYERS, &self.players, None),
+      QuerySet::new(Categories::ENEMIES, &self.enemies, None),
+      QuerySet::new(Categories::PLAYER_BULLETS, &self.player_bullets, None),
+      QuerySet::new(Categories::ENEMY_BULLETS, &self.enemy_bullets, None),
+      self.world.query_set(),
+    )
+  }
+  
+  // Line of sight, hitscan and the like
+  pub fn raycast(&self, origin: &Vector3, direction: &Vector3, max_distance: f64, filter: Categories) -> Option<QueryHit> {
+    query::raycast(&self.query_sets(), origin, direction, max_distance, filter)
+  }
+  
+  pub fn overlap_sphere(&self, centre: &Vector3, radius: f64, filter: Categories) -> Vec<QueryHit> {
+    query::overlap(&self.query_sets(), &CollisionInfo::Sphere(Vector4::new(centre.x, centre.y, centre.z, radius)), filter)
+  }
+  
+  pub fn overlap_aabb(&self, pos: &Vector3, size: &Vector3, filter: Categories) -> Vec<QueryHit> {
+    query::overlap(&self.query_sets(), &CollisionInfo::AABB(pos.clone(), size.clone(), narrow_phase::identity()), filter)
+  }
+  
   pub fn set_player_rotation(&mut self, idx: usize, rot: f64) {
     if idx >= self.players.len() {
       return;
@@ -73,18 +310,66 @@ impl TwinstickGame {
     self.players[idx] = Box::new(player);
   }
   
-  pub fn add_player(&mut self) {
-    self.players.push(Box::new(Character::new(Vector3::new(0.0, 10.0, 0.0), Vector3::new_same(1.0))));
+  pub fn new_player() -> Character {
+    Character::new(Vector3::new(0.0, 10.0, 0.0), Vector3::new_same(1.0))
+  }
+  
+  // After a restore players pick up the saved characters in the order they reconnect,
+  // whoever connects first gets the first one saved. Clients don't say who they are and
+  // their addresses change between sessions, so there's nothing better to match on.
+  // Characters nobody has picked up yet stay in the next save. Returns the new player's id.
+  pub fn add_player(&mut self) -> u64 {
+    if self.saved_players.is_empty() {
+      let spawn_points = &self.map.spawn_points;
+      let pos = spawn_points[self.players.len() % spawn_points.len()].clone();
+      self.players.push(Box::new(Character::new(pos, Vector3::new_same(1.0))));
+    } else {
+      let data = self.saved_players.remove(0);
+      log::debug!("player {} restored a saved character, {} left", self.players.len(), self.saved_players.len());
+      self.players.push(Box::new(Character::from_data(data)));
+    }
+    
+    let id = self.next_player_id;
+    self.next_player_id += 1;
+    self.player_ids.push(id);
+    id
   }
   
-  pub fn remove_player(&mut self, i: usize) {
-    self.players.remove(i);
+  // Where the player is in players(), None once they have died or left
+  pub fn player_index(&self, id: u64) -> Option<usize> {
+    self.player_ids.iter().position(|player| *player == id)
+  }
+  
+  // Players that died are already gone, their clients can still leave
+  pub fn remove_player(&mut self, id: u64) {
+    if let Some(i) = self.player_index(id) {
+      self.players.remove(i);
+      self.player_ids.remove(i);
+    }
+  }
+  
+  // Done here rather than left to update so the ids go with them
+  fn remove_dead_players(&mut self) {
+    for i in (0..self.players.len()).rev() {
+      if self.players[i].is_dead() {
+        log::debug!("player {} died", self.player_ids[i]);
+        self.players.remove(i);
+        self.player_ids.remove(i);
+      }
+    }
   }
   
   pub fn add_enemy(&mut self, x: f64 , z: f64) -> Enemy {
-    let pos = Vector3::new(x, 20.0, z);
+    self.spawn_enemy(Vector3::new(x, 20.0, z))
+  }
+  
+  pub fn spawn_enemy(&mut self, pos: Vector3) -> Enemy {
+    self.spawn_enemy_with_difficulty(pos, 0)
+  }
+  
+  pub fn spawn_enemy_with_difficulty(&mut self, pos: Vector3, difficulty: u32) -> Enemy {
     let size = Vector3::new_same(2.0);
-    let enemy = Enemy::new(pos, size, "enemy".to_string());
+    let enemy = Enemy::new(pos, size, "enemy".to_string()).difficulty(difficulty);
     self.enemies.push(Box::new(enemy.clone()));
     
     enemy
@@ -95,6 +380,11 @@ impl TwinstickGame {
       return;
     }
     
+    if input == Input::Interact {
+      self.interact_requests.push(i);
+      return;
+    }
+    
     self.players[i].add_input(input);
   }
   
@@ -106,15 +396,19 @@ impl TwinstickGame {
       if self.enemy_tick > ENEMY_RESPAWN_TIMER {
         self.enemy_tick -= ENEMY_RESPAWN_TIMER;
         for i in 0..self.players.len() {
+          let level = Section::floor_at(self.players[i].position().y);
           let indexs = self.world.calculate_grid_area_indexs(self.players[i].position().x,
-                                                             self.players[i].position().y,
+                                                             self.players[i].position().z,
                                                              4);
-          println!("Indexs: {:?}", indexs);
+          log::debug!("spawning enemies player={} floor={} sections={:?}", i, level, indexs);
           for (x, z) in indexs {
-            if let Some(section) = self.world.section_at_xz(x, z) {
+            if let Some(section) = self.world.section_at(x, level, z) {
               if section.has_floor() {
                 let (pos_x, pos_z) = self.world.xz_from_grid_index(x, z);
-                new_enemies.push(Box::new(self.add_enemy(pos_x, pos_z)) as Box<dyn GenericObject>);
+                // Dropped in from low enough not to land on the floor above
+                let pos = Vector3::new(pos_x, Section::floor_top(level) + 4.5, pos_z);
+                let enemy = self.spawn_enemy_with_difficulty(pos, section.difficulty_level());
+                new_enemies.push(Box::new(enemy) as Box<dyn GenericObject>);
               }
             }
           }
@@ -125,15 +419,138 @@ impl TwinstickGame {
     new_enemies
   }
   
+  // A spawner's timer only runs while there is room for another enemy
+  fn tick_spawners(&mut self, delta_time: f64) -> Vec<Box<dyn GenericObject>> {
+    let mut new_enemies = Vec::new();
+    
+    for i in 0..self.map.enemy_spawners.len() {
+      let spawner = self.map.enemy_spawners[i].clone();
+      if self.enemies.len() >= spawner.max_enemies {
+        self.spawner_ticks[i] = 0.0;
+        continue;
+      }
+      
+      self.spawner_ticks[i] += delta_time as f32;
+      if self.spawner_ticks[i] >= spawner.interval {
+        self.spawner_ticks[i] = 0.0;
+        new_enemies.push(Box::new(self.spawn_enemy(spawner.pos)) as Box<dyn GenericObject>);
+      }
+    }
+    
+    new_enemies
+  }
+  
+  // Players that stepped on an elevator pad this tick arrive on its floor at rest
+  fn use_elevators(&mut self) {
+    for overlap in &self.trigger_overlaps {
+      if overlap.trigger.category != Categories::STATIC || overlap.other.category != Categories::PLAYERS {
+        continue;
+      }
+      
+      if let Some(target) = self.world.objects()[overlap.trigger.index].teleport_target() {
+        let player = &mut self.players[overlap.other.index];
+        log::debug!("player {} took an elevator to floor {}", overlap.other.index, Section::floor_at(target.y));
+        player.set_position(target);
+        player.mut_data().vel = Vector3::new_same(0.0);
+      }
+    }
+  }
+  
+  // Plates are on while something stands on them, channels are on while any switch or plate
+  // on them is, and doors open while their channel is on or for good once their room is cleared
+  fn use_interactives(&mut self) {
+    let mut pressed = Vec::new();
+    for overlap in &self.trigger_overlaps {
+      if overlap.trigger.category == Categories::INTERACTIVES {
+        pressed.push(overlap.trigger.index);
+      }
+    }
+    
+    for i in 0..self.interactives.len() {
+      let interactive = self.interactives[i].mut_interactive().unwrap();
+      if interactive.kind() == InteractiveKind::PressurePlate && interactive.set_active(pressed.contains(&i)) {
+        log::debug!("pressure plate {} is now {}", i, if interactive.is_active() { "on" } else { "off" });
+      }
+    }
+    
+    for player in self.interact_requests.drain(..) {
+      if player >= self.players.len() {
+        continue;
+      }
+      
+      let pos = self.players[player].position().clone();
+      let nearest = self.interactives.iter_mut()
+                      .filter(|i| i.interactive().unwrap().kind() == InteractiveKind::Switch)
+                      .map(|i| (i.position().sub(&pos).magnitude(), i))
+                      .filter(|(distance, _)| *distance <= INTERACT_RANGE)
+                      .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+      if let Some((_, switch)) = nearest {
+        switch.mut_interactive().unwrap().interact();
+      }
+    }
+    
+    let mut channels = Vec::new();
+    for interactive in &self.interactives {
+      let interactive = interactive.interactive().unwrap();
+      match interactive.kind() {
+        InteractiveKind::Switch | InteractiveKind::PressurePlate => {
+          if let (true, Some(channel)) = (interactive.is_active(), interactive.channel_number()) {
+            channels.push(channel);
+          }
+        },
+        _ => {},
+      }
+    }
+    
+    for interactive in &mut self.interactives {
+      let interactive = interactive.mut_interactive().unwrap();
+      if interactive.kind() != InteractiveKind::Door {
+        continue;
+      }
+      
+      if let Some(bounds) = interactive.room_bounds().cloned() {
+        let enemies = self.enemies.iter().filter(|e| Section::contains(&bounds, e.position())).count();
+        interactive.count_room_enemies(enemies);
+      }
+      let open = interactive.room_cleared() || interactive.channel_number().is_some_and(|c| channels.contains(&c));
+      interactive.set_active(open);
+    }
+  }
+  
+  // Players keep everything within INTEREST_RADIUS, enemies just the section they are in
+  // so they don't drop through the floor while nobody is around
+  fn unload_far_sections(&mut self) {
+    let mut areas = Vec::new();
+    for player in &self.players {
+      areas.push((self.world.section_containing(player.position()), INTEREST_RADIUS));
+    }
+    for enemy in &self.enemies {
+      areas.push((self.world.section_containing(enemy.position()), 0));
+    }
+    
+    self.unloaded_sections = self.world.unload_outside(&areas);
+    if !self.unloaded_sections.is_empty() {
+      log::debug!("unloaded sections={:?} loaded={}", self.unloaded_sections, self.world.loaded_sections());
+    }
+  }
+  
   pub fn update_server(&mut self, delta_time: f64) -> (Vec<Box<dyn GenericObject>>, Vec<Box<dyn GenericObject>>) {
-    TwinstickGame::update(&mut self.players,
-                          &mut self.enemies,
-                          &mut self.player_bullets,
-                          &mut self.enemy_bullets,
-                          &mut self.world.mut_objects(),
-                          &mut self.dynamic_objects,
-                          None,
-                          delta_time);
+    self.remove_dead_players();
+    let (static_objects, static_grid) = self.world.objects_and_grid();
+    let objects = TickObjects {
+      players: &mut self.players,
+      enemies: &mut self.enemies,
+      player_bullets: &mut self.player_bullets,
+      enemy_bullets: &mut self.enemy_bullets,
+      static_objects,
+      static_grid,
+      interactives: &mut self.interactives,
+      platforms: &mut self.platforms,
+      dynamic_objects: &mut self.dynamic_objects,
+    };
+    self.trigger_overlaps = TwinstickGame::update(objects, &self.collision_matrix, None, delta_time);
+    self.use_elevators();
+    self.use_interactives();
     
     if self.enemies.len() == 0 {
      /* let pos = Vector3::new(-2.0 * 20.0, 10.0, 2.0 * 20.0);
@@ -148,35 +565,60 @@ impl TwinstickGame {
       let x = self.players[i].position().x;
       let z = self.players[i].position().z;
       let (px, pz) = self.world.calculate_grid_index(x, z);
-      let mut new_sections = self.world.load_area(px, pz, 5);
+      let level = Section::floor_at(self.players[i].position().y);
+      let mut new_sections = self.world.load_area(px, level, pz, 5);
       for section in &mut new_sections {
         new_objects.append(&mut section.static_objects());
       }
     }
+    self.unload_far_sections();
     
-    let enemies: Vec<Box<dyn GenericObject>> = self.spawn_enemies(delta_time);
+    let mut enemies: Vec<Box<dyn GenericObject>> = self.spawn_enemies(delta_time);
+    enemies.append(&mut self.tick_spawners(delta_time));
 //    println!("enemies: {}", self.enemies.len());
     (new_objects, enemies)
   }
   
-  pub fn update(players: &mut Vec<Box<dyn GenericObject>>,
-                enemies: &mut Vec<Box<dyn GenericObject>>,
-                player_bullets: &mut Vec<Box<dyn GenericObject>>,
-                enemy_bullets: &mut Vec<Box<dyn GenericObject>>,
-                static_objects: &mut Vec<Box<dyn GenericObject>>,
-                dynamic_objects: &mut Vec<Box<dyn GenericObject>>,
+  // Whatever is standing on a platform moves with it, before it moves itself
+  fn move_platforms(platforms: &mut [Box<dyn GenericObject>],
+                    players: &mut [Box<dyn GenericObject>],
+                    enemies: &mut [Box<dyn GenericObject>],
+                    delta_time: f64) {
+    for platform in platforms.iter_mut().filter_map(|p| p.mut_platform()) {
+      let riding_players = (0..players.len()).filter(|i| platform.carries(players[*i].as_ref())).collect::<Vec<usize>>();
+      let riding_enemies = (0..enemies.len()).filter(|i| platform.carries(enemies[*i].as_ref())).collect::<Vec<usize>>();
+      
+      let moved = platform.advance(delta_time);
+      for i in riding_players {
+        let pos = players[i].position().add(&moved);
+        players[i].set_position(pos);
+      }
+      for i in riding_enemies {
+        let pos = enemies[i].position().add(&moved);
+        enemies[i].set_position(pos);
+      }
+    }
+  }
+  
+  pub fn update(objects: TickObjects,
+                collision_matrix: &CollisionMatrix,
                 char_idx: Option<usize>,
-                delta_time: f64) {
+                delta_time: f64) -> Vec<TriggerOverlap> {
+    let TickObjects { players, enemies, player_bullets, enemy_bullets, static_objects, static_grid, interactives, platforms, .. } = objects;
     let mut new_player_bullets = Vec::new();
     let mut new_enemy_bullets = Vec::new();
     
+    TwinstickGame::move_platforms(platforms, players, enemies, delta_time);
+    
+    // Players that died last tick are taken out before they can move or shoot again
     let mut to_remove = Vec::new();
     for i in (0..players.len()).rev() {
-      let is_player = if char_idx.is_some() { char_idx.unwrap() == i } else { true };
-      new_player_bullets.append(&mut players[i].update(is_player, delta_time));
       if players[i].is_dead() {
         to_remove.push(i);
+        continue;
       }
+      let is_player = char_idx.is_none_or(|idx| idx == i);
+      new_player_bullets.append(&mut players[i].update(is_player, delta_time));
     }
     
     for remove in to_remove {
@@ -219,13 +661,367 @@ impl TwinstickGame {
       enemy_bullets.remove(remove);
     }
     
-    collisions::calculate_collisions(players, 
-                                     static_objects,
-                                     enemies,
-                                     player_bullets,
-                                     enemy_bullets);
+    let mut overlaps = collisions::calculate_collisions(players, 
+                                                   static_objects,
+                                                   static_grid,
+                                                   enemies,
+                                                   player_bullets,
+                                                   enemy_bullets,
+                                                   collision_matrix);
+    overlaps.append(&mut collisions::collide_with_interactives(players,
+                                                               interactives,
+                                                               static_grid.cell_size(),
+                                                               enemies,
+                                                               player_bullets,
+                                                               enemy_bullets,
+                                                               collision_matrix));
+    overlaps.append(&mut collisions::collide_with_platforms(players,
+                                                            platforms,
+                                                            static_grid.cell_size(),
+                                                            enemies,
+                                                            player_bullets,
+                                                            enemy_bullets,
+                                                            collision_matrix));
     
     player_bullets.append(&mut new_player_bullets);
     enemy_bullets.append(&mut new_enemy_bullets);
+    
+    overlaps
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{EntityId, MapSection, MapLayout, MapInteractive, MapPlatform, Dungeon, Wall, SECTION_SIZE, FLOOR_RANGE};
+  
+  #[test]
+  fn queries_cover_the_world_and_everything_in_it() {
+    let mut game = TwinstickGame::new();
+    game.add_player();
+    
+    // The player spawns around here, section (0, 0) has a wall on its +x edge
+    let origin = Vector3::new(0.0, 9.0, 0.0);
+    let hit = game.raycast(&origin, &Vector3::new(1.0, 0.0, 0.0), 100.0, Categories::ALL).unwrap();
+    assert_eq!(hit.entity, EntityId::new(Categories::PLAYERS, 0));
+    assert_eq!(hit.distance, 0.0);
+    
+    let hit = game.raycast(&origin, &Vector3::new(1.0, 0.0, 0.0), 100.0, Categories::STATIC).unwrap();
+    assert_eq!(hit.distance, 19.0);
+    assert_eq!(game.world.raycast(&origin, &Vector3::new(1.0, 0.0, 0.0), 100.0, Categories::ALL), Some(hit));
+    
+    let hits = game.overlap_sphere(&origin, 1.0, Categories::PLAYERS.with(Categories::ENEMIES));
+    assert_eq!(hits.len(), 1);
+    assert!(!game.overlap_aabb(&Vector3::new(0.0, 5.0, 0.0), &Vector3::new_same(1.0), Categories::STATIC).is_empty());
+  }
+  
+  #[test]
+  fn maps_place_players_and_keep_spawners_topped_up() {
+    let mut map = Map::default_map();
+    map.spawn_points.push(Vector3::new(-40.0, 10.0, 0.0));
+    map.enemy_spawners[0].interval = 1.0;
+    map.enemy_spawners[0].max_enemies = 2;
+    
+    let mut game = TwinstickGame::from_map(map);
+    assert_eq!(game.enemies().len(), 1);
+    
+    // Nothing turns up until the interval has passed
+    for _ in 0..55 {
+      assert!(game.update_server(1.0/60.0).1.is_empty());
+    }
+    let spawned = (0..10).map(|_| game.update_server(1.0/60.0).1.len()).sum::<usize>();
+    assert_eq!(spawned, 1);
+    assert_eq!(game.enemies().len(), 2);
+    
+    for _ in 0..120 {
+      game.update_server(1.0/60.0);
+    }
+    assert_eq!(game.enemies().len(), 2);
+    
+    game.add_player();
+    game.add_player();
+    game.add_player();
+    assert_eq!(game.players()[0].position().x, 0.0);
+    assert_eq!(game.players()[1].position().x, -40.0);
+    assert_eq!(game.players()[2].position().x, 0.0);
+  }
+  
+  #[test]
+  fn players_keep_their_ids_when_others_die() {
+    let mut game = TwinstickGame::new();
+    let ids = (0..3).map(|_| game.add_player()).collect::<Vec<u64>>();
+    game.players[1].set_position(Vector3::new(-20.0, 9.0, 0.0));
+    game.players[2].set_position(Vector3::new(20.0, 9.0, 0.0));
+    
+    game.players[0].take_damage(1000);
+    game.update_server(1.0/60.0);
+    assert_eq!(game.players().len(), 2);
+    assert_eq!(ids.iter().map(|id| game.player_index(*id)).collect::<Vec<_>>(), vec!(None, Some(0), Some(1)));
+    assert!(game.players()[game.player_index(ids[2]).unwrap()].position().x > 10.0);
+    
+    // Leaving after dying takes nobody else with them
+    game.remove_player(ids[0]);
+    assert_eq!(game.players().len(), 2);
+    game.remove_player(ids[1]);
+    assert_eq!(game.player_index(ids[2]), Some(0));
+    assert!(game.players()[0].position().x > 10.0);
+    
+    // Ids aren't handed out again
+    assert!(!ids.contains(&game.add_player()));
+  }
+  
+  #[test]
+  fn elevators_move_players_between_floors() {
+    let mut map = Map::default_map();
+    map.sections = vec!(
+      MapSection { x: 0, z: 0, level: 0, floor: true, walls: Vec::new(), ramp: None, elevators: vec!(1) },
+      MapSection { x: 0, z: 0, level: 1, floor: true, walls: Vec::new(), ramp: None, elevators: vec!(-1) },
+    );
+    // Dropped straight onto the pad going up
+    map.spawn_points = vec!(Vector3::new(SECTION_SIZE*0.25, 10.0, 0.0));
+    map.enemy_spawners.clear();
+    
+    let mut game = TwinstickGame::from_map(map);
+    game.add_player();
+    for _ in 0..60 {
+      game.update_server(1.0/60.0);
+    }
+    
+    // Arrives in the middle of the floor above, off that floor's own pad
+    let pos = game.players()[0].position().clone();
+    assert_eq!(Section::floor_at(pos.y), 1);
+    assert_eq!((pos.x, pos.z), (0.0, 0.0));
+    assert!(game.players()[0].data().grounded);
+    
+    // And the floors either side of it are streamed in
+    assert!(game.world.section_at(0, 2, 0).is_some());
+    assert!(game.world.section_at(0, 3, 0).is_none());
+    
+    // Walking onto the pad going down takes it back
+    for _ in 0..60 {
+      game.add_input(0, Input::D);
+      game.update_server(1.0/60.0);
+    }
+    assert_eq!(Section::floor_at(game.players()[0].position().y), 0);
+  }
+  
+  #[test]
+  fn players_far_apart_hear_about_different_things() {
+    let mut map = Map::default_map();
+    map.sections.clear();
+    map.layout = MapLayout::Dungeon(Dungeon::new(11));
+    map.enemy_spawners.clear();
+    map.spawn_points = vec!(Vector3::new(0.0, 10.0, 0.0), Vector3::new(SECTION_SIZE*100.0, 10.0, SECTION_SIZE*-40.0));
+    
+    let mut game = TwinstickGame::from_map(map);
+    game.add_player();
+    game.add_player();
+    game.update_server(1.0/60.0);
+    game.spawn_enemy(Vector3::new(SECTION_SIZE, 10.0, 0.0));
+    game.spawn_enemy(Vector3::new(SECTION_SIZE*101.0, 10.0, SECTION_SIZE*-40.0));
+    
+    let first = game.interest_set(0).unwrap();
+    let second = game.interest_set(1).unwrap();
+    assert!(first.is_disjoint(&second));
+    assert_eq!((first.players.clone(), second.players.clone()), (vec!(0), vec!(1)));
+    assert_eq!((first.enemies.clone(), second.enemies.clone()), (vec!(0), vec!(1)));
+    
+    // Both have everything loaded around them
+    for set in &[&first, &second] {
+      let (x, level, z) = set.centre;
+      assert!(set.contains_section((x + 2, level, z - 2)));
+      assert!(!set.contains_section((x + INTEREST_RADIUS + 1, level, z)));
+      assert!(set.sections.iter().all(|s| game.world.is_loaded(s.0, s.1, s.2)));
+    }
+    
+    // Walking together they share everything
+    game.players[1].set_position(Vector3::new(SECTION_SIZE, 10.0, 0.0));
+    game.update_server(1.0/60.0);
+    let first = game.interest_set(0).unwrap();
+    assert_eq!(first.players, vec!(0, 1));
+    assert!(first.contains_section(game.interest_set(1).unwrap().centre));
+    assert!(game.interest_set(2).is_none());
+  }
+  
+  #[test]
+  fn interest_follows_the_player_id_when_an_earlier_player_dies() {
+    let mut map = Map::default_map();
+    map.sections.clear();
+    map.layout = MapLayout::Dungeon(Dungeon::new(11));
+    map.enemy_spawners.clear();
+    map.spawn_points = vec!(Vector3::new(0.0, 10.0, 0.0), Vector3::new(SECTION_SIZE*100.0, 10.0, SECTION_SIZE*-40.0));
+    
+    let mut game = TwinstickGame::from_map(map);
+    let first = game.add_player();
+    let second = game.add_player();
+    game.update_server(1.0/60.0);
+    let far = game.interest_set(game.player_index(second).unwrap()).unwrap();
+    
+    game.players[0].take_damage(1000);
+    game.update_server(1.0/60.0);
+    assert!(game.player_index(first).is_none());
+    let interest = game.interest_set(game.player_index(second).unwrap()).unwrap();
+    assert_eq!(interest.centre, far.centre);
+    assert_eq!(interest.players, vec!(0));
+  }
+  
+  #[test]
+  fn roaming_players_only_keep_the_sections_around_them() {
+    let mut map = Map::default_map();
+    map.sections.clear();
+    map.layout = MapLayout::Dungeon(Dungeon::new(3).floors(2));
+    map.enemy_spawners.clear();
+    
+    let mut game = TwinstickGame::from_map(map);
+    game.add_player();
+    
+    let area = ((INTEREST_RADIUS*2 + 1)*(INTEREST_RADIUS*2 + 1)*(FLOOR_RANGE*2 + 1)) as usize;
+    let mut unloaded = 0;
+    for i in 0..300 {
+      // A few sections a tick, standing on the ground floor
+      let pos = Vector3::new(i as f64*SECTION_SIZE*8.0, Section::floor_top(0) + 1.0, i as f64*SECTION_SIZE*4.0);
+      game.players[0].set_position(pos);
+      game.update_server(1.0/60.0);
+      unloaded += game.unloaded_sections().len();
+      
+      // Enemies hold on to the section they are standing in
+      assert!(game.world.loaded_sections() <= area + game.enemies().len()*3, "{} sections loaded at step {}", game.world.loaded_sections(), i);
+      assert_eq!(game.world.static_grid().len(), game.static_objects().len());
+    }
+    
+    assert!(unloaded > 300*25);
+    assert!(game.world.is_loaded(2392, 0, 1196));
+    assert!(!game.world.is_loaded(0, 0, 0));
+    assert!(game.world.static_grid().occupied_cells() <= area);
+  }
+  
+  #[test]
+  fn edited_maps_only_replace_the_sections_that_changed() {
+    let mut game = TwinstickGame::from_map(Map::default_map());
+    let loaded = game.world.locations();
+    
+    let mut map = Map::default_map();
+    map.set_wall(0, 0, 0, Wall::Left, false);
+    map.set_floor(9, 0, 9, true);
+    assert_eq!(game.change_map(map.clone()), Some(vec!((0, 0, 0), (9, 0, 9))));
+    assert_eq!(game.world.section_at(0, 0, 0), Some(map.section_at(0, 0, 0).unwrap().to_section(SECTION_SIZE)));
+    assert_eq!(game.world.send_section(0, 0, 0).unwrap()[0].objects.len(), 3);
+    assert!(game.world.is_loaded(9, 0, 9));
+    assert_eq!(game.change_map(map.clone()), Some(Vec::new()));
+    
+    // Sections the map stops listing go back to the layout's, and can be unloaded again
+    map.sections.retain(|s| (s.x, s.level, s.z) != (9, 0, 9));
+    assert_eq!(game.change_map(map.clone()), Some(vec!((9, 0, 9))));
+    assert_eq!(game.world.unload_outside(&[((0, 0, 0), 4)]), vec!((9, 0, 9)));
+    assert_eq!(game.world.locations(), loaded);
+    
+    map.section_size *= 2.0;
+    assert_eq!(game.change_map(map), None);
+    assert_eq!(game.world.section_size(), SECTION_SIZE*2.0);
+  }
+  
+  #[test]
+  fn restored_saves_unload_sections_streamed_in_before_saving() {
+    let mut game = TwinstickGame::new();
+    game.world.load_section(30, 0, 30);
+    game.world.load_section(-40, 1, 12);
+    
+    let mut restored = TwinstickGame::from_save(game.save());
+    assert!(restored.world.is_loaded(30, 0, 30));
+    let unloaded = restored.world.unload_outside(&[((0, 0, 0), 4)]);
+    assert_eq!(unloaded, vec!((-40, 1, 12), (30, 0, 30)));
+    
+    // The map's own sections are still kept however far the players are
+    restored.world.unload_outside(&[((500, 0, 500), 1)]);
+    for section in &restored.map.sections {
+      assert!(restored.world.is_loaded(section.x, section.level, section.z));
+    }
+    assert!(!restored.map.sections.is_empty());
+  }
+  
+  #[test]
+  fn switches_plates_and_cleared_rooms_open_doors() {
+    let interactive = |kind, pos, size, channel| MapInteractive {
+      kind,
+      pos,
+      size,
+      rotation: Vector3::new_same(0.0),
+      channel,
+      room: None,
+      life: None,
+    };
+    let door = |channel| interactive(InteractiveKind::Door, Vector3::new(-100.0, 8.0, 0.0), Vector3::new(1.0, 5.0, 8.0), channel);
+    
+    let mut map = Map::default_map();
+    map.interactives = vec!(
+      interactive(InteractiveKind::Switch, Vector3::new(3.0, 8.0, 0.0), Vector3::new_same(1.0), Some(1)),
+      door(Some(1)),
+      // The player spawns standing on this one
+      interactive(InteractiveKind::PressurePlate, Vector3::new(0.0, 6.0, 0.0), Vector3::new(4.0, 2.0, 4.0), Some(2)),
+      door(Some(2)),
+      // Around the enemy spawner
+      MapInteractive { room: Some((Vector3::new(-180.0, 0.0, -20.0), Vector3::new(-140.0, 30.0, 20.0))), ..door(None) },
+    );
+    let mut game = TwinstickGame::from_map(map);
+    game.add_player();
+    let active = |game: &TwinstickGame| game.interactives().iter().map(|i| i.interactive().unwrap().is_active()).collect::<Vec<bool>>();
+    
+    for _ in 0..60 {
+      game.update_server(1.0/60.0);
+    }
+    assert_eq!(active(&game), vec!(false, false, true, true, false));
+    
+    game.add_input(0, Input::Interact);
+    game.update_server(1.0/60.0);
+    assert_eq!(active(&game)[..2], [true, true]);
+    
+    // Stepping off the plate closes its door, the switch stays on
+    game.players[0].set_position(Vector3::new(-20.0, 8.0, 0.0));
+    game.update_server(1.0/60.0);
+    assert_eq!(active(&game)[..4], [true, true, false, false]);
+    
+    // Out of reach of the switch
+    game.add_input(0, Input::Interact);
+    game.update_server(1.0/60.0);
+    assert!(active(&game)[0]);
+    
+    game.enemies.clear();
+    game.update_server(1.0/60.0);
+    assert!(active(&game)[4]);
+    
+    // Cleared rooms stay open even once enemies come back
+    for _ in 0..600 {
+      game.update_server(1.0/60.0);
+    }
+    assert!(!game.enemies.is_empty());
+    assert!(active(&game)[4]);
+  }
+  
+  #[test]
+  fn platforms_move_with_the_server_and_restart_when_changed_in_the_map() {
+    let mut map = Map::default_map();
+    map.platforms.push(MapPlatform {
+      path: vec!(Vector3::new(-40.0, 6.0, 0.0), Vector3::new(-60.0, 6.0, 0.0)),
+      size: Vector3::new(4.0, 1.0, 4.0),
+      speed: 10.0,
+      rotation: Vector3::new_same(0.0),
+      model: "unit_floor".to_string(),
+      hazard: None,
+    });
+    let mut game = TwinstickGame::from_map(map.clone());
+    
+    for _ in 0..60 {
+      game.update_server(1.0/60.0);
+    }
+    assert!((game.platforms()[0].position().x + 50.0).abs() < 1e-6);
+    
+    // Something else in the map changed
+    map.spawn_points.push(Vector3::new(10.0, 8.0, 0.0));
+    game.set_map(map.clone());
+    assert!((game.platforms()[0].position().x + 50.0).abs() < 1e-6);
+    
+    map.platforms[0].speed = 5.0;
+    game.set_map(map);
+    assert_eq!(game.platforms()[0].position().x, -40.0);
   }
 }
@@ -0,0 +1,305 @@
+use crate::{ObjectData, ObjectPhysicsType, GenericObject, StaticObject, SendInteractive, DrawCall, Vector3, Contact};
+
+// How close a player has to be to a switch to flip it with Input::Interact
+pub const INTERACT_RANGE: f64 = 5.0;
+const WALL_LIFE: i32 = 10;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum InteractiveKind {
+  // In the way until a switch or plate on its channel is on, or its room is cleared
+  Door,
+  // Flips when shot or interacted with
+  Switch,
+  // On while a player or enemy stands on it
+  PressurePlate,
+  // In the way until it has been shot enough, on once it breaks
+  DestructibleWall,
+}
+
+impl InteractiveKind {
+  fn model(&self) -> String {
+    match self {
+      InteractiveKind::Switch => "hug_cube".to_string(),
+      _ => "unit_floor".to_string(),
+    }
+  }
+}
+
+// Parts of the world that change while the game runs. Each is either on or off, an on
+// door is open and an on wall is broken, both letting everything through.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Interactive {
+  // Collides like any other static object while it is in the way
+  object: StaticObject,
+  kind: InteractiveKind,
+  channel: Option<u32>,
+  room: Option<(Vector3, Vector3)>,
+  active: bool,
+  enemies_seen: bool,
+  cleared: bool,
+}
+
+impl Interactive {
+  pub fn new(kind: InteractiveKind, pos: Vector3, size: Vector3) -> Interactive {
+    let mut object = StaticObject::new(pos, size, kind.model());
+    match kind {
+      InteractiveKind::PressurePlate => object = object.trigger(),
+      InteractiveKind::DestructibleWall => object.mut_data().life = WALL_LIFE,
+      _ => {},
+    }
+    
+    Interactive {
+      object,
+      kind,
+      channel: None,
+      room: None,
+      active: false,
+      enemies_seen: false,
+      cleared: false,
+    }
+  }
+  
+  // Switches and plates turn on their channel, doors open while any of theirs is on
+  pub fn channel(mut self, channel: u32) -> Interactive {
+    self.channel = Some(channel);
+    self
+  }
+  
+  // Doors open for good once every enemy seen inside these bounds, see Section::bounds, has died
+  pub fn room(mut self, min: Vector3, max: Vector3) -> Interactive {
+    self.room = Some((min, max));
+    self
+  }
+  
+  pub fn life(mut self, life: i32) -> Interactive {
+    self.object.mut_data().life = life;
+    self
+  }
+  
+  pub fn rotation(mut self, rot: Vector3) -> Interactive {
+    self.object = self.object.rotation(rot);
+    self
+  }
+  
+  pub fn kind(&self) -> InteractiveKind {
+    self.kind
+  }
+  
+  pub fn channel_number(&self) -> Option<u32> {
+    self.channel
+  }
+  
+  pub fn room_bounds(&self) -> Option<&(Vector3, Vector3)> {
+    self.room.as_ref()
+  }
+  
+  pub fn is_active(&self) -> bool {
+    self.active
+  }
+  
+  // Open doors and broken walls stop colliding, returns whether anything changed
+  pub fn set_active(&mut self, active: bool) -> bool {
+    if self.active == active {
+      return false;
+    }
+    
+    self.active = active;
+    match self.kind {
+      InteractiveKind::Door | InteractiveKind::DestructibleWall => {
+        self.object.mut_data().physics_type = if active { ObjectPhysicsType::Decorative } else { ObjectPhysicsType::Static };
+      },
+      _ => {},
+    }
+    
+    true
+  }
+  
+  pub fn interact(&mut self) {
+    if self.kind == InteractiveKind::Switch {
+      let active = !self.active;
+      self.set_active(active);
+    }
+  }
+  
+  // Called every tick with how many enemies are in the room
+  pub fn count_room_enemies(&mut self, enemies: usize) {
+    if enemies > 0 {
+      self.enemies_seen = true;
+    } else if self.enemies_seen {
+      self.cleared = true;
+    }
+  }
+  
+  pub fn room_cleared(&self) -> bool {
+    self.cleared
+  }
+  
+  pub fn send_interactive(&self) -> SendInteractive {
+    SendInteractive {
+      kind: self.kind,
+      pos: self.position().clone(),
+      size: self.size().clone(),
+      rotation: self.rotation().clone(),
+      active: self.active,
+    }
+  }
+}
+
+impl GenericObject for Interactive {
+  fn data(&self) -> &ObjectData {
+    self.object.data()
+  }
+  
+  fn mut_data(&mut self) -> &mut ObjectData {
+    self.object.mut_data()
+  }
+  
+  fn interactive(&self) -> Option<&Interactive> {
+    Some(self)
+  }
+  
+  fn mut_interactive(&mut self) -> Option<&mut Interactive> {
+    Some(self)
+  }
+  
+  fn shot(&mut self, damage: i32) {
+    match self.kind {
+      InteractiveKind::Switch => self.interact(),
+      InteractiveKind::DestructibleWall if !self.active => {
+        self.object.take_damage(damage);
+        if self.object.is_dead() {
+          self.set_active(true);
+        }
+      },
+      _ => {},
+    }
+  }
+  
+  fn collided_with_static_object(&mut self, _static_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
+  
+  }
+  
+  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>, contact: &Contact) {
+    self.object.collided_with_dynamic_object(dynamic_object, contact);
+  }
+  
+  fn update(&mut self, _is_player: bool, _delta_time: f64) -> Vec<Box<dyn GenericObject>> {
+    Vec::new()
+  }
+  
+  fn physics_update(&mut self, _delta_time: f64) {
+  
+  }
+  
+  fn additional_draws(&self, _draw_calls: &mut Vec<DrawCall>) {
+  
+  }
+  
+  // Open doors sink into the floor, pressed plates half way and broken walls are gone
+  fn draw(&self, additional_draws: bool, draw_calls: &mut Vec<DrawCall>) {
+    let sink = match (self.kind, self.active) {
+      (InteractiveKind::DestructibleWall, true) => return,
+      (InteractiveKind::Door, true) => self.size().y,
+      (InteractiveKind::PressurePlate, true) => self.size().y*0.5,
+      _ => 0.0,
+    };
+    
+    let mut object = self.object.clone();
+    let pos = self.position();
+    object.set_position(Vector3::new(pos.x, pos.y - sink, pos.z));
+    object.draw(additional_draws, draw_calls);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Bullet, Character, CollisionGrid, CollisionMatrix, TwinstickGame, TickObjects, Input, Section, SECTION_SIZE};
+  
+  fn door() -> Interactive {
+    Interactive::new(InteractiveKind::Door, Vector3::new(0.0, 8.0, 0.0), Vector3::new(1.0, 5.0, 8.0))
+  }
+  
+  // Runs the shared update with only a player and the interactives, the player walks along +x
+  fn walk_into(interactives: &mut Vec<Box<dyn GenericObject>>) -> f64 {
+    let mut players: Vec<Box<dyn GenericObject>> = vec!(Box::new(Character::new(Vector3::new(-4.0, Section::floor_top(0) + 1.75, 0.0), Vector3::new_same(1.0))));
+    let mut statics = Section::new(0, 0, SECTION_SIZE).floor().static_objects();
+    let grid = CollisionGrid::build(SECTION_SIZE, &statics);
+    for _ in 0..60 {
+      players[0].add_input(Input::A);
+      let objects = TickObjects {
+        players: &mut players,
+        enemies: &mut Vec::new(),
+        player_bullets: &mut Vec::new(),
+        enemy_bullets: &mut Vec::new(),
+        static_objects: &mut statics,
+        static_grid: &grid,
+        interactives,
+        platforms: &mut Vec::new(),
+        dynamic_objects: &mut Vec::new(),
+      };
+      TwinstickGame::update(objects, &CollisionMatrix::new(), None, 1.0/60.0);
+    }
+    
+    players[0].position().x
+  }
+  
+  #[test]
+  fn open_doors_and_broken_walls_let_players_through() {
+    let mut interactives: Vec<Box<dyn GenericObject>> = vec!(Box::new(door()));
+    assert!(walk_into(&mut interactives) < 0.0);
+    
+    interactives[0].mut_interactive().unwrap().set_active(true);
+    assert!(walk_into(&mut interactives) > 4.0);
+    
+    let mut wall = Interactive::new(InteractiveKind::DestructibleWall, Vector3::new(0.0, 8.0, 0.0), Vector3::new(1.0, 5.0, 8.0)).life(3);
+    for _ in 0..2 {
+      wall.shot(1);
+    }
+    assert!(!wall.is_active());
+    wall.shot(1);
+    assert!(wall.is_active());
+    let mut interactives: Vec<Box<dyn GenericObject>> = vec!(Box::new(wall));
+    assert!(walk_into(&mut interactives) > 4.0);
+  }
+  
+  // Runs the shared update with only bullets and the interactives
+  fn shoot(bullets: &mut Vec<Box<dyn GenericObject>>, statics: &mut Vec<Box<dyn GenericObject>>,
+           grid: &CollisionGrid, interactives: &mut Vec<Box<dyn GenericObject>>) {
+    let objects = TickObjects {
+      players: &mut Vec::new(),
+      enemies: &mut Vec::new(),
+      player_bullets: bullets,
+      enemy_bullets: &mut Vec::new(),
+      static_objects: statics,
+      static_grid: grid,
+      interactives,
+      platforms: &mut Vec::new(),
+      dynamic_objects: &mut Vec::new(),
+    };
+    TwinstickGame::update(objects, &CollisionMatrix::new(), None, 1.0/60.0);
+  }
+  
+  #[test]
+  fn bullets_flip_switches_and_stop_at_closed_doors() {
+    let switch = Interactive::new(InteractiveKind::Switch, Vector3::new(10.0, 8.0, 0.0), Vector3::new_same(1.0)).channel(1);
+    let mut interactives: Vec<Box<dyn GenericObject>> = vec!(Box::new(switch));
+    let mut statics = Vec::new();
+    let grid = CollisionGrid::build(SECTION_SIZE, &statics);
+    let mut bullets: Vec<Box<dyn GenericObject>> = vec!(Box::new(Bullet::new(Vector3::new(0.0, 8.0, 0.0), Vector3::new_same(1.0), 90.0, 0.0, "bullet".to_string())));
+    for _ in 0..60 {
+      shoot(&mut bullets, &mut statics, &grid, &mut interactives);
+    }
+    assert!(bullets.is_empty());
+    assert!(interactives[0].interactive().unwrap().is_active());
+    
+    // Bullets can't reach anything past a closed door
+    interactives.insert(0, Box::new(door().channel(2)));
+    bullets.push(Box::new(Bullet::new(Vector3::new(-5.0, 8.0, 0.0), Vector3::new_same(1.0), 90.0, 0.0, "bullet".to_string())));
+    for _ in 0..60 {
+      shoot(&mut bullets, &mut statics, &grid, &mut interactives);
+    }
+    assert!(bullets.is_empty());
+    assert!(interactives[1].interactive().unwrap().is_active());
+  }
+}
@@ -0,0 +1,40 @@
+use crate::FLOOR_RANGE;
+
+// Whether a section is within radius sections of a centre one, counting the floors
+// within FLOOR_RANGE of the centre's as in range too
+pub fn in_range(centre: (i32, i32, i32), location: (i32, i32, i32), radius: i32) -> bool {
+  let (cx, clevel, cz) = centre;
+  let (x, level, z) = location;
+  (x - cx).abs() <= radius && (z - cz).abs() <= radius && (level - clevel).abs() <= FLOOR_RANGE
+}
+
+// Everything one player's client needs to hear about, the sections around them and
+// the players and enemies in those sections. Indexes are into the game's lists.
+#[derive(PartialEq, Debug, Clone)]
+pub struct InterestSet {
+  pub centre: (i32, i32, i32),
+  pub sections: Vec<(i32, i32, i32)>,
+  pub players: Vec<usize>,
+  pub enemies: Vec<usize>,
+}
+
+impl InterestSet {
+  pub fn contains_section(&self, location: (i32, i32, i32)) -> bool {
+    self.sections.binary_search(&location).is_ok()
+  }
+  
+  pub fn contains_player(&self, index: usize) -> bool {
+    self.players.contains(&index)
+  }
+  
+  pub fn contains_enemy(&self, index: usize) -> bool {
+    self.enemies.contains(&index)
+  }
+  
+  // Nothing in common, neither client hears about anything the other does
+  pub fn is_disjoint(&self, other: &InterestSet) -> bool {
+    !self.sections.iter().any(|s| other.contains_section(*s)) &&
+    !self.players.iter().any(|p| other.contains_player(*p)) &&
+    !self.enemies.iter().any(|e| other.contains_enemy(*e))
+  }
+}
@@ -13,33 +13,66 @@ pub const FPS_60: f64 = 1.0/60.0;
 pub const FPS_120: f64 = 1.0/120.0;
 
 pub const SPEED: f64 = 12.0;
+// Ledges this high or lower are walked up instead of blocking
+pub const STEP_HEIGHT: f64 = 0.6;
+
+pub const SECTION_SIZE: f64 = 40.0;
 
 pub const  ENEMY_RESPAWN_TIMER: f32 = 5.0;
 
-pub use self::game::TwinstickGame;
+pub use self::game::{TwinstickGame, TickObjects};
 pub use self::player::Character;
-pub use self::object::{GenericObject, ObjectData, CollisionInfo, Vector2, Vector3, Vector4};
+pub use self::object::{GenericObject, ObjectData, ObjectPhysicsType, CollisionType, CollisionInfo, Hitbox, HitRegion, Vector2, Vector3, Vector4};
 pub use self::static_object::{StaticObject};
+pub use self::interactive::{Interactive, InteractiveKind, INTERACT_RANGE};
+pub use self::platform::MovingPlatform;
 pub use self::bullet::Bullet;
 pub use self::enemy::Enemy;
-pub use self::section::Section;
+pub use self::section::{Section, Wall, DOOR_WIDTH, FLOOR_HEIGHT};
 pub use self::section_layout::SectionLayout;
-pub use self::world::World;
+pub use self::world::{World, FLOOR_RANGE, INTEREST_RADIUS};
+pub use self::static_sections::{StaticSections, SectionAssembler};
+pub use self::interest::InterestSet;
+pub use self::broad_phase::CollisionGrid;
+pub use self::narrow_phase::Contact;
+pub use self::query::{Categories, EntityId, QueryHit, QuerySet};
+pub use self::collisions::{CollisionMatrix, CollisionGroup, TriggerOverlap};
+pub use self::model_shapes::{ModelShapes, ModelShape, ShapePart};
 pub use self::send_structs::*;
+pub use self::error::TwinstickError;
+pub use self::admin::{AdminCommand, ADMIN_USAGE};
+pub use self::save::{SaveGame, SAVE_VERSION};
+pub use self::map::{Map, MapSection, MapStaticObject, MapInteractive, MapPlatform, MapLayout, EnemySpawner, MAP_VERSION, MAP_ENV, DEFAULT_MAP_PATH};
+pub use self::dungeon::{Dungeon, DungeonCell, Opening};
+pub use self::protocol::{PROTOCOL_VERSION, MIN_PROTOCOL_VERSION, LEGACY_VERSION, ProtocolRange, Features,
+                         Handshake, HandshakeAccepted, ConnectionRejection};
 
 pub mod collisions;
+mod broad_phase;
+pub mod narrow_phase;
+pub mod query;
+pub mod physics;
+mod model_shapes;
 mod section;
 mod object;
 mod game;
 mod player;
 mod static_object;
+mod interactive;
+mod platform;
 mod bullet;
 mod send_structs;
 mod enemy;
 mod section_layout;
 mod world;
-
-pub const VERSION: u32 = 4;
+mod static_sections;
+pub mod interest;
+mod protocol;
+mod error;
+mod admin;
+mod save;
+mod map;
+mod dungeon;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum Input {
@@ -49,6 +82,8 @@ pub enum Input {
   D,
   Space,
   LeftClick,
+  // Flips a switch in reach, needs Features::INTERACTIVES
+  Interact,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -67,22 +102,73 @@ pub enum DataType {
   StaticObject(SendStaticObject),
   Exit,
   Err(String),
+  Handshake(Handshake),
+  HandshakeAccepted(HandshakeAccepted),
+  Rejected(ConnectionRejection),
+  Admin(String, AdminCommand),
+  AdminResponse(String),
+  Chat(String),
+  Kicked(String),
+  ServerShutdown(String),
+  ClearStaticObjects,
+  DropStaticObjects(Vector3, Vector3),
+  LoadSection(SendSection),
+  UnloadSection(i32, i32, i32),
+  AddInteractive(SendInteractive),
+  InteractiveState(usize, bool),
+  ClearInteractives,
+  AddPlatform(SendPlatform),
+  // Index, position and the waypoint it is heading for
+  PlatformState(usize, Vector3, usize),
+  ClearPlatforms,
+  // From the client once every fragment of a LoadSection has arrived
+  SectionLoaded(i32, i32, i32),
 }
 
 impl DataType {
-  pub fn serialise(&self) -> Vec<u8> {
-    bincode::serialize(&self).unwrap()
+  pub fn serialise(&self) -> Result<Vec<u8>, TwinstickError> {
+    bincode::serialize(&self).map_err(TwinstickError::Serialise)
+  }
+  
+  pub fn deserialise(serialised: &[u8]) -> Result<DataType, TwinstickError> {
+    bincode::deserialize(serialised).map_err(TwinstickError::Deserialise)
   }
   
-  pub fn deserialise(serialised: &[u8]) -> Option<DataType> {
-    match bincode::deserialize(&serialised) {
-      Ok(data) => {
-        Some(data)
-      },
-      Err(e) => {
-        println!("{:?}", e);
-        None
-      }
+  // Used to label metrics and logs
+  pub fn name(&self) -> &'static str {
+    match self {
+      DataType::TryConnect(_) => "TryConnect",
+      DataType::ConfirmConnect(_) => "ConfirmConnect",
+      DataType::PlayerNum(_) => "PlayerNum",
+      DataType::PlayerRotation(_, _) => "PlayerRotation",
+      DataType::AddPlayer(_) => "AddPlayer",
+      DataType::Player(_, _) => "Player",
+      DataType::RemovePlayer(_) => "RemovePlayer",
+      DataType::AddEnemy(_) => "AddEnemy",
+      DataType::Enemy(_, _) => "Enemy",
+      DataType::Input(_) => "Input",
+      DataType::StaticObject(_) => "StaticObject",
+      DataType::Exit => "Exit",
+      DataType::Err(_) => "Err",
+      DataType::Handshake(_) => "Handshake",
+      DataType::HandshakeAccepted(_) => "HandshakeAccepted",
+      DataType::Rejected(_) => "Rejected",
+      DataType::Admin(_, _) => "Admin",
+      DataType::AdminResponse(_) => "AdminResponse",
+      DataType::Chat(_) => "Chat",
+      DataType::Kicked(_) => "Kicked",
+      DataType::ServerShutdown(_) => "ServerShutdown",
+      DataType::ClearStaticObjects => "ClearStaticObjects",
+      DataType::DropStaticObjects(_, _) => "DropStaticObjects",
+      DataType::LoadSection(_) => "LoadSection",
+      DataType::UnloadSection(_, _, _) => "UnloadSection",
+      DataType::AddInteractive(_) => "AddInteractive",
+      DataType::InteractiveState(_, _) => "InteractiveState",
+      DataType::ClearInteractives => "ClearInteractives",
+      DataType::AddPlatform(_) => "AddPlatform",
+      DataType::PlatformState(_, _, _) => "PlatformState",
+      DataType::ClearPlatforms => "ClearPlatforms",
+      DataType::SectionLoaded(_, _, _) => "SectionLoaded",
     }
   }
 }
@@ -0,0 +1,742 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::{Section, SectionLayout, StaticObject, GenericObject, Interactive, InteractiveKind, MovingPlatform, Vector3, Wall, Dungeon, TwinstickError};
+use crate::physics::KILL_PLANE_Y;
+
+// Bump when Map changes shape, older files are refused rather than guessed at
+pub const MAP_VERSION: u32 = 3;
+
+// Where the server and the editor find the map, so both work on the same one
+pub const MAP_ENV: &str = "TWINSTICK_MAP";
+pub const DEFAULT_MAP_PATH: &str = "resources/maps/default.ron";
+
+// The map the game has always shipped with
+const DEFAULT_MAP: &str = include_str!("../../resources/maps/default.ron");
+// How far above the floor players are dropped in, as in the default map
+const SPAWN_HEIGHT: f64 = 4.5;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MapSection {
+  pub x: i32,
+  pub z: i32,
+  // Which floor of the tower
+  #[serde(default)]
+  pub level: i32,
+  #[serde(default)]
+  pub floor: bool,
+  #[serde(default)]
+  pub walls: Vec<Wall>,
+  // Rises to the next floor towards this edge, see Section::ramp
+  #[serde(default)]
+  pub ramp: Option<Wall>,
+  // Floors to move players up, or down when negative
+  #[serde(default)]
+  pub elevators: Vec<i32>,
+}
+
+impl MapSection {
+  pub fn to_section(&self, section_size: f64) -> Section {
+    let mut section = Section::new_on_floor(self.x, self.level, self.z, section_size);
+    if self.floor {
+      section = section.floor();
+    }
+    for wall in &self.walls {
+      section = section.wall(*wall);
+    }
+    if let Some(towards) = self.ramp {
+      section = section.ramp(towards);
+    }
+    for floors in &self.elevators {
+      section = section.elevator(*floors);
+    }
+    
+    section
+  }
+}
+
+// Anything that isn't a floor or a wall, placed in world space
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MapStaticObject {
+  pub pos: Vector3,
+  pub size: Vector3,
+  #[serde(default = "no_rotation")]
+  pub rotation: Vector3,
+  pub model: String,
+}
+
+fn no_rotation() -> Vector3 {
+  Vector3::new_same(0.0)
+}
+
+impl MapStaticObject {
+  pub fn to_object(&self) -> StaticObject {
+    StaticObject::new(self.pos.clone(), self.size.clone(), self.model.clone()).rotation(self.rotation.clone())
+  }
+}
+
+// Doors, switches and the like, see Interactive
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MapInteractive {
+  pub kind: InteractiveKind,
+  pub pos: Vector3,
+  pub size: Vector3,
+  #[serde(default = "no_rotation")]
+  pub rotation: Vector3,
+  #[serde(default)]
+  pub channel: Option<u32>,
+  // Doors only, min and max corners of the room that opens them once it is cleared
+  #[serde(default)]
+  pub room: Option<(Vector3, Vector3)>,
+  // Destructible walls only, how much damage they take to break
+  #[serde(default)]
+  pub life: Option<i32>,
+}
+
+impl MapInteractive {
+  pub fn to_interactive(&self) -> Interactive {
+    let mut interactive = Interactive::new(self.kind, self.pos.clone(), self.size.clone()).rotation(self.rotation.clone());
+    if let Some(channel) = self.channel {
+      interactive = interactive.channel(channel);
+    }
+    if let Some((min, max)) = &self.room {
+      interactive = interactive.room(min.clone(), max.clone());
+    }
+    if let Some(life) = self.life {
+      interactive = interactive.life(life);
+    }
+    
+    interactive
+  }
+}
+
+// Loops through its path at speed units a second, see MovingPlatform
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MapPlatform {
+  pub path: Vec<Vector3>,
+  pub size: Vector3,
+  pub speed: f64,
+  #[serde(default = "no_rotation")]
+  pub rotation: Vector3,
+  #[serde(default = "platform_model")]
+  pub model: String,
+  // Damage a second, hazards hurt what they touch rather than carrying it
+  #[serde(default)]
+  pub hazard: Option<i32>,
+}
+
+fn platform_model() -> String {
+  "unit_floor".to_string()
+}
+
+impl MapPlatform {
+  pub fn to_platform(&self) -> Result<MovingPlatform, TwinstickError> {
+    let platform = MovingPlatform::new(self.path.clone(), self.size.clone(), self.speed, self.model.clone())?.rotation(self.rotation.clone());
+    Ok(match self.hazard {
+      Some(damage) => platform.hazard(damage),
+      None => platform,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct EnemySpawner {
+  pub pos: Vector3,
+  // Seconds between spawns
+  pub interval: f32,
+  // Holds off while there are at least this many enemies in the game
+  pub max_enemies: usize,
+}
+
+// What goes everywhere the map doesn't list a section
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub enum MapLayout {
+  // Repeated in both directions, sections are given row by row, width to a row
+  Tiled {
+    width: u32,
+    depth: u32,
+    sections: Vec<MapSection>,
+  },
+  Dungeon(Dungeon),
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Map {
+  pub version: u32,
+  pub section_size: f64,
+  pub sections: Vec<MapSection>,
+  pub layout: MapLayout,
+  #[serde(default)]
+  pub static_objects: Vec<MapStaticObject>,
+  pub spawn_points: Vec<Vector3>,
+  #[serde(default)]
+  pub enemy_spawners: Vec<EnemySpawner>,
+  #[serde(default)]
+  pub interactives: Vec<MapInteractive>,
+  #[serde(default)]
+  pub platforms: Vec<MapPlatform>,
+}
+
+impl Map {
+  pub fn default_map() -> Map {
+    Map::parse(DEFAULT_MAP).expect("the default map is checked by the tests")
+  }
+  
+  pub fn parse(text: &str) -> Result<Map, TwinstickError> {
+    let map: Map = ron::de::from_str(text).map_err(|e| TwinstickError::InvalidMap(e.to_string()))?;
+    map.validate()?;
+    
+    Ok(map)
+  }
+  
+  pub fn load<P: AsRef<Path>>(path: P) -> Result<Map, TwinstickError> {
+    Map::parse(&fs::read_to_string(path)?)
+  }
+  
+  pub fn to_ron_string(&self) -> Result<String, TwinstickError> {
+    ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::new()).map_err(|e| TwinstickError::InvalidMap(e.to_string()))
+  }
+  
+  // Written alongside and renamed over the old map, so a server watching it never loads half of one
+  pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), TwinstickError> {
+    let path = path.as_ref();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, self.to_ron_string()?)?;
+    fs::rename(&tmp, path)?;
+    
+    Ok(())
+  }
+  
+  pub fn validate(&self) -> Result<(), TwinstickError> {
+    let invalid = |reason: String| Err(TwinstickError::InvalidMap(reason));
+    
+    if self.version != MAP_VERSION {
+      return Err(TwinstickError::IncompatibleMap(self.version));
+    }
+    if !(self.section_size.is_finite() && self.section_size > 0.0) {
+      return invalid(format!("section size {} must be above 0", self.section_size));
+    }
+    
+    // Anything on a floor reaching down past the kill plane would be killed just for standing there
+    let lowest = Section::floor_at(KILL_PLANE_Y) + 1;
+    let mut seen = HashSet::new();
+    for section in &self.sections {
+      if !seen.insert((section.x, section.level, section.z)) {
+        return invalid(format!("section ({}, {}, {}) is given more than once", section.x, section.level, section.z));
+      }
+      if section.level < lowest {
+        return invalid(format!("section ({}, {}, {}) is below the lowest floor {}", section.x, section.level, section.z, lowest));
+      }
+      if section.elevators.contains(&0) {
+        return invalid(format!("section ({}, {}, {}) has an elevator to its own floor", section.x, section.level, section.z));
+      }
+      if section.elevators.iter().any(|floors| section.level + *floors < lowest) {
+        return invalid(format!("section ({}, {}, {}) has an elevator below the lowest floor {}", section.x, section.level, section.z, lowest));
+      }
+    }
+    
+    match &self.layout {
+      MapLayout::Tiled { width, depth, sections } => {
+        if *width == 0 || *depth == 0 {
+          return invalid("layout must be at least 1 by 1".to_string());
+        }
+        if sections.len() != (width*depth) as usize {
+          return invalid(format!("layout is {} by {} but has {} sections", width, depth, sections.len()));
+        }
+        for (i, section) in sections.iter().enumerate() {
+          if SectionLayout::index(*width, *depth, section.x, section.z) != i {
+            return invalid(format!("layout section ({}, {}) is out of place", section.x, section.z));
+          }
+        }
+      },
+      MapLayout::Dungeon(dungeon) => {
+        if dungeon.chunk_size < 2 || dungeon.chunk_size > 64 {
+          return invalid(format!("dungeon chunk size {} must be from 2 to 64", dungeon.chunk_size));
+        }
+        if dungeon.floors == 0 {
+          return invalid("dungeon needs at least one floor".to_string());
+        }
+        if !(dungeon.difficulty_step.is_finite() && dungeon.difficulty_step > 0.0) {
+          return invalid(format!("dungeon difficulty step {} must be above 0", dungeon.difficulty_step));
+        }
+      },
+    }
+    
+    for object in &self.static_objects {
+      let numbers = [object.pos.x, object.pos.y, object.pos.z, object.size.x, object.size.y, object.size.z,
+                     object.rotation.x, object.rotation.y, object.rotation.z];
+      if numbers.iter().any(|n| !n.is_finite()) || object.size.x <= 0.0 || object.size.y <= 0.0 || object.size.z <= 0.0 {
+        return invalid(format!("static object {} at {:?} has a bad position, size or rotation", object.model, object.pos));
+      }
+      if !seen.contains(&self.section_containing(&object.pos)) {
+        return invalid(format!("static object {} at {:?} isn't inside any section", object.model, object.pos));
+      }
+    }
+    
+    for interactive in &self.interactives {
+      let mut numbers = vec!(interactive.pos.x, interactive.pos.y, interactive.pos.z, interactive.size.x, interactive.size.y, interactive.size.z,
+                             interactive.rotation.x, interactive.rotation.y, interactive.rotation.z);
+      if let Some((min, max)) = &interactive.room {
+        numbers.extend(&[min.x, min.y, min.z, max.x, max.y, max.z]);
+      }
+      if numbers.iter().any(|n| !n.is_finite()) || interactive.size.x <= 0.0 || interactive.size.y <= 0.0 || interactive.size.z <= 0.0 {
+        return invalid(format!("{:?} at {:?} has a bad position, size, rotation or room", interactive.kind, interactive.pos));
+      }
+      if interactive.life.is_some_and(|life| life <= 0) {
+        return invalid(format!("{:?} at {:?} has no life", interactive.kind, interactive.pos));
+      }
+      if interactive.kind == InteractiveKind::Door && interactive.channel.is_none() && interactive.room.is_none() {
+        return invalid(format!("door at {:?} has no channel or room to open it", interactive.pos));
+      }
+    }
+    
+    for platform in &self.platforms {
+      let start = platform.path.first().cloned().unwrap_or(Vector3::new_same(0.0));
+      if platform.path.len() < 2 {
+        return invalid(format!("platform at {:?} needs at least 2 waypoints", start));
+      }
+      let mut numbers = vec!(platform.size.x, platform.size.y, platform.size.z, platform.rotation.x, platform.rotation.y, platform.rotation.z);
+      for point in &platform.path {
+        numbers.extend(&[point.x, point.y, point.z]);
+      }
+      if numbers.iter().any(|n| !n.is_finite()) || platform.size.x <= 0.0 || platform.size.y <= 0.0 || platform.size.z <= 0.0 {
+        return invalid(format!("platform at {:?} has a bad path, size or rotation", start));
+      }
+      if !(platform.speed.is_finite() && platform.speed > 0.0) {
+        return invalid(format!("platform at {:?} has speed {}, it must be above 0", start, platform.speed));
+      }
+      if platform.hazard.is_some_and(|damage| damage <= 0) {
+        return invalid(format!("hazard at {:?} does no damage", start));
+      }
+    }
+    
+    if self.spawn_points.is_empty() {
+      return invalid("there must be at least one spawn point".to_string());
+    }
+    for point in self.spawn_points.iter().chain(self.enemy_spawners.iter().map(|s| &s.pos)) {
+      if !(point.x.is_finite() && point.y.is_finite() && point.z.is_finite()) {
+        return invalid(format!("spawn position {:?} isn't a number", point));
+      }
+    }
+    for spawner in &self.enemy_spawners {
+      if !(spawner.interval.is_finite() && spawner.interval > 0.0) {
+        return invalid(format!("enemy spawner at {:?} has an interval of {}", spawner.pos, spawner.interval));
+      }
+    }
+    
+    Ok(())
+  }
+  
+  // Sections are centred on their location
+  pub fn section_containing(&self, pos: &Vector3) -> (i32, i32, i32) {
+    ((pos.x/self.section_size).round() as i32, Section::floor_at(pos.y), (pos.z/self.section_size).round() as i32)
+  }
+  
+  // Extra static objects go in the section they sit in, so they are saved along with it
+  pub fn sections(&self) -> Vec<Section> {
+    let mut sections = self.sections.iter().map(|s| s.to_section(self.section_size)).collect::<Vec<Section>>();
+    for object in &self.static_objects {
+      let location = self.section_containing(&object.pos);
+      if let Some(section) = sections.iter_mut().find(|s| s.location() == location) {
+        section.add_object(object.to_object());
+      }
+    }
+    
+    sections
+  }
+  
+  pub fn interactives(&self) -> Vec<Box<dyn GenericObject>> {
+    self.interactives.iter().map(|i| Box::new(i.to_interactive()) as Box<dyn GenericObject>).collect()
+  }
+  
+  pub fn platforms(&self) -> Vec<Box<dyn GenericObject>> {
+    // validate refuses these, but maps put together in code never go through it
+    self.platforms.iter().filter_map(|p| match p.to_platform() {
+      Ok(platform) => Some(Box::new(platform) as Box<dyn GenericObject>),
+      Err(e) => {
+        log::warn!("skipping platform at {:?} error={}", p.path.first(), e);
+        None
+      },
+    }).collect()
+  }
+  
+  pub fn layout(&self) -> SectionLayout {
+    match &self.layout {
+      MapLayout::Tiled { width, depth, sections } => {
+        let sections = sections.iter().map(|s| s.to_section(self.section_size)).collect();
+        SectionLayout::new(*width, *depth, self.section_size, sections)
+      },
+      MapLayout::Dungeon(dungeon) => SectionLayout::dungeon(dungeon.clone(), self.section_size),
+    }
+  }
+  
+  pub fn section_at(&self, x: i32, level: i32, z: i32) -> Option<&MapSection> {
+    self.sections.iter().find(|s| (s.x, s.level, s.z) == (x, level, z))
+  }
+  
+  // Middle of a section on top of its floor
+  pub fn section_centre(&self, x: i32, level: i32, z: i32) -> Vector3 {
+    Vector3::new(x as f64*self.section_size, Section::floor_top(level), z as f64*self.section_size)
+  }
+  
+  // The editing functions below add sections as things are put in them and drop them again once empty
+  fn section_mut(&mut self, x: i32, level: i32, z: i32) -> &mut MapSection {
+    match self.sections.iter().position(|s| (s.x, s.level, s.z) == (x, level, z)) {
+      Some(i) => &mut self.sections[i],
+      None => {
+        self.sections.push(MapSection { x, z, level, floor: false, walls: Vec::new(), ramp: None, elevators: Vec::new() });
+        self.sections.last_mut().unwrap()
+      }
+    }
+  }
+  
+  fn drop_if_empty(&mut self, x: i32, level: i32, z: i32) {
+    let has_objects = self.static_objects.iter().any(|o| self.section_containing(&o.pos) == (x, level, z));
+    self.sections.retain(|s| {
+      (s.x, s.level, s.z) != (x, level, z) || s.floor || !s.walls.is_empty() || s.ramp.is_some() || !s.elevators.is_empty() || has_objects
+    });
+  }
+  
+  pub fn set_floor(&mut self, x: i32, level: i32, z: i32, floor: bool) {
+    self.section_mut(x, level, z).floor = floor;
+    self.drop_if_empty(x, level, z);
+  }
+  
+  pub fn set_wall(&mut self, x: i32, level: i32, z: i32, wall: Wall, present: bool) {
+    let section = self.section_mut(x, level, z);
+    section.walls.retain(|w| *w != wall);
+    if present {
+      section.walls.push(wall);
+    }
+    self.drop_if_empty(x, level, z);
+  }
+  
+  // Goes in whichever section it is in
+  pub fn add_static_object(&mut self, object: MapStaticObject) {
+    let (x, level, z) = self.section_containing(&object.pos);
+    self.section_mut(x, level, z);
+    self.static_objects.push(object);
+  }
+  
+  // How many there were
+  pub fn remove_static_objects(&mut self, x: i32, level: i32, z: i32) -> usize {
+    let objects = self.static_objects.drain(..).collect::<Vec<MapStaticObject>>();
+    let before = objects.len();
+    self.static_objects = objects.into_iter().filter(|o| self.section_containing(&o.pos) != (x, level, z)).collect();
+    self.drop_if_empty(x, level, z);
+    
+    before - self.static_objects.len()
+  }
+  
+  pub fn add_spawn_point(&mut self, x: i32, level: i32, z: i32) {
+    let mut pos = self.section_centre(x, level, z);
+    pos.y += SPAWN_HEIGHT;
+    self.spawn_points.push(pos);
+  }
+  
+  // A map needs a spawn point so the last one is always left, returns how many were removed
+  pub fn remove_spawn_points(&mut self, x: i32, level: i32, z: i32) -> usize {
+    let before = self.spawn_points.len();
+    let kept = self.spawn_points.iter().filter(|p| self.section_containing(p) != (x, level, z)).cloned().collect::<Vec<Vector3>>();
+    if kept.is_empty() {
+      return 0;
+    }
+    self.spawn_points = kept;
+    
+    before - self.spawn_points.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{World, SECTION_SIZE};
+  
+  // What World::new was written as before there were map files
+  fn hard_coded_sections(section_size: f64) -> Vec<Section> {
+    vec!(
+      Section::new( 1, -2, section_size).floor().front_wall().left_wall().right_wall(),
+      Section::new( 0, -2, section_size),
+      Section::new(-1, -2, section_size).floor().front_wall().left_wall().right_wall(),
+      Section::new( 2, -1, section_size).floor().right_wall().front_wall().back_wall(),
+      Section::new( 1, -1, section_size),
+      Section::new( 0, -1, section_size),
+      Section::new(-1, -1, section_size),
+      Section::new(-2, -1, section_size),
+      Section::new(-3, -1, section_size).floor().back_wall().left_wall(),
+      Section::new(-4, -1, section_size).floor(),
+      Section::new(-5, -1, section_size).floor().back_wall(),
+      Section::new( 1,  0, section_size),
+      Section::new( 0,  0, section_size).floor().left_wall().front_wall().back_wall(),
+      Section::new(-1,  0, section_size).floor().front_wall().back_wall(),
+      Section::new(-2,  0, section_size).floor().front_wall().back_wall(),
+      Section::new(-3,  0, section_size).floor(),
+      Section::new(-4,  0, section_size).floor(),
+      Section::new(-5,  0, section_size).floor().right_wall(),
+      Section::new( 2,  1, section_size).floor().right_wall().front_wall().back_wall(),
+      Section::new( 1,  1, section_size),
+      Section::new( 0,  1, section_size),
+      Section::new(-1,  1, section_size),
+      Section::new(-2,  1, section_size),
+      Section::new(-3,  1, section_size).floor().left_wall().front_wall(),
+      Section::new(-4,  1, section_size).floor(),
+      Section::new(-5,  1, section_size).floor().front_wall(),
+      Section::new( 1,  2, section_size).floor().back_wall().left_wall().right_wall(),
+      Section::new(-1,  2, section_size).floor().back_wall().left_wall().right_wall(),
+    )
+  }
+  
+  fn hard_coded_layout(section_size: f64) -> Vec<Section> {
+    vec!(
+      Section::new( 0, 0, section_size),
+      Section::new(-1, 0, section_size).floor().left_wall().right_wall(),
+      Section::new(-2, 0, section_size),
+      Section::new( 0, 1, section_size).floor().front_wall().back_wall(),
+      Section::new(-1, 1, section_size).floor(),
+      Section::new(-2, 1, section_size).floor().front_wall().back_wall(),
+      Section::new( 0, 2, section_size),
+      Section::new(-1, 2, section_size).floor().left_wall().right_wall(),
+      Section::new(-2, 2, section_size),
+    )
+  }
+  
+  fn tiles(map: &mut Map) -> &mut Vec<MapSection> {
+    match &mut map.layout {
+      MapLayout::Tiled { sections, .. } => sections,
+      MapLayout::Dungeon(_) => panic!("the default map is tiled"),
+    }
+  }
+  
+  fn expect_invalid(map: &Map, reason: &str) {
+    match map.validate() {
+      Err(TwinstickError::InvalidMap(message)) => assert!(message.contains(reason), "{} doesn't mention {}", message, reason),
+      other => panic!("expected {} to be refused, got {:?}", reason, other),
+    }
+  }
+  
+  #[test]
+  fn default_map_is_the_old_hard_coded_world() {
+    let map = Map::default_map();
+    assert_eq!(map.section_size, SECTION_SIZE);
+    assert_eq!(map.sections(), hard_coded_sections(SECTION_SIZE));
+    
+    let mut layout = map.layout();
+    for (i, section) in hard_coded_layout(SECTION_SIZE).into_iter().enumerate() {
+      let (x, level, z) = section.location();
+      assert_eq!(layout.get_section(x, level, z), section, "layout section {}", i);
+    }
+    
+    let mut sections = hard_coded_sections(SECTION_SIZE);
+    sections.sort_by_key(|s| s.location());
+    let world = World::from_map(&map);
+    assert_eq!(world.sections(), sections);
+    assert_eq!(world.objects().len(), 50);
+  }
+  
+  #[test]
+  fn every_shipped_map_loads() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../resources/maps");
+    let mut count = 0;
+    for entry in fs::read_dir(dir).unwrap() {
+      let path = entry.unwrap().path();
+      if let Err(e) = Map::load(&path) {
+        panic!("{} doesn't load: {}", path.display(), e);
+      }
+      count += 1;
+    }
+    assert!(count >= 2);
+  }
+  
+  #[test]
+  fn maps_round_trip_through_ron() {
+    let mut map = Map::default_map();
+    map.static_objects.push(MapStaticObject {
+      pos: Vector3::new(-40.0, 7.0, 3.0),
+      size: Vector3::new(2.0, 4.0, 2.0),
+      rotation: Vector3::new(0.0, 45.0, 0.0),
+      model: "pillar".to_string(),
+    });
+    map.interactives.push(MapInteractive {
+      kind: InteractiveKind::Door,
+      pos: Vector3::new(-20.0, 8.0, 0.0),
+      size: Vector3::new(1.0, 5.0, 8.0),
+      rotation: no_rotation(),
+      channel: Some(1),
+      room: Some((Vector3::new(-60.0, 0.0, -20.0), Vector3::new(-20.0, 10.0, 20.0))),
+      life: None,
+    });
+    map.platforms.push(MapPlatform {
+      path: vec!(Vector3::new(-40.0, 6.0, 0.0), Vector3::new(-40.0, 16.0, 0.0)),
+      size: Vector3::new(4.0, 1.0, 4.0),
+      speed: 3.0,
+      rotation: no_rotation(),
+      model: platform_model(),
+      hazard: Some(2),
+    });
+    
+    let text = map.to_ron_string().unwrap();
+    assert_eq!(Map::parse(&text).unwrap(), map);
+    
+    map.layout = MapLayout::Dungeon(Dungeon::new(42).chunk_size(6).difficulty_step(2.5));
+    assert_eq!(Map::parse(&map.to_ron_string().unwrap()).unwrap(), map);
+    
+    let path = std::env::temp_dir().join(format!("twinstick_map_test_{}.ron", std::process::id()));
+    map.write_to(&path).unwrap();
+    assert_eq!(Map::load(&path).unwrap(), map);
+    assert!(!path.with_extension("tmp").exists());
+    let _ = fs::remove_file(&path);
+  }
+  
+  #[test]
+  fn extra_static_objects_join_the_section_they_are_in() {
+    let mut map = Map::default_map();
+    map.static_objects.push(MapStaticObject {
+      pos: Vector3::new(-41.0, 7.0, 18.0),
+      size: Vector3::new_same(2.0),
+      rotation: no_rotation(),
+      model: "crate".to_string(),
+    });
+    
+    let world = World::from_map(&map);
+    let section = world.section_at(-1, 0, 0).unwrap();
+    assert_eq!(section.clone().static_objects().last().unwrap().model(), "crate");
+    assert_eq!(world.objects().len(), 51);
+  }
+  
+  #[test]
+  fn bad_maps_are_refused() {
+    let mut map = Map::default_map();
+    map.version = MAP_VERSION + 1;
+    match map.validate() {
+      Err(TwinstickError::IncompatibleMap(v)) => assert_eq!(v, MAP_VERSION + 1),
+      other => panic!("expected an incompatible map, got {:?}", other),
+    }
+    
+    let mut map = Map::default_map();
+    map.section_size = 0.0;
+    expect_invalid(&map, "section size");
+    
+    let mut map = Map::default_map();
+    map.sections.push(map.sections[3].clone());
+    expect_invalid(&map, "more than once");
+    
+    // Floor -5 still stands clear of the kill plane, floor -6 doesn't
+    let lowest = Section::floor_at(KILL_PLANE_Y) + 1;
+    assert_eq!(lowest, -5);
+    assert!(Section::floor_top(lowest) > KILL_PLANE_Y);
+    let mut map = Map::default_map();
+    map.sections.push(MapSection { x: 0, level: lowest, z: 0, floor: true, walls: Vec::new(), ramp: None, elevators: vec!(-1) });
+    expect_invalid(&map, "elevator below the lowest floor -5");
+    map.sections.last_mut().unwrap().elevators.clear();
+    assert!(map.validate().is_ok());
+    map.sections.last_mut().unwrap().level = lowest - 1;
+    expect_invalid(&map, "below the lowest floor -5");
+    
+    let mut map = Map::default_map();
+    tiles(&mut map).pop();
+    expect_invalid(&map, "has 8 sections");
+    
+    let mut map = Map::default_map();
+    tiles(&mut map).swap(0, 1);
+    expect_invalid(&map, "out of place");
+    
+    let mut map = Map::default_map();
+    map.layout = MapLayout::Dungeon(Dungeon::new(3).chunk_size(1));
+    expect_invalid(&map, "chunk size");
+    
+    let mut map = Map::default_map();
+    map.static_objects.push(MapStaticObject {
+      pos: Vector3::new(1000.0, 7.0, 0.0),
+      size: Vector3::new_same(1.0),
+      rotation: no_rotation(),
+      model: "crate".to_string(),
+    });
+    expect_invalid(&map, "isn't inside any section");
+    
+    let door = MapInteractive {
+      kind: InteractiveKind::Door,
+      pos: Vector3::new(-20.0, 8.0, 0.0),
+      size: Vector3::new(1.0, 5.0, 8.0),
+      rotation: no_rotation(),
+      channel: None,
+      room: None,
+      life: None,
+    };
+    let mut map = Map::default_map();
+    map.interactives.push(door.clone());
+    expect_invalid(&map, "no channel or room");
+    
+    let mut map = Map::default_map();
+    map.interactives.push(MapInteractive { kind: InteractiveKind::DestructibleWall, life: Some(0), ..door.clone() });
+    expect_invalid(&map, "no life");
+    
+    let mut map = Map::default_map();
+    map.interactives.push(MapInteractive { size: Vector3::new(1.0, 0.0, 8.0), channel: Some(1), ..door });
+    expect_invalid(&map, "bad position");
+    
+    let platform = MapPlatform {
+      path: vec!(Vector3::new(-40.0, 6.0, 0.0)),
+      size: Vector3::new(4.0, 1.0, 4.0),
+      speed: 3.0,
+      rotation: no_rotation(),
+      model: platform_model(),
+      hazard: None,
+    };
+    let mut map = Map::default_map();
+    map.platforms.push(platform.clone());
+    expect_invalid(&map, "at least 2 waypoints");
+    // Built anyway, without the platform
+    assert!(map.platforms().is_empty());
+    
+    let mut map = Map::default_map();
+    map.platforms.push(MapPlatform { path: vec!(Vector3::new(-40.0, 6.0, 0.0), Vector3::new(-40.0, 16.0, 0.0)), speed: 0.0, ..platform });
+    expect_invalid(&map, "speed 0");
+    
+    let mut map = Map::default_map();
+    map.spawn_points.clear();
+    expect_invalid(&map, "spawn point");
+    
+    let mut map = Map::default_map();
+    map.enemy_spawners[0].interval = 0.0;
+    expect_invalid(&map, "interval");
+    
+    match Map::parse("(version: 1, section_size: 40.0") {
+      Err(TwinstickError::InvalidMap(_)) => {},
+      other => panic!("expected a parse error, got {:?}", other),
+    }
+  }
+  
+  #[test]
+  fn editing_adds_and_drops_sections() {
+    let mut map = Map::default_map();
+    let sections = map.sections.len();
+    
+    map.set_floor(9, 1, 9, true);
+    map.set_wall(9, 1, 9, Wall::Left, true);
+    map.set_wall(9, 1, 9, Wall::Left, true);
+    assert_eq!(map.section_at(9, 1, 9).unwrap().walls, vec!(Wall::Left));
+    
+    let mut pos = map.section_centre(9, 1, 9);
+    pos.y += 1.0;
+    map.add_static_object(MapStaticObject { pos, size: Vector3::new_same(2.0), rotation: no_rotation(), model: "fridge".to_string() });
+    map.add_spawn_point(9, 1, 9);
+    assert_eq!(map.section_containing(map.spawn_points.last().unwrap()), (9, 1, 9));
+    map.validate().unwrap();
+    assert_eq!(Map::parse(&map.to_ron_string().unwrap()).unwrap(), map);
+    
+    // Kept while something is still in it
+    map.set_floor(9, 1, 9, false);
+    map.set_wall(9, 1, 9, Wall::Left, false);
+    assert!(map.section_at(9, 1, 9).is_some());
+    assert_eq!(map.remove_static_objects(9, 1, 9), 1);
+    assert!(map.section_at(9, 1, 9).is_none());
+    assert_eq!(map.sections.len(), sections);
+    
+    assert_eq!(map.remove_spawn_points(9, 1, 9), 1);
+    map.spawn_points.truncate(1);
+    let (x, level, z) = map.section_containing(&map.spawn_points[0]);
+    assert_eq!(map.remove_spawn_points(x, level, z), 0);
+    map.validate().unwrap();
+  }
+}
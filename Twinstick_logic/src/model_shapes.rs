@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::{GenericObject, Hitbox, Vector3, Vector4, TwinstickError};
+use crate::narrow_phase::{self, rotated_half_extents};
+
+const COLUMNS: [&str; 12] = ["Name", "X", "Y", "Z", "ScaleX", "ScaleY", "ScaleZ", "Quat_s", "Quat_x", "Quat_y", "Quat_z", "Type"];
+
+// One box out of a model's csv export, in the model's own space
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ShapePart {
+  pub name: String,
+  pub pos: Vector3,
+  pub size: Vector3,
+  pub rotation: Vector4,
+}
+
+// The boxes a model is made of. Objects are drawn with the model scaled to their
+// size, so the box around every part is stretched to fill the object's hitbox.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ModelShape {
+  parts: Vec<ShapePart>,
+  centre: Vector3,
+  size: Vector3,
+}
+
+impl ModelShape {
+  pub fn new(parts: Vec<ShapePart>) -> ModelShape {
+    let mut min = Vector3::new_same(f64::MAX);
+    let mut max = Vector3::new_same(f64::MIN);
+    for part in &parts {
+      let half = rotated_half_extents(&part.size, &part.rotation);
+      min = Vector3::new(min.x.min(part.pos.x - half.x), min.y.min(part.pos.y - half.y), min.z.min(part.pos.z - half.z));
+      max = Vector3::new(max.x.max(part.pos.x + half.x), max.y.max(part.pos.y + half.y), max.z.max(part.pos.z + half.z));
+    }
+    
+    ModelShape {
+      parts,
+      centre: min.add(&max).scale(0.5),
+      size: max.sub(&min),
+    }
+  }
+  
+  // Tab separated with a header row, as exported next to the models
+  pub fn parse(file: &str, text: &str) -> Result<ModelShape, TwinstickError> {
+    let invalid = |line: usize, reason: String| TwinstickError::InvalidModel(format!("{} line {}: {}", file, line, reason));
+    
+    let mut lines = text.lines().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    match lines.next() {
+      Some((_, header)) if header.trim().split('\t').eq(COLUMNS.iter().cloned()) => {},
+      Some((i, _)) => return Err(invalid(i+1, format!("expected the header {}", COLUMNS.join(" ")))),
+      None => return Err(invalid(1, "empty file".to_string())),
+    }
+    
+    let mut parts = Vec::new();
+    for (i, line) in lines {
+      let fields = line.trim_end_matches(['\r', '\n']).split('\t').collect::<Vec<&str>>();
+      if fields.len() != COLUMNS.len() {
+        return Err(invalid(i+1, format!("expected {} columns, found {}", COLUMNS.len(), fields.len())));
+      }
+      if fields[11] != "Cube" {
+        return Err(invalid(i+1, format!("unsupported shape type {}", fields[11])));
+      }
+      
+      let mut values = [0.0; 10];
+      for (j, value) in values.iter_mut().enumerate() {
+        *value = match fields[j+1].parse::<f64>() {
+          Ok(v) if v.is_finite() => v,
+          _ => return Err(invalid(i+1, format!("{} is not a number: {}", COLUMNS[j+1], fields[j+1]))),
+        };
+      }
+      
+      // Mirrored objects export negative scales, the box they take up is the same
+      let size = Vector3::new(values[3].abs(), values[4].abs(), values[5].abs());
+      let rotation = Vector4::new(values[6], values[7], values[8], values[9]);
+      if rotation.x == 0.0 && rotation.y == 0.0 && rotation.z == 0.0 && rotation.w == 0.0 {
+        return Err(invalid(i+1, "rotation is all zeros".to_string()));
+      }
+      
+      parts.push(ShapePart {
+        name: fields[0].to_string(),
+        pos: Vector3::new(values[0], values[1], values[2]),
+        size,
+        rotation: narrow_phase::normalised(&rotation),
+      });
+    }
+    
+    if parts.is_empty() {
+      return Err(invalid(1, "no shapes".to_string()));
+    }
+    
+    Ok(ModelShape::new(parts))
+  }
+  
+  pub fn parts(&self) -> &Vec<ShapePart> {
+    &self.parts
+  }
+  
+  // Size of the box around every part in model space
+  pub fn bounds(&self) -> &Vector3 {
+    &self.size
+  }
+  
+  // A single unrotated box filling the bounds collides the same as the hitbox on its own
+  pub fn is_single_box(&self) -> bool {
+    self.parts.len() == 1 && self.parts[0].rotation == narrow_phase::identity()
+  }
+  
+  // Lays the parts out inside a hitbox of the given size. Parts turned off the
+  // model's axes are stretched along their own axes, which is exact for quarter
+  // turns and close enough otherwise.
+  pub fn hitboxes(&self, hitbox: &Vector3) -> Vec<Hitbox> {
+    let ratio = |hitbox: f64, bounds: f64| if bounds > 0.0 { hitbox / bounds } else { 0.0 };
+    let stretch = Vector3::new(ratio(hitbox.x, self.size.x), ratio(hitbox.y, self.size.y), ratio(hitbox.z, self.size.z));
+    
+    self.parts.iter().map(|part| {
+      let offset = part.pos.sub(&self.centre).mul(&stretch);
+      let along = |axis: Vector3| narrow_phase::rotate(&part.rotation, &axis).mul(&stretch).magnitude();
+      let size = Vector3::new(part.size.x*along(Vector3::new(1.0, 0.0, 0.0)),
+                              part.size.y*along(Vector3::new(0.0, 1.0, 0.0)),
+                              part.size.z*along(Vector3::new(0.0, 0.0, 1.0)));
+      
+      Hitbox::new(offset, size).rotation(part.rotation.clone())
+    }).collect()
+  }
+}
+
+// Every model's shape by model name, the name of the csv without .csv
+#[derive(Clone, Default)]
+pub struct ModelShapes {
+  shapes: HashMap<String, ModelShape>,
+}
+
+impl ModelShapes {
+  pub fn new() -> ModelShapes {
+    ModelShapes {
+      shapes: HashMap::new(),
+    }
+  }
+  
+  pub fn load(dir: &Path) -> Result<ModelShapes, TwinstickError> {
+    let mut shapes = ModelShapes::new();
+    for entry in fs::read_dir(dir)? {
+      let path = entry?.path();
+      if path.extension().map(|e| e == "csv").unwrap_or(false) {
+        let text = fs::read_to_string(&path)?;
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+        shapes.insert(name, ModelShape::parse(&path.display().to_string(), &text)?);
+      }
+    }
+    
+    Ok(shapes)
+  }
+  
+  pub fn insert(&mut self, model: String, shape: ModelShape) {
+    self.shapes.insert(model, shape);
+  }
+  
+  pub fn get(&self, model: &str) -> Option<&ModelShape> {
+    self.shapes.get(model)
+  }
+  
+  pub fn len(&self) -> usize {
+    self.shapes.len()
+  }
+  
+  pub fn is_empty(&self) -> bool {
+    self.shapes.is_empty()
+  }
+  
+  // Objects whose model is more than one box collide with the boxes instead of
+  // their whole hitbox. Returns whether the object was given a shape.
+  pub fn attach(&self, object: &mut dyn GenericObject) -> bool {
+    match self.shapes.get(object.model()) {
+      Some(shape) if !shape.is_single_box() => {
+        object.mut_data().hitboxes = shape.hitboxes(&object.hitbox_size());
+        true
+      },
+      _ => false,
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::path::PathBuf;
+  use crate::{StaticObject, CollisionGrid, CollisionInfo, SECTION_SIZE};
+  
+  fn models() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../resources/models")
+  }
+  
+  fn shape(name: &str) -> ModelShape {
+    let path = models().join(format!("{}.csv", name));
+    ModelShape::parse(name, &fs::read_to_string(path).unwrap()).unwrap()
+  }
+  
+  fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-6
+  }
+  
+  fn boxes(info: CollisionInfo) -> Vec<(Vector3, Vector3, Vector4)> {
+    match info {
+      CollisionInfo::Compound(parts) => parts.into_iter().map(|(p, _)| match p {
+        CollisionInfo::AABB(pos, size, rotation) => (pos, size, rotation),
+        _ => panic!("model parts are boxes"),
+      }).collect(),
+      _ => panic!("expected a compound shape"),
+    }
+  }
+  
+  #[test]
+  fn every_shipped_model_loads() {
+    let shapes = ModelShapes::load(&models()).unwrap();
+    assert_eq!(shapes.len(), 17);
+    
+    assert_eq!(shapes.get("HouseL").unwrap().parts().len(), 16);
+    assert_eq!(shapes.get("45DeDeux").unwrap().parts().len(), 2);
+    assert_eq!(shapes.get("debug_cube").unwrap().parts().len(), 12);
+    assert!(shapes.get("unit_floor").unwrap().is_single_box());
+    assert!(!shapes.get("45DeFlat").unwrap().is_single_box());
+    assert!(shapes.get("house_one").is_none());
+  }
+  
+  #[test]
+  fn unusual_exports_are_cleaned_up() {
+    // Exported with a 180 where the quaternion's y should be, it still comes out unit length
+    let player = shape("playerone");
+    let q = &player.parts()[0].rotation;
+    assert!(close(q.x*q.x + q.y*q.y + q.z*q.z + q.w*q.w, 1.0));
+    
+    // Mirrored with negative scales
+    let kit = shape("housebuildakit");
+    assert!(kit.parts().iter().all(|p| p.size.x >= 0.0 && p.size.y >= 0.0 && p.size.z >= 0.0));
+    assert!(close(kit.parts()[0].size.x, 2.8421154022216797));
+  }
+  
+  #[test]
+  fn bad_files_are_rejected() {
+    let header = COLUMNS.join("\t");
+    assert!(ModelShape::parse("empty", "").is_err());
+    assert!(ModelShape::parse("header only", &header).is_err());
+    assert!(ModelShape::parse("no header", "Cube\t0\t0\t0\t1\t1\t1\t1\t0\t0\t0\tCube").is_err());
+    
+    let short = format!("{}\nCube\t0\t0\t0\t1\t1\t1\t1\t0\t0\tCube", header);
+    let text = format!("{}\nCube\t0\t0\tup\t1\t1\t1\t1\t0\t0\t0\tCube", header);
+    let sphere = format!("{}\nBall\t0\t0\t0\t1\t1\t1\t1\t0\t0\t0\tSphere", header);
+    let zero = format!("{}\nCube\t0\t0\t0\t1\t1\t1\t0\t0\t0\t0\tCube", header);
+    for bad in &[short, text, sphere, zero] {
+      match ModelShape::parse("bad", bad) {
+        Err(TwinstickError::InvalidModel(reason)) => assert!(reason.starts_with("bad line 2: "), "{}", reason),
+        other => panic!("expected an invalid model, got {:?}", other),
+      }
+    }
+    
+    let windows = format!("{}\r\nCube\t0\t0\t0\t1\t1\t1\t1\t0\t0\t0\tCube\r\n", header);
+    assert_eq!(ModelShape::parse("windows", &windows).unwrap().parts().len(), 1);
+  }
+  
+  #[test]
+  fn parts_fill_the_hitbox() {
+    // Twelve bars along the edges of a unit cube
+    let frame = shape("debug_cube");
+    assert!(close(frame.bounds().x, 1.0) && close(frame.bounds().y, 1.0) && close(frame.bounds().z, 1.0));
+    
+    let hitboxes = frame.hitboxes(&Vector3::new(4.0, 2.0, 2.0));
+    assert_eq!(hitboxes.len(), 12);
+    let (offset, size) = (&hitboxes[0].offset, &hitboxes[0].size);
+    assert!(close(offset.x, 1.8) && close(offset.y, 0.9) && close(offset.z, 0.0));
+    assert!(close(size.x, 0.4) && close(size.y, 0.2) && close(size.z, 2.0));
+    
+    // Placed in the world they move and turn with the object, a quarter turn swaps x and z
+    let mut object = StaticObject::new(Vector3::new(10.0, 0.0, 0.0), Vector3::new(4.0, 2.0, 2.0), "debug_cube".to_string());
+    object.mut_data().hitboxes = hitboxes.clone();
+    let (pos, _, _) = boxes(object.collision_data()).remove(0);
+    assert!(close(pos.x, 11.8) && close(pos.y, 0.9) && close(pos.z, 0.0));
+    
+    let object = object.rotation(Vector3::new(0.0, 90.0, 0.0));
+    let (pos, _, _) = boxes(object.collision_data()).remove(0);
+    assert!(close(pos.x, 10.0) && close(pos.z.abs(), 1.8));
+    
+    // Turned parts are stretched along their own axes, the flat ramp lies along z once turned
+    let flat = shape("45DeFlat");
+    let size = &flat.hitboxes(&Vector3::new(2.0, 1.0, 9.0))[0].size;
+    assert!(close(size.x, 9.0) && close(size.y, 1.0) && close(size.z, 2.0));
+  }
+  
+  #[test]
+  fn static_objects_collide_with_their_parts() {
+    let mut shapes = ModelShapes::new();
+    shapes.insert("debug_cube".to_string(), shape("debug_cube"));
+    shapes.insert("unit_floor".to_string(), shape("unit_floor"));
+    
+    let mut frame = StaticObject::new(Vector3::new_same(0.0), Vector3::new_same(10.0), "debug_cube".to_string());
+    let mut floor = StaticObject::new(Vector3::new_same(0.0), Vector3::new_same(10.0), "unit_floor".to_string());
+    assert!(shapes.attach(&mut frame));
+    assert!(!shapes.attach(&mut floor));
+    
+    // The middle of the frame is hollow, the middle of the floor isn't
+    let middle = CollisionInfo::AABB(Vector3::new_same(0.0), Vector3::new_same(1.0), narrow_phase::identity());
+    assert!(narrow_phase::contact(&middle, &frame.collision_data()).is_none());
+    assert!(narrow_phase::contact(&middle, &floor.collision_data()).is_some());
+    
+    let edge = CollisionInfo::AABB(Vector3::new(4.5, 4.5, 0.0), Vector3::new_same(1.0), narrow_phase::identity());
+    assert!(narrow_phase::contact(&edge, &frame.collision_data()).is_some());
+    
+    // The grid still knows where all of it is
+    let mut grid = CollisionGrid::new(SECTION_SIZE);
+    let index = grid.insert(&frame.collision_data());
+    assert_eq!(grid.query(&edge), vec!(index));
+  }
+}
@@ -0,0 +1,703 @@
+use crate::{CollisionInfo, HitRegion, Vector3, Vector4};
+
+// Describes how two shapes overlap. The normal is a unit vector pointing from
+// the first shape towards the second, moving the second shape by normal*depth
+// (or the first by -normal*depth) separates them. Point is where they touch,
+// on or inside the first shape. Region is the part of the first shape that was
+// touched and other_region the part of the second, Body unless they are compound.
+#[derive(PartialEq, Debug, Clone)]
+pub struct Contact {
+  pub normal: Vector3,
+  pub depth: f64,
+  pub point: Vector3,
+  pub region: HitRegion,
+  pub other_region: HitRegion,
+}
+
+impl Contact {
+  pub fn new(normal: Vector3, depth: f64, point: Vector3) -> Contact {
+    Contact {
+      normal,
+      depth,
+      point,
+      region: HitRegion::Body,
+      other_region: HitRegion::Body,
+    }
+  }
+  
+  // The same contact seen from the other shape
+  pub fn flipped(&self) -> Contact {
+    Contact {
+      normal: self.normal.scale(-1.0),
+      depth: self.depth,
+      point: self.point.clone(),
+      region: self.other_region,
+      other_region: self.region,
+    }
+  }
+  
+  // How far the second shape has to move to get out of the first
+  pub fn separation(&self) -> Vector3 {
+    self.normal.scale(self.depth)
+  }
+}
+
+// Used when two centres sit exactly on top of each other and there is no better guess
+fn up() -> Vector3 {
+  Vector3::new(0.0, 1.0, 0.0)
+}
+
+fn axis(i: usize, sign: f64) -> Vector3 {
+  match i {
+    0 => Vector3::new(sign, 0.0, 0.0),
+    1 => Vector3::new(0.0, sign, 0.0),
+    _ => Vector3::new(0.0, 0.0, sign),
+  }
+}
+
+fn components(v: &Vector3) -> [f64; 3] {
+  [v.x, v.y, v.z]
+}
+
+fn centre(sphere: &Vector4) -> Vector3 {
+  Vector3::new(sphere.x, sphere.y, sphere.z)
+}
+
+// Box rotations are quaternions stored as (s, x, y, z) in the x, y, z, w of a
+// Vector4, the order the model csv files use. (1, 0, 0, 0) is no rotation.
+pub fn identity() -> Vector4 {
+  Vector4::new(1.0, 0.0, 0.0, 0.0)
+}
+
+pub fn multiply(a: &Vector4, b: &Vector4) -> Vector4 {
+  Vector4::new(a.x*b.x - a.y*b.y - a.z*b.z - a.w*b.w,
+               a.x*b.y + a.y*b.x + a.z*b.w - a.w*b.z,
+               a.x*b.z - a.y*b.w + a.z*b.x + a.w*b.y,
+               a.x*b.w + a.y*b.z - a.z*b.y + a.w*b.x)
+}
+
+pub fn normalised(q: &Vector4) -> Vector4 {
+  let length = (q.x*q.x + q.y*q.y + q.z*q.z + q.w*q.w).sqrt();
+  if length > 0.0 {
+    Vector4::new(q.x/length, q.y/length, q.z/length, q.w/length)
+  } else {
+    identity()
+  }
+}
+
+// Euler angles in degrees as ObjectData stores them, applied x then y then z
+pub fn quaternion_from_euler(degrees: &Vector3) -> Vector4 {
+  let half = |d: f64| (d.to_radians()*0.5).sin_cos();
+  let (sx, cx) = half(degrees.x);
+  let (sy, cy) = half(degrees.y);
+  let (sz, cz) = half(degrees.z);
+  
+  let x = Vector4::new(cx, sx, 0.0, 0.0);
+  let y = Vector4::new(cy, 0.0, sy, 0.0);
+  let z = Vector4::new(cz, 0.0, 0.0, sz);
+  multiply(&z, &multiply(&y, &x))
+}
+
+pub fn rotate(q: &Vector4, v: &Vector3) -> Vector3 {
+  let q = normalised(q);
+  let u = Vector3::new(q.y, q.z, q.w);
+  let t = u.cross(v).scale(2.0);
+  v.add(&t.scale(q.x)).add(&u.cross(&t))
+}
+
+// Half size of the world aligned box a rotated box fits inside
+pub fn rotated_half_extents(size: &Vector3, rotation: &Vector4) -> Vector3 {
+  let obb = Obb::new(&Vector3::new_same(0.0), size, rotation);
+  Vector3::new(obb.radius_along(&axis(0, 1.0)), obb.radius_along(&axis(1, 1.0)), obb.radius_along(&axis(2, 1.0)))
+}
+
+struct Obb {
+  centre: Vector3,
+  half: [f64; 3],
+  axes: [Vector3; 3],
+}
+
+impl Obb {
+  fn new(pos: &Vector3, size: &Vector3, rotation: &Vector4) -> Obb {
+    Obb {
+      centre: pos.clone(),
+      half: components(&size.scale(0.5)),
+      axes: [rotate(rotation, &axis(0, 1.0)), rotate(rotation, &axis(1, 1.0)), rotate(rotation, &axis(2, 1.0))],
+    }
+  }
+  
+  fn to_local(&self, p: &Vector3) -> [f64; 3] {
+    let d = p.sub(&self.centre);
+    [d.dot(&self.axes[0]), d.dot(&self.axes[1]), d.dot(&self.axes[2])]
+  }
+  
+  fn to_world(&self, local: [f64; 3]) -> Vector3 {
+    self.centre.add(&self.axes[0].scale(local[0]))
+               .add(&self.axes[1].scale(local[1]))
+               .add(&self.axes[2].scale(local[2]))
+  }
+  
+  fn closest_point(&self, p: &Vector3) -> Vector3 {
+    let mut local = self.to_local(p);
+    for (value, half) in local.iter_mut().zip(self.half.iter()) {
+      *value = value.max(-half).min(*half);
+    }
+    self.to_world(local)
+  }
+  
+  // Face closest to a point inside the box, as (outward normal, distance to face)
+  fn nearest_face(&self, p: &Vector3) -> (Vector3, f64) {
+    let d = self.to_local(p);
+    
+    let mut best = 0;
+    for i in 1..3 {
+      if self.half[i] - d[i].abs() < self.half[best] - d[best].abs() {
+        best = i;
+      }
+    }
+    
+    let sign = if d[best] < 0.0 { -1.0 } else { 1.0 };
+    (self.axes[best].scale(sign), self.half[best] - d[best].abs())
+  }
+  
+  // Half the length of the box projected on to a unit axis
+  fn radius_along(&self, l: &Vector3) -> f64 {
+    (0..3).map(|i| self.half[i]*self.axes[i].dot(l).abs()).sum()
+  }
+}
+
+// Separating axis test over both boxes' face normals and the cross products of
+// their edges, the axis with the least overlap is the way out
+fn box_box(a: &Obb, b: &Obb) -> Option<Contact> {
+  let d = b.centre.sub(&a.centre);
+  
+  // y comes first so ties favour it and things resting on floors stay on them
+  let mut candidates = Vec::with_capacity(15);
+  for i in [1, 0, 2].iter() {
+    candidates.push(a.axes[*i].clone());
+  }
+  for i in [1, 0, 2].iter() {
+    candidates.push(b.axes[*i].clone());
+  }
+  for i in 0..3 {
+    for j in 0..3 {
+      let cross = a.axes[i].cross(&b.axes[j]);
+      let length = cross.magnitude();
+      // Parallel edges, already covered by the face normals
+      if length > 1e-9 {
+        candidates.push(cross.scale(1.0/length));
+      }
+    }
+  }
+  
+  let mut best: Option<(Vector3, f64)> = None;
+  for l in candidates {
+    let distance = d.dot(&l);
+    let overlap = a.radius_along(&l) + b.radius_along(&l) - distance.abs();
+    if overlap <= 0.0 {
+      return None;
+    }
+    
+    if best.as_ref().map(|(_, o)| overlap < *o).unwrap_or(true) {
+      let sign = if distance < 0.0 { -1.0 } else { 1.0 };
+      best = Some((l.scale(sign), overlap));
+    }
+  }
+  
+  let (normal, depth) = best?;
+  
+  // Centre of where b's bounds overlap a, in a's frame
+  let b_local = a.to_local(&b.centre);
+  let mut point = [0.0; 3];
+  for i in 0..3 {
+    let reach = b.radius_along(&a.axes[i]);
+    let lo = (b_local[i] - reach).max(-a.half[i]);
+    let hi = (b_local[i] + reach).min(a.half[i]);
+    point[i] = (lo + hi)*0.5;
+  }
+  
+  Some(Contact::new(normal, depth, a.to_world(point)))
+}
+
+fn box_sphere(obb: &Obb, sphere: &Vector4) -> Option<Contact> {
+  let c = centre(sphere);
+  let closest = obb.closest_point(&c);
+  let offset = c.sub(&closest);
+  let distance = offset.magnitude();
+  
+  if distance >= sphere.w {
+    return None;
+  }
+  
+  if distance > 0.0 {
+    Some(Contact::new(offset.scale(1.0/distance), sphere.w - distance, closest))
+  } else {
+    // Centre is inside the box, push out through the nearest face
+    let (normal, to_face) = obb.nearest_face(&c);
+    let point = c.add(&normal.scale(to_face));
+    Some(Contact::new(normal, sphere.w + to_face, point))
+  }
+}
+
+fn box_point(obb: &Obb, p: &Vector3) -> Option<Contact> {
+  let d = obb.to_local(p);
+  if d.iter().zip(obb.half.iter()).any(|(d, half)| d.abs() > *half) {
+    return None;
+  }
+  
+  let (normal, to_face) = obb.nearest_face(p);
+  Some(Contact::new(normal, to_face, p.clone()))
+}
+
+fn sphere_sphere(a: &Vector4, b: &Vector4) -> Option<Contact> {
+  let offset = centre(b).sub(&centre(a));
+  let distance = offset.magnitude();
+  
+  if distance >= a.w + b.w {
+    return None;
+  }
+  
+  let normal = if distance > 0.0 { offset.scale(1.0/distance) } else { up() };
+  let depth = a.w + b.w - distance;
+  // Middle of the overlapping section between the two centres
+  let point = centre(a).add(&normal.scale(a.w - depth*0.5));
+  Some(Contact::new(normal, depth, point))
+}
+
+fn sphere_point(sphere: &Vector4, p: &Vector3) -> Option<Contact> {
+  let offset = p.sub(&centre(sphere));
+  let distance = offset.magnitude();
+  
+  if distance > sphere.w {
+    return None;
+  }
+  
+  let normal = if distance > 0.0 { offset.scale(1.0/distance) } else { up() };
+  Some(Contact::new(normal, sphere.w - distance, p.clone()))
+}
+
+fn point_point(a: &Vector3, b: &Vector3) -> Option<Contact> {
+  if a == b {
+    Some(Contact::new(up(), 0.0, a.clone()))
+  } else {
+    None
+  }
+}
+
+fn sweep_box(start: &Vector3, end: &Vector3, obb: &Obb) -> Option<(f64, Contact)> {
+  let p = obb.to_local(start);
+  let q = obb.to_local(end);
+  
+  // Slab test, the path is inside the box between the latest entry and earliest exit
+  let mut enter = 0.0;
+  let mut exit = 1.0;
+  let mut normal = None;
+  for i in 0..3 {
+    let d = q[i] - p[i];
+    if d.abs() < 1e-12 {
+      if p[i].abs() >= obb.half[i] {
+        return None;
+      }
+      continue;
+    }
+    
+    let near_side = if d > 0.0 { -1.0 } else { 1.0 };
+    let t_near = (near_side*obb.half[i] - p[i]) / d;
+    let t_far = (-near_side*obb.half[i] - p[i]) / d;
+    if t_near > enter {
+      enter = t_near;
+      normal = Some(obb.axes[i].scale(near_side));
+    }
+    if t_far < exit {
+      exit = t_far;
+    }
+    if enter >= exit {
+      return None;
+    }
+  }
+  
+  match normal {
+    Some(normal) => {
+      let point = start.add(&end.sub(start).scale(enter));
+      Some((enter, Contact::new(normal, 0.0, point)))
+    },
+    None => {
+      // Started inside
+      let (normal, to_face) = obb.nearest_face(start);
+      Some((0.0, Contact::new(normal, to_face, start.clone())))
+    },
+  }
+}
+
+fn sweep_sphere(start: &Vector3, end: &Vector3, c: &Vector3, radius: f64) -> Option<(f64, Contact)> {
+  let d = end.sub(start);
+  let m = start.sub(c);
+  
+  let inside = m.dot(&m) - radius*radius;
+  if inside <= 0.0 {
+    let distance = m.magnitude();
+    let normal = if distance > 0.0 { m.scale(1.0/distance) } else { up() };
+    return Some((0.0, Contact::new(normal, radius - distance, start.clone())));
+  }
+  
+  let a = d.dot(&d);
+  let b = m.dot(&d);
+  let discriminant = b*b - a*inside;
+  if a == 0.0 || discriminant <= 0.0 {
+    return None;
+  }
+  
+  let t = (-b - discriminant.sqrt()) / a;
+  if !(0.0..=1.0).contains(&t) {
+    return None;
+  }
+  
+  let point = start.add(&d.scale(t));
+  Some((t, Contact::new(point.sub(c).scale(1.0/radius), 0.0, point)))
+}
+
+// Moves a box with the given half size from start to end and finds the first
+// time (0 to 1 along the path) it touches the shape. The contact is seen from the
+// shape, its normal points back out of the surface and its point is where the
+// moving box's centre is at that moment. Rotated shapes are padded along their own
+// axes, which is slightly generous at their corners.
+pub fn sweep(start: &Vector3, end: &Vector3, half: &Vector3, shape: &CollisionInfo) -> Option<(f64, Contact)> {
+  match shape {
+    CollisionInfo::AABB(pos, size, rotation) => {
+      sweep_box(start, end, &Obb::new(pos, &size.add(&half.scale(2.0)), rotation))
+    },
+    CollisionInfo::Sphere(sphere) => {
+      sweep_sphere(start, end, &centre(sphere), sphere.w + half.x.max(half.y).max(half.z))
+    },
+    CollisionInfo::Point(p) => {
+      sweep_box(start, end, &Obb::new(p, &half.scale(2.0), &identity()))
+    },
+    CollisionInfo::Compound(parts) => {
+      parts.iter().filter_map(|(part, region)| sweep(start, end, half, part).map(|(t, mut c)| { c.region = *region; (t, c) }))
+                  .fold(None, |first: Option<(f64, Contact)>, hit| match first {
+                    Some(first) if first.0 <= hit.0 => Some(first),
+                    _ => Some(hit),
+                  })
+    },
+  }
+}
+
+fn deepest(contacts: impl Iterator<Item = Contact>) -> Option<Contact> {
+  contacts.fold(None, |deepest: Option<Contact>, c| match deepest {
+    Some(deepest) if deepest.depth >= c.depth => Some(deepest),
+    _ => Some(c),
+  })
+}
+
+// Every shape pair goes through here, None when they don't overlap
+pub fn contact(a: &CollisionInfo, b: &CollisionInfo) -> Option<Contact> {
+  match (a, b) {
+    // Only the deepest part is pushed out of, the rest follow over the next ticks
+    (CollisionInfo::Compound(parts), b) => {
+      deepest(parts.iter().filter_map(|(part, region)| contact(part, b).map(|mut c| { c.region = *region; c })))
+    },
+    (a, CollisionInfo::Compound(parts)) => {
+      deepest(parts.iter().filter_map(|(part, region)| contact(a, part).map(|mut c| { c.other_region = *region; c })))
+    },
+    (CollisionInfo::AABB(a_pos, a_size, a_rot), CollisionInfo::AABB(b_pos, b_size, b_rot)) => {
+      box_box(&Obb::new(a_pos, a_size, a_rot), &Obb::new(b_pos, b_size, b_rot))
+    },
+    (CollisionInfo::AABB(pos, size, rot), CollisionInfo::Sphere(sphere)) => box_sphere(&Obb::new(pos, size, rot), sphere),
+    (CollisionInfo::AABB(pos, size, rot), CollisionInfo::Point(p)) => box_point(&Obb::new(pos, size, rot), p),
+    (CollisionInfo::Sphere(sphere), CollisionInfo::AABB(pos, size, rot)) => box_sphere(&Obb::new(pos, size, rot), sphere).map(|c| c.flipped()),
+    (CollisionInfo::Sphere(a), CollisionInfo::Sphere(b)) => sphere_sphere(a, b),
+    (CollisionInfo::Sphere(sphere), CollisionInfo::Point(p)) => sphere_point(sphere, p),
+    (CollisionInfo::Point(p), CollisionInfo::AABB(pos, size, rot)) => box_point(&Obb::new(pos, size, rot), p).map(|c| c.flipped()),
+    (CollisionInfo::Point(p), CollisionInfo::Sphere(sphere)) => sphere_point(sphere, p).map(|c| c.flipped()),
+    (CollisionInfo::Point(a), CollisionInfo::Point(b)) => point_point(a, b),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  const EPSILON: f64 = 1e-9;
+  
+  fn aabb(x: f64, y: f64, z: f64, sx: f64, sy: f64, sz: f64) -> CollisionInfo {
+    CollisionInfo::AABB(Vector3::new(x, y, z), Vector3::new(sx, sy, sz), Vector4::new(1.0, 0.0, 0.0, 0.0))
+  }
+  
+  fn sphere(x: f64, y: f64, z: f64, r: f64) -> CollisionInfo {
+    CollisionInfo::Sphere(Vector4::new(x, y, z, r))
+  }
+  
+  fn point(x: f64, y: f64, z: f64) -> CollisionInfo {
+    CollisionInfo::Point(Vector3::new(x, y, z))
+  }
+  
+  fn assert_vec(actual: &Vector3, x: f64, y: f64, z: f64) {
+    assert!((actual.x-x).abs() < EPSILON && (actual.y-y).abs() < EPSILON && (actual.z-z).abs() < EPSILON,
+            "expected ({}, {}, {}) got {:?}", x, y, z, actual);
+  }
+  
+  fn assert_contact(c: Option<Contact>, normal: (f64, f64, f64), depth: f64) -> Contact {
+    let c = c.expect("expected a contact");
+    assert_vec(&c.normal, normal.0, normal.1, normal.2);
+    assert!((c.depth-depth).abs() < EPSILON, "expected depth {} got {}", depth, c.depth);
+    c
+  }
+  
+  // Swapping the shapes must give the same contact with the normal reversed
+  fn assert_symmetric(a: &CollisionInfo, b: &CollisionInfo) {
+    match (contact(a, b), contact(b, a)) {
+      (Some(ab), Some(ba)) => {
+        assert_vec(&ab.normal, -ba.normal.x, -ba.normal.y, -ba.normal.z);
+        assert!((ab.depth-ba.depth).abs() < EPSILON);
+      },
+      (None, None) => {},
+      (ab, ba) => panic!("asymmetric result {:?} {:?}", ab, ba),
+    }
+  }
+  
+  #[test]
+  fn aabb_aabb() {
+    // Resting on top, y has the least overlap
+    let floor = aabb(0.0, 5.0, 0.0, 40.0, 1.0, 40.0);
+    let c = assert_contact(contact(&floor, &aabb(3.0, 6.25, -2.0, 1.0, 2.0, 1.0)), (0.0, 1.0, 0.0), 0.25);
+    assert_vec(&c.point, 3.0, 5.375, -2.0);
+    
+    // Walking into a wall from either side
+    let wall = aabb(0.0, 7.5, 0.0, 1.0, 5.0, 38.0);
+    assert_contact(contact(&wall, &aabb(0.8, 7.5, 0.0, 1.0, 3.5, 1.0)), (1.0, 0.0, 0.0), 0.2);
+    assert_contact(contact(&wall, &aabb(-0.9, 7.5, 3.0, 1.0, 3.5, 1.0)), (-1.0, 0.0, 0.0), 0.1);
+    assert_contact(contact(&aabb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0), &aabb(0.0, 0.0, -1.5, 2.0, 2.0, 2.0)), (0.0, 0.0, -1.0), 0.5);
+    
+    // Under a ceiling
+    assert_contact(contact(&floor, &aabb(0.0, 4.0, 0.0, 1.0, 1.5, 1.0)), (0.0, -1.0, 0.0), 0.25);
+    
+    // Identical boxes still separate, upwards
+    assert_contact(contact(&aabb(1.0, 1.0, 1.0, 2.0, 2.0, 2.0), &aabb(1.0, 1.0, 1.0, 2.0, 2.0, 2.0)), (0.0, 1.0, 0.0), 2.0);
+    
+    // Touching faces and gaps are not contacts
+    assert_eq!(contact(&aabb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0), &aabb(2.0, 0.0, 0.0, 2.0, 2.0, 2.0)), None);
+    assert_eq!(contact(&floor, &aabb(0.0, 7.0, 0.0, 1.0, 2.0, 1.0)), None);
+    assert_eq!(contact(&floor, &aabb(25.0, 5.0, 0.0, 1.0, 1.0, 1.0)), None);
+    
+    assert_symmetric(&floor, &aabb(3.0, 6.25, -2.0, 1.0, 2.0, 1.0));
+    assert_symmetric(&wall, &aabb(0.8, 7.5, 0.0, 1.0, 3.5, 1.0));
+  }
+  
+  #[test]
+  fn aabb_sphere() {
+    let cube = aabb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0);
+    
+    // Face
+    let c = assert_contact(contact(&cube, &sphere(1.5, 0.0, 0.0, 1.0)), (1.0, 0.0, 0.0), 0.5);
+    assert_vec(&c.point, 1.0, 0.0, 0.0);
+    assert_contact(contact(&cube, &sphere(0.0, -1.9, 0.0, 1.0)), (0.0, -1.0, 0.0), 0.1);
+    
+    // Corner
+    let r = 0.5_f64.sqrt();
+    let c = assert_contact(contact(&cube, &sphere(1.5, 1.5, 0.0, 1.0)), (r, r, 0.0), 1.0 - 0.5_f64.sqrt());
+    assert_vec(&c.point, 1.0, 1.0, 0.0);
+    
+    // Centre inside the box goes out the nearest face
+    assert_contact(contact(&cube, &sphere(0.0, 0.0, 0.75, 0.5)), (0.0, 0.0, 1.0), 0.75);
+    
+    // Misses, including the corner the bounding boxes would say is a hit
+    assert_eq!(contact(&cube, &sphere(2.0, 0.0, 0.0, 1.0)), None);
+    assert_eq!(contact(&cube, &sphere(1.8, 1.8, 0.0, 1.0)), None);
+    
+    assert_symmetric(&cube, &sphere(1.5, 1.5, 0.0, 1.0));
+    assert_symmetric(&cube, &sphere(0.0, 0.0, 0.75, 0.5));
+    assert_symmetric(&cube, &sphere(2.0, 0.0, 0.0, 1.0));
+  }
+  
+  #[test]
+  fn aabb_point() {
+    let cube = aabb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0);
+    
+    let c = assert_contact(contact(&cube, &point(0.25, 0.9, 0.0)), (0.0, 1.0, 0.0), 0.1);
+    assert_vec(&c.point, 0.25, 0.9, 0.0);
+    assert_contact(contact(&cube, &point(-0.8, 0.0, 0.1)), (-1.0, 0.0, 0.0), 0.2);
+    assert_contact(contact(&cube, &point(0.0, 0.0, -0.95)), (0.0, 0.0, -1.0), 0.05);
+    
+    // On the surface counts, the same as the old is_point_inside_AABB
+    assert_contact(contact(&cube, &point(1.0, 0.0, 0.0)), (1.0, 0.0, 0.0), 0.0);
+    assert_eq!(contact(&cube, &point(1.01, 0.0, 0.0)), None);
+    
+    assert_symmetric(&cube, &point(0.25, 0.9, 0.0));
+    assert_symmetric(&cube, &point(3.0, 0.0, 0.0));
+  }
+  
+  #[test]
+  fn sphere_sphere_contacts() {
+    let c = assert_contact(contact(&sphere(0.0, 0.0, 0.0, 1.0), &sphere(0.0, 0.0, 1.5, 1.0)), (0.0, 0.0, 1.0), 0.5);
+    assert_vec(&c.point, 0.0, 0.0, 0.75);
+    assert_contact(contact(&sphere(0.0, 0.0, 0.0, 2.0), &sphere(-1.0, 0.0, 0.0, 0.5)), (-1.0, 0.0, 0.0), 1.5);
+    assert_contact(contact(&sphere(1.0, 1.0, 1.0, 1.0), &sphere(1.0, 1.0, 1.0, 1.0)), (0.0, 1.0, 0.0), 2.0);
+    
+    assert_eq!(contact(&sphere(0.0, 0.0, 0.0, 1.0), &sphere(2.0, 0.0, 0.0, 1.0)), None);
+    
+    assert_symmetric(&sphere(0.0, 0.0, 0.0, 1.0), &sphere(0.0, 0.0, 1.5, 1.0));
+    assert_symmetric(&sphere(0.0, 0.0, 0.0, 2.0), &sphere(-1.0, 0.0, 0.0, 0.5));
+  }
+  
+  #[test]
+  fn sphere_point_contacts() {
+    let c = assert_contact(contact(&sphere(0.0, 0.0, 0.0, 2.0), &point(0.0, 1.5, 0.0)), (0.0, 1.0, 0.0), 0.5);
+    assert_vec(&c.point, 0.0, 1.5, 0.0);
+    assert_contact(contact(&sphere(1.0, 0.0, 0.0, 2.0), &point(1.0, 0.0, 0.0)), (0.0, 1.0, 0.0), 2.0);
+    
+    // On the surface counts, the same as the old is_point_inside_sphere
+    assert_contact(contact(&sphere(0.0, 0.0, 0.0, 2.0), &point(-2.0, 0.0, 0.0)), (-1.0, 0.0, 0.0), 0.0);
+    assert_eq!(contact(&sphere(0.0, 0.0, 0.0, 2.0), &point(0.0, 0.0, 2.5)), None);
+    
+    assert_symmetric(&sphere(0.0, 0.0, 0.0, 2.0), &point(0.0, 1.5, 0.0));
+    assert_symmetric(&sphere(0.0, 0.0, 0.0, 2.0), &point(0.0, 0.0, 2.5));
+  }
+  
+  fn obb(x: f64, y: f64, z: f64, sx: f64, sy: f64, sz: f64, degrees: (f64, f64, f64)) -> CollisionInfo {
+    CollisionInfo::AABB(Vector3::new(x, y, z), Vector3::new(sx, sy, sz),
+                        quaternion_from_euler(&Vector3::new(degrees.0, degrees.1, degrees.2)))
+  }
+  
+  #[test]
+  fn euler_rotations() {
+    let r = 0.5_f64.sqrt();
+    
+    assert_eq!(quaternion_from_euler(&Vector3::new_same(0.0)), identity());
+    assert_vec(&rotate(&quaternion_from_euler(&Vector3::new(0.0, 90.0, 0.0)), &Vector3::new(1.0, 0.0, 0.0)), 0.0, 0.0, -1.0);
+    assert_vec(&rotate(&quaternion_from_euler(&Vector3::new(0.0, 0.0, 45.0)), &Vector3::new(1.0, 0.0, 0.0)), r, r, 0.0);
+    // x is applied before y
+    assert_vec(&rotate(&quaternion_from_euler(&Vector3::new(90.0, 90.0, 0.0)), &Vector3::new(0.0, 1.0, 0.0)), 1.0, 0.0, 0.0);
+    
+    let half = rotated_half_extents(&Vector3::new(2.0, 2.0, 2.0), &quaternion_from_euler(&Vector3::new(0.0, 45.0, 0.0)));
+    assert_vec(&half, 2.0*r, 1.0, 2.0*r);
+  }
+  
+  #[test]
+  fn obb_obb() {
+    let r = 0.5_f64.sqrt();
+    let diamond = obb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0, (0.0, 45.0, 0.0));
+    
+    // Its corner reaches root 2 along x, an upright box's bounds would stop at 1
+    assert_contact(contact(&diamond, &aabb(1.9, 0.0, 0.0, 1.0, 1.0, 1.0)), (1.0, 0.0, 0.0), 2.0_f64.sqrt() + 0.5 - 1.9);
+    assert_eq!(contact(&diamond, &aabb(2.0, 0.0, 1.2, 1.0, 1.0, 1.0)), None);
+    
+    // Face to face the rotated faces are the way out
+    assert_contact(contact(&diamond, &obb(1.2, 0.0, -1.2, 2.0, 2.0, 2.0, (0.0, 45.0, 0.0))), (r, 0.0, -r), 2.0 - 2.4*r);
+    
+    // Top face of a ramp tipped 30 degrees about z
+    let ramp = obb(0.0, 0.0, 0.0, 10.0, 1.0, 4.0, (0.0, 0.0, 30.0));
+    let (s, c) = 30.0_f64.to_radians().sin_cos();
+    let hit = assert_contact(contact(&ramp, &sphere(-s*0.9, c*0.9, 0.0, 0.5)), (-s, c, 0.0), 0.1);
+    assert_vec(&hit.point, -s*0.5, c*0.5, 0.0);
+    
+    // Identity rotations give the same answers as before
+    assert_eq!(contact(&obb(0.0, 5.0, 0.0, 40.0, 1.0, 40.0, (0.0, 0.0, 0.0)), &aabb(3.0, 6.25, -2.0, 1.0, 2.0, 1.0)),
+               contact(&aabb(0.0, 5.0, 0.0, 40.0, 1.0, 40.0), &aabb(3.0, 6.25, -2.0, 1.0, 2.0, 1.0)));
+    
+    assert_symmetric(&diamond, &aabb(1.9, 0.0, 0.0, 1.0, 1.0, 1.0));
+    assert_symmetric(&ramp, &obb(2.0, 1.0, 0.5, 1.0, 3.5, 1.0, (10.0, 20.0, 0.0)));
+  }
+  
+  #[test]
+  fn obb_sphere_and_point() {
+    let r = 0.5_f64.sqrt();
+    let diamond = obb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0, (0.0, 45.0, 0.0));
+    
+    // Nearest thing is the corner on the x axis
+    let c = assert_contact(contact(&diamond, &sphere(1.9, 0.0, 0.0, 0.5)), (1.0, 0.0, 0.0), 2.0_f64.sqrt() + 0.5 - 1.9);
+    assert_vec(&c.point, 2.0_f64.sqrt(), 0.0, 0.0);
+    assert_eq!(contact(&diamond, &sphere(1.0, 0.0, 1.0, 0.3)), None);
+    
+    // Points near the corner are inside, points in the upright box's corner are not
+    assert_contact(contact(&diamond, &point(1.3, 0.0, -0.1)), (r, 0.0, -r), 1.0 - 1.4*r);
+    assert_eq!(contact(&diamond, &point(0.9, 0.0, 0.9)), None);
+    assert_contact(contact(&point(1.3, 0.0, -0.1), &diamond), (-r, 0.0, r), 1.0 - 1.4*r);
+    
+    assert_symmetric(&diamond, &sphere(1.9, 0.0, 0.0, 0.5));
+    assert_symmetric(&diamond, &point(0.0, 0.5, 1.0));
+  }
+  
+  #[test]
+  fn sweeps() {
+    let wall = aabb(10.0, 0.0, 0.0, 1.0, 4.0, 4.0);
+    let start = Vector3::new(0.0, 0.0, 0.0);
+    
+    // Straight through, the point stops half a bullet short of the face at 9.5
+    let (t, c) = sweep(&start, &Vector3::new(20.0, 0.0, 0.0), &Vector3::new_same(0.5), &wall).unwrap();
+    assert!((t - 0.45).abs() < EPSILON);
+    assert_vec(&c.normal, -1.0, 0.0, 0.0);
+    assert_vec(&c.point, 9.0, 0.0, 0.0);
+    
+    // Short of it, past its edge, and already behind it
+    assert_eq!(sweep(&start, &Vector3::new(8.9, 0.0, 0.0), &Vector3::new_same(0.5), &wall), None);
+    assert_eq!(sweep(&Vector3::new(0.0, 0.0, 3.0), &Vector3::new(20.0, 0.0, 3.0), &Vector3::new_same(0.5), &wall), None);
+    assert_eq!(sweep(&Vector3::new(12.0, 0.0, 0.0), &Vector3::new(20.0, 0.0, 0.0), &Vector3::new_same(0.5), &wall), None);
+    
+    // A zero size ray still finds it, diagonally through the top face
+    let (t, c) = sweep(&Vector3::new(9.0, 5.0, 0.0), &Vector3::new(10.5, 1.0, 0.0), &Vector3::new_same(0.0), &wall).unwrap();
+    assert!((t - 0.75).abs() < EPSILON);
+    assert_vec(&c.normal, 0.0, 1.0, 0.0);
+    
+    // Starting inside is an immediate hit
+    let (t, c) = sweep(&Vector3::new(10.3, 0.0, 0.0), &Vector3::new(20.0, 0.0, 0.0), &Vector3::new_same(0.0), &wall).unwrap();
+    assert_eq!(t, 0.0);
+    assert_vec(&c.normal, 1.0, 0.0, 0.0);
+    
+    // Rotated boxes are swept in their own frame
+    let r = 0.5_f64.sqrt();
+    let diamond = obb(10.0, 0.0, 0.0, 2.0, 2.0, 2.0, (0.0, 45.0, 0.0));
+    let (t, c) = sweep(&start, &Vector3::new(20.0, 0.0, 0.0), &Vector3::new_same(0.0), &diamond).unwrap();
+    assert!((t - (10.0 - 2.0_f64.sqrt())/20.0).abs() < EPSILON);
+    assert!(c.normal.x < 0.0 && (c.normal.x.abs() - r).abs() < EPSILON);
+    
+    // Spheres and points
+    let (t, c) = sweep(&start, &Vector3::new(20.0, 0.0, 0.0), &Vector3::new_same(0.5), &sphere(10.0, 0.0, 0.0, 1.0)).unwrap();
+    assert!((t - 0.425).abs() < EPSILON);
+    assert_vec(&c.normal, -1.0, 0.0, 0.0);
+    assert_eq!(sweep(&start, &Vector3::new(20.0, 0.0, 0.0), &Vector3::new_same(0.5), &sphere(10.0, 2.0, 0.0, 1.0)), None);
+    let (t, _) = sweep(&start, &Vector3::new(20.0, 0.0, 0.0), &Vector3::new_same(0.5), &point(10.0, 0.2, 0.0)).unwrap();
+    assert!((t - 0.475).abs() < EPSILON);
+  }
+  
+  #[test]
+  fn point_point_contacts() {
+    let c = assert_contact(contact(&point(1.0, 2.0, 3.0), &point(1.0, 2.0, 3.0)), (0.0, 1.0, 0.0), 0.0);
+    assert_vec(&c.point, 1.0, 2.0, 3.0);
+    assert_eq!(contact(&point(1.0, 2.0, 3.0), &point(1.0, 2.0, 3.1)), None);
+  }
+  
+  #[test]
+  fn flipped_contacts_have_reversed_normals() {
+    let c = assert_contact(contact(&point(0.0, 0.9, 0.0), &aabb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0)), (0.0, -1.0, 0.0), 0.1);
+    assert_vec(&c.point, 0.0, 0.9, 0.0);
+    assert_contact(contact(&sphere(1.5, 0.0, 0.0, 1.0), &aabb(0.0, 0.0, 0.0, 2.0, 2.0, 2.0)), (-1.0, 0.0, 0.0), 0.5);
+    assert_contact(contact(&point(0.0, 1.5, 0.0), &sphere(0.0, 0.0, 0.0, 2.0)), (0.0, -1.0, 0.0), 0.5);
+    assert_vec(&c.flipped().flipped().normal, 0.0, -1.0, 0.0);
+  }
+  
+  #[test]
+  fn compound_shapes_report_the_part_that_was_hit() {
+    // A body with a head on top
+    let person = CollisionInfo::Compound(vec!(
+      (aabb(0.0, 0.0, 0.0, 1.0, 2.0, 1.0), HitRegion::Body),
+      (aabb(0.0, 1.5, 0.0, 0.6, 1.0, 0.6), HitRegion::Head),
+    ));
+    
+    let c = assert_contact(contact(&sphere(0.0, 1.5, 0.6, 0.5), &person), (0.0, 0.0, -1.0), 0.2);
+    assert_eq!((c.region, c.other_region), (HitRegion::Body, HitRegion::Head));
+    assert_eq!(c.flipped().region, HitRegion::Head);
+    
+    // Both parts touched, the deepest wins
+    let c = contact(&person, &aabb(0.0, 0.9, 0.0, 2.0, 0.4, 2.0)).unwrap();
+    assert_eq!(c.region, HitRegion::Body);
+    assert_eq!(contact(&person, &point(0.0, 0.0, 0.9)), None);
+    
+    // Above the shoulders only the head is in the way
+    let start = Vector3::new(-5.0, 1.8, 0.0);
+    let (t, c) = sweep(&start, &Vector3::new(5.0, 1.8, 0.0), &Vector3::new_same(0.1), &person).unwrap();
+    assert!((t - 0.46).abs() < EPSILON);
+    assert_eq!(c.region, HitRegion::Head);
+    let (_, c) = sweep(&Vector3::new(-5.0, 0.5, 0.0), &Vector3::new(5.0, 0.5, 0.0), &Vector3::new_same(0.1), &person).unwrap();
+    assert_eq!(c.region, HitRegion::Body);
+  }
+}
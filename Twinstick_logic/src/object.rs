@@ -1,7 +1,7 @@
 pub use bincode::{deserialize, serialize};
 
-use crate::{math, cgmath, DrawCall};
-use crate::{SendStaticObject, SendDynamicObject, SendDynamicObjectUpdate, SendPlayerObjectUpdate, Input};
+use crate::{math, cgmath, DrawCall, narrow_phase};
+use crate::{SendStaticObject, SendDynamicObject, SendDynamicObjectUpdate, SendPlayerObjectUpdate, Input, Contact, Categories, Interactive, MovingPlatform};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Vector2 {
@@ -57,6 +57,30 @@ impl Vector3 {
     Vector3::new(self.x*v.x, self.y*v.y, self.z*v.z)
   }
   
+  pub fn add(&self, v: &Vector3) -> Vector3 {
+    Vector3::new(self.x+v.x, self.y+v.y, self.z+v.z)
+  }
+  
+  pub fn sub(&self, v: &Vector3) -> Vector3 {
+    Vector3::new(self.x-v.x, self.y-v.y, self.z-v.z)
+  }
+  
+  pub fn scale(&self, s: f64) -> Vector3 {
+    Vector3::new(self.x*s, self.y*s, self.z*s)
+  }
+  
+  pub fn dot(&self, v: &Vector3) -> f64 {
+    self.x*v.x + self.y*v.y + self.z*v.z
+  }
+  
+  pub fn cross(&self, v: &Vector3) -> Vector3 {
+    Vector3::new(self.y*v.z - self.z*v.y, self.z*v.x - self.x*v.z, self.x*v.y - self.y*v.x)
+  }
+  
+  pub fn magnitude(&self) -> f64 {
+    self.dot(self).sqrt()
+  }
+  
   pub fn to_cgmath(&self) -> cgmath::Vector3<f32> {
     cgmath::Vector3::new(self.x as f32, self.y as f32, self.z as f32)
   }
@@ -108,10 +132,77 @@ pub enum CollisionType {
   Point,//(Vector3),
 }
 
+// Which part of an object a hitbox covers, damage code reads it off the contact
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub enum HitRegion {
+  Body,
+  Head,
+}
+
+impl HitRegion {
+  pub fn damage_multiplier(&self) -> i32 {
+    match self {
+      HitRegion::Body => 1,
+      HitRegion::Head => 2,
+    }
+  }
+}
+
+// One shape out of a compound hitbox, placed relative to the object's position and
+// turned along with it. Spheres use size.x as their radius like whole hitboxes do.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct Hitbox {
+  pub shape: CollisionType,
+  pub offset: Vector3,
+  pub size: Vector3,
+  pub rotation: Vector4,
+  pub region: HitRegion,
+}
+
+impl Hitbox {
+  pub fn new(offset: Vector3, size: Vector3) -> Hitbox {
+    Hitbox {
+      shape: CollisionType::AABB,
+      offset,
+      size,
+      rotation: narrow_phase::identity(),
+      region: HitRegion::Body,
+    }
+  }
+  
+  pub fn sphere(mut self) -> Hitbox {
+    self.shape = CollisionType::Sphere;
+    
+    self
+  }
+  
+  pub fn rotation(mut self, rotation: Vector4) -> Hitbox {
+    self.rotation = rotation;
+    
+    self
+  }
+  
+  pub fn region(mut self, region: HitRegion) -> Hitbox {
+    self.region = region;
+    
+    self
+  }
+  
+  pub fn collision_data(&self, pos: &Vector3, rotation: &Vector4) -> CollisionInfo {
+    let centre = pos.add(&narrow_phase::rotate(rotation, &self.offset));
+    match self.shape {
+      CollisionType::AABB => CollisionInfo::AABB(centre, self.size.clone(), narrow_phase::multiply(rotation, &self.rotation)),
+      CollisionType::Sphere => CollisionInfo::Sphere(Vector4::new(centre.x, centre.y, centre.z, self.size.x)),
+      CollisionType::Point => CollisionInfo::Point(centre),
+    }
+  }
+}
+
 pub enum CollisionInfo {
   AABB(Vector3, Vector3, Vector4),
   Sphere(Vector4),
   Point(Vector3),
+  Compound(Vec<(CollisionInfo, HitRegion)>),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -132,6 +223,16 @@ pub struct ObjectData {
   pub collision_data: CollisionType,
   
   pub hitbox_size: Vector3,
+  // Used instead of the single hitbox when there are any, hitbox_size should
+  // still cover all of them
+  pub hitboxes: Vec<Hitbox>,
+  pub step_height: f64,
+  
+  // What this object is and what it is willing to touch, see CollisionMatrix
+  pub layer: Categories,
+  pub mask: Categories,
+  // Overlaps are reported instead of pushing anything around
+  pub trigger: bool,
   
   damage: i32,
   pub life: i32,
@@ -157,6 +258,12 @@ impl ObjectData {
       collision_data: CollisionType::AABB,//(pos, size.clone(), Vector4::new(0.0, 0.0, 0.0, 1.0))),
       
       hitbox_size: size,//: Vector3::new_same(1.0),
+      hitboxes: Vec::new(),
+      step_height: 0.0,
+      
+      layer: Categories::NONE,
+      mask: Categories::ALL,
+      trigger: false,
       
       damage: 1,
       life: 1,
@@ -180,6 +287,36 @@ impl ObjectData {
     self
   }
   
+  pub fn hitboxes(mut self, hitboxes: Vec<Hitbox>) -> ObjectData {
+    self.hitboxes = hitboxes;
+    
+    self
+  }
+  
+  pub fn step_height(mut self, height: f64) -> ObjectData {
+    self.step_height = height;
+    
+    self
+  }
+  
+  pub fn layer(mut self, layer: Categories) -> ObjectData {
+    self.layer = layer;
+    
+    self
+  }
+  
+  pub fn mask(mut self, mask: Categories) -> ObjectData {
+    self.mask = mask;
+    
+    self
+  }
+  
+  pub fn trigger(mut self) -> ObjectData {
+    self.trigger = true;
+    
+    self
+  }
+  
   pub fn set_life(mut self, life: i32) -> ObjectData {
     self.life = life;
     
@@ -210,11 +347,49 @@ pub trait GenericObject: GenericObjectClone {
   fn update(&mut self, is_player: bool, delta_time: f64) -> Vec<Box<dyn GenericObject>>;
   fn physics_update(&mut self, delta_time: f64);
   
-  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>);
-  fn collided_with_static_object(&mut self, static_object: &mut Box<dyn GenericObject>);
+  // The contact normal points from self towards the other object
+  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>, contact: &Contact);
+  fn collided_with_static_object(&mut self, static_object: &mut Box<dyn GenericObject>, contact: &Contact);
   
   fn additional_draws(&self, draw_calls: &mut Vec<DrawCall>);
   
+  // Things fast enough to pass through a wall in one tick say where they started
+  // it, collisions then check the whole path instead of only where they ended up
+  fn swept_from(&self) -> Option<Vector3> {
+    None
+  }
+  
+  // Where players touching this are moved to, see Section::elevator
+  fn teleport_target(&self) -> Option<Vector3> {
+    None
+  }
+  
+  fn set_teleport(&mut self, _target: Vector3) {
+    
+  }
+  
+  // A bullet ran into this static object
+  fn shot(&mut self, _damage: i32) {
+    
+  }
+  
+  // Doors, switches and the like, see Interactive
+  fn interactive(&self) -> Option<&Interactive> {
+    None
+  }
+  
+  fn mut_interactive(&mut self) -> Option<&mut Interactive> {
+    None
+  }
+  
+  fn platform(&self) -> Option<&MovingPlatform> {
+    None
+  }
+  
+  fn mut_platform(&mut self) -> Option<&mut MovingPlatform> {
+    None
+  }
+  
   fn send_dyn_obj(&self) -> SendDynamicObject {
     SendDynamicObject {
       x: self.position().x,
@@ -255,7 +430,9 @@ pub trait GenericObject: GenericObjectClone {
       pos: self.position().clone(),
       size: self.size().clone(),
       hitbox_scale: self.data().hitbox_size.clone(),
+      rotation: self.rotation().clone(),
       model: self.model().to_string(),
+      trigger: self.data().trigger,
     }
   }
   
@@ -288,9 +465,19 @@ pub trait GenericObject: GenericObjectClone {
   }
   
   fn collision_data(&self) -> CollisionInfo {
+    // Characters and bullets turn to aim, only static geometry has rotated hitboxes
+    let rotation = match self.data().physics_type {
+      ObjectPhysicsType::Static => narrow_phase::quaternion_from_euler(self.rotation()),
+      _ => narrow_phase::identity(),
+    };
+    
+    if !self.data().hitboxes.is_empty() {
+      return CollisionInfo::Compound(self.data().hitboxes.iter().map(|h| (h.collision_data(&self.data().pos, &rotation), h.region)).collect());
+    }
+    
     match &self.data().collision_data {
       CollisionType::AABB => {
-        CollisionInfo::AABB(self.data().pos.clone(), self.hitbox_size(), Vector4::new(1.0, 0.0, 0.0, 0.0))
+        CollisionInfo::AABB(self.data().pos.clone(), self.hitbox_size(), rotation)
       },
       CollisionType::Sphere => {
         CollisionInfo::Sphere(Vector4::new(self.data().pos.x, self.data().pos.y, self.data().pos.z, self.hitbox_size().x))
@@ -301,6 +488,12 @@ pub trait GenericObject: GenericObjectClone {
     }
   }
   
+  // Knockback and the like, friction wears it off again
+  fn apply_impulse(&mut self, impulse: &Vector3) {
+    let vel = self.data().vel.add(impulse);
+    self.mut_data().vel = vel;
+  }
+  
   fn take_damage(&mut self, dmg: i32) {
     self.mut_data().life -= dmg;
     if self.data().life <= 0 {
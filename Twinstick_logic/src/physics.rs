@@ -0,0 +1,165 @@
+use crate::{ObjectData, Vector3};
+
+// What the old 9.8 taken off every tick came to at 60hz, falling speed is capped
+// at the same 9.8 it used to be clamped to
+pub const GRAVITY: f64 = 588.0;
+pub const TERMINAL_VELOCITY: f64 = 9.8;
+// How quickly walking speeds up to what was asked for, and slows back down without input
+pub const ACCELERATION: f64 = 120.0;
+pub const FRICTION: f64 = 120.0;
+// Fraction of the above available while off the ground
+pub const AIR_CONTROL: f64 = 0.5;
+// Anything below this has fallen out of the world
+pub const KILL_PLANE_Y: f64 = -50.0;
+
+// Moves v towards target at rate for delta_time seconds, returning the new value
+// and how far it went doing so. Exact for any delta_time, which is what keeps
+// the results the same at every tick rate.
+fn approach(v: f64, target: f64, rate: f64, delta_time: f64) -> (f64, f64) {
+  let gap = target - v;
+  if rate <= 0.0 || gap == 0.0 {
+    return (v, v*delta_time);
+  }
+  
+  let time_to_reach = gap.abs() / rate;
+  if time_to_reach >= delta_time {
+    let new_v = v + gap.signum()*rate*delta_time;
+    (new_v, (v + new_v)*0.5*delta_time)
+  } else {
+    (target, (v + target)*0.5*time_to_reach + target*(delta_time - time_to_reach))
+  }
+}
+
+// The same for the xz plane, the velocity turns straight towards the target
+fn approach_xz(vel: &Vector3, target: &Vector3, rate: f64, delta_time: f64) -> (Vector3, Vector3) {
+  let gap = Vector3::new(target.x - vel.x, 0.0, target.z - vel.z);
+  let length = gap.magnitude();
+  if length == 0.0 {
+    return (Vector3::new(vel.x, 0.0, vel.z), Vector3::new(vel.x*delta_time, 0.0, vel.z*delta_time));
+  }
+  
+  let direction = gap.scale(1.0/length);
+  let along = Vector3::new(vel.x, 0.0, vel.z).dot(&direction);
+  let (new_along, moved_along) = approach(along, along + length, rate, delta_time);
+  
+  // Whatever isn't along the direction of change carries on as it was
+  let across = Vector3::new(vel.x, 0.0, vel.z).sub(&direction.scale(along));
+  let new_vel = across.add(&direction.scale(new_along));
+  let moved = across.scale(delta_time).add(&direction.scale(moved_along));
+  (new_vel, moved)
+}
+
+// Steps an object that walks and falls. Wish is the horizontal velocity it is
+// trying to reach, knockback and anything else added to vel is worn away towards
+// it. Grounded is cleared, resting on something sets it again during collisions.
+// Returns true once the object has fallen below the kill plane.
+pub fn integrate(data: &mut ObjectData, wish: &Vector3, delta_time: f64) -> bool {
+  let was_grounded = data.grounded;
+  data.grounded = false;
+  
+  let mut rate = if wish.x != 0.0 || wish.z != 0.0 { ACCELERATION } else { FRICTION };
+  if !was_grounded {
+    rate *= AIR_CONTROL;
+  }
+  let (vel, moved) = approach_xz(&data.vel, wish, rate, delta_time);
+  
+  if data.vel.y < -TERMINAL_VELOCITY {
+    data.vel.y = -TERMINAL_VELOCITY;
+  }
+  let (vel_y, moved_y) = approach(data.vel.y, -TERMINAL_VELOCITY, GRAVITY, delta_time);
+  
+  data.vel = Vector3::new(vel.x, vel_y, vel.z);
+  data.pos = data.pos.add(&Vector3::new(moved.x, moved_y, moved.z));
+  
+  data.pos.y < KILL_PLANE_Y
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  
+  const TICK_RATES: [f64; 5] = [10.0, 20.0, 30.0, 60.0, 144.0];
+  
+  fn object() -> ObjectData {
+    ObjectData::new(Vector3::new(0.0, 10.0, 0.0), Vector3::new_same(1.0), "test".to_string()).dynamic_physics()
+  }
+  
+  // Runs for a whole number of seconds, input can change each second
+  fn run(mut data: ObjectData, seconds: &[(Vector3, bool)], tick_rate: f64) -> ObjectData {
+    let delta_time = 1.0/tick_rate;
+    for (wish, grounded) in seconds {
+      for _ in 0..tick_rate as usize {
+        data.grounded = *grounded;
+        integrate(&mut data, wish, delta_time);
+      }
+    }
+    
+    data
+  }
+  
+  fn assert_same_at_every_tick_rate(start: ObjectData, seconds: &[(Vector3, bool)]) -> ObjectData {
+    let expected = run(start.clone(), seconds, 60.0);
+    for tick_rate in TICK_RATES.iter() {
+      let data = run(start.clone(), seconds, *tick_rate);
+      assert!(data.pos.sub(&expected.pos).magnitude() < 1e-6, "{} hz ended at {:?} not {:?}", tick_rate, data.pos, expected.pos);
+      assert!(data.vel.sub(&expected.vel).magnitude() < 1e-6, "{} hz moving at {:?} not {:?}", tick_rate, data.vel, expected.vel);
+    }
+    
+    expected
+  }
+  
+  #[test]
+  fn falling_is_the_same_at_every_tick_rate() {
+    let data = assert_same_at_every_tick_rate(object(), &[(Vector3::new_same(0.0), false)]);
+    assert_eq!(data.vel.y, -TERMINAL_VELOCITY);
+    // Most of a second at terminal velocity, after the short while it took to get there
+    let reach = TERMINAL_VELOCITY/GRAVITY;
+    assert!((data.pos.y - (10.0 - TERMINAL_VELOCITY*(1.0 - reach) - TERMINAL_VELOCITY*0.5*reach)).abs() < 1e-9);
+    
+    // A jump comes back down to the same place
+    let mut jumping = object();
+    jumping.vel.y = 50.0;
+    assert_same_at_every_tick_rate(jumping, &[(Vector3::new_same(0.0), false)]);
+  }
+  
+  #[test]
+  fn walking_speeds_up_and_slows_down_the_same_at_every_tick_rate() {
+    let forwards = Vector3::new(12.0, 0.0, 0.0);
+    let data = assert_same_at_every_tick_rate(object(), &[(forwards.clone(), true), (Vector3::new_same(0.0), true)]);
+    // 0.1s to get up to speed and 0.1s to stop, losing half of each
+    assert!((data.pos.x - 12.0).abs() < 1e-9);
+    assert_eq!(data.vel.x, 0.0);
+    
+    // Turning round in the air takes 0.4s, going nowhere overall while it does
+    let data = assert_same_at_every_tick_rate(object(), &[(forwards.clone(), true), (forwards.scale(-1.0), false)]);
+    assert!(data.vel.x < 0.0);
+    assert!((data.pos.x - (11.4 - 12.0*0.6)).abs() < 1e-9, "{}", data.pos.x);
+  }
+  
+  #[test]
+  fn knockback_wears_off_the_same_at_every_tick_rate() {
+    let mut hit = object();
+    hit.vel = Vector3::new(0.0, 0.0, 30.0);
+    let data = assert_same_at_every_tick_rate(hit, &[(Vector3::new_same(0.0), true)]);
+    assert_eq!(data.vel.z, 0.0);
+    assert!((data.pos.z - 30.0*30.0/(2.0*FRICTION)).abs() < 1e-9);
+    
+    // Walking sideways while being pushed keeps the push along its own axis
+    let mut hit = object();
+    hit.vel = Vector3::new(0.0, 0.0, 30.0);
+    let data = assert_same_at_every_tick_rate(hit, &[(Vector3::new(12.0, 0.0, 0.0), true)]);
+    assert_eq!(data.vel.x, 12.0);
+    assert_eq!(data.vel.z, 0.0);
+  }
+  
+  #[test]
+  fn falling_out_of_the_world_is_noticed() {
+    let mut data = object();
+    let mut seconds = 0.0;
+    while !integrate(&mut data, &Vector3::new_same(0.0), 1.0/60.0) {
+      seconds += 1.0/60.0;
+      assert!(seconds < 10.0, "still falling at {}", data.pos.y);
+    }
+    assert!(data.pos.y < KILL_PLANE_Y);
+  }
+}
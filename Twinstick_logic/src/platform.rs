@@ -0,0 +1,368 @@
+use crate::{ObjectData, GenericObject, StaticObject, SendPlatform, DrawCall, Vector3, Contact, TwinstickError};
+
+// How far above the top something's feet can be and still be carried along
+const RIDE_HEIGHT: f64 = 0.5;
+// Clients make up this much of the gap to the server's position each second
+const CATCH_UP: f64 = 8.0;
+// Any further behind than this and clients jump straight to the server's position
+const SNAP_DISTANCE: f64 = 5.0;
+// Speed hazards throw things away from them at
+const HAZARD_KNOCKBACK: f64 = 20.0;
+
+// Kinematic, moves along its path whatever is in the way and pushes things out of itself
+// like any other static object. The path loops from the last waypoint back to the first,
+// so one with two waypoints goes back and forth.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct MovingPlatform {
+  object: StaticObject,
+  path: Vec<Vector3>,
+  speed: f64,
+  next: usize,
+  // Damage a second done to whatever touches it, if it is a hazard
+  hazard: Option<i32>,
+  // Damage built up over time spent touching it that isn't a whole point yet, and the
+  // whole points done to anything touching it this tick
+  owed: f64,
+  dealing: i32,
+  touched: bool,
+  // How far it went in the last advance, riders moved the same
+  moved: Vector3,
+  // Clients only, how far off the server's position it is
+  correction: Vector3,
+}
+
+impl MovingPlatform {
+  // Starts on the first waypoint heading for the second, so there has to be a second
+  pub fn new(path: Vec<Vector3>, size: Vector3, speed: f64, model: String) -> Result<MovingPlatform, TwinstickError> {
+    if path.len() < 2 {
+      return Err(TwinstickError::InvalidPlatform(format!("path has {} waypoints, needs at least 2", path.len())));
+    }
+    
+    Ok(MovingPlatform {
+      object: StaticObject::new(path[0].clone(), size, model),
+      next: 1,
+      path,
+      speed,
+      hazard: None,
+      owed: 0.0,
+      dealing: 0,
+      touched: false,
+      moved: Vector3::new_same(0.0),
+      correction: Vector3::new_same(0.0),
+    })
+  }
+  
+  // Hurts players and enemies that touch it by damage a second, and knocks them back
+  // instead of being stood on
+  pub fn hazard(mut self, damage: i32) -> MovingPlatform {
+    self.hazard = Some(damage);
+    self
+  }
+  
+  pub fn rotation(mut self, rot: Vector3) -> MovingPlatform {
+    self.object = self.object.rotation(rot);
+    self
+  }
+  
+  pub fn path(&self) -> &Vec<Vector3> {
+    &self.path
+  }
+  
+  pub fn speed(&self) -> f64 {
+    self.speed
+  }
+  
+  pub fn next_waypoint(&self) -> usize {
+    self.next
+  }
+  
+  pub fn is_hazard(&self) -> bool {
+    self.hazard.is_some()
+  }
+  
+  pub fn moved(&self) -> &Vector3 {
+    &self.moved
+  }
+  
+  // Feet resting on the top, and over it. Rotation is ignored, like the step up in StaticObject.
+  pub fn carries(&self, object: &dyn GenericObject) -> bool {
+    if self.hazard.is_some() || object.data().vel.y > 0.0 {
+      return false;
+    }
+    
+    let pos = self.position();
+    let half = self.hitbox_size().scale(0.5);
+    let feet = object.position().y - object.hitbox_size().y*0.5;
+    let above = feet - (pos.y + half.y);
+    
+    (-RIDE_HEIGHT..=RIDE_HEIGHT).contains(&above) &&
+    (object.position().x - pos.x).abs() <= half.x &&
+    (object.position().z - pos.z).abs() <= half.z
+  }
+  
+  // Moves speed*delta_time along the path, plus some of any correction from the server,
+  // and returns how far it went
+  pub fn advance(&mut self, delta_time: f64) -> Vector3 {
+    if let Some(damage) = self.hazard {
+      // Only time spent touching counts
+      if !self.touched {
+        self.owed = 0.0;
+      }
+      self.owed += damage as f64*delta_time;
+      // Small enough not to matter, big enough that adding up thirds makes a whole
+      self.dealing = (self.owed + 1e-9).floor() as i32;
+      self.owed -= self.dealing as f64;
+      self.touched = false;
+    }
+    
+    let start = self.position().clone();
+    let mut pos = start.clone();
+    let mut left = self.speed*delta_time;
+    
+    // Each waypoint reached is one pass, a step longer than the whole loop stops early
+    for _ in 0..self.path.len() {
+      let to = self.path[self.next].sub(&pos);
+      let distance = to.magnitude();
+      if distance > left {
+        pos = pos.add(&to.scale(left/distance));
+        break;
+      }
+      
+      pos = self.path[self.next].clone();
+      left -= distance;
+      self.next = (self.next + 1) % self.path.len();
+    }
+    
+    let catch_up = self.correction.scale((CATCH_UP*delta_time).min(1.0));
+    self.correction = self.correction.sub(&catch_up);
+    pos = pos.add(&catch_up);
+    
+    self.moved = pos.sub(&start);
+    self.object.set_position(pos);
+    
+    self.moved.clone()
+  }
+  
+  // Where the server says it is. Small differences are eased out over the next few
+  // advances rather than jumped, so riders aren't thrown about.
+  pub fn sync(&mut self, pos: Vector3, next: usize) {
+    self.next = next % self.path.len();
+    
+    let error = pos.sub(self.position());
+    if error.magnitude() > SNAP_DISTANCE {
+      self.object.set_position(pos);
+      self.correction = Vector3::new_same(0.0);
+    } else {
+      self.correction = error;
+    }
+  }
+  
+  pub fn send_platform(&self) -> SendPlatform {
+    SendPlatform {
+      path: self.path.clone(),
+      size: self.size().clone(),
+      rotation: self.rotation().clone(),
+      model: self.model().to_string(),
+      speed: self.speed,
+      hazard: self.hazard,
+      pos: self.position().clone(),
+      next: self.next,
+    }
+  }
+}
+
+impl GenericObject for MovingPlatform {
+  fn data(&self) -> &ObjectData {
+    self.object.data()
+  }
+  
+  fn mut_data(&mut self) -> &mut ObjectData {
+    self.object.mut_data()
+  }
+  
+  fn platform(&self) -> Option<&MovingPlatform> {
+    Some(self)
+  }
+  
+  fn mut_platform(&mut self) -> Option<&mut MovingPlatform> {
+    Some(self)
+  }
+  
+  fn collided_with_static_object(&mut self, _static_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
+  
+  }
+  
+  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>, contact: &Contact) {
+    self.object.collided_with_dynamic_object(dynamic_object, contact);
+    if self.hazard.is_some() {
+      self.touched = true;
+      if self.dealing > 0 {
+        dynamic_object.take_damage(self.dealing);
+      }
+      
+      // Brought up to the knockback speed rather than added to, so touching it for more
+      // ticks doesn't throw things further
+      let away = dynamic_object.data().vel.dot(&contact.normal);
+      if away < HAZARD_KNOCKBACK {
+        dynamic_object.apply_impulse(&contact.normal.scale(HAZARD_KNOCKBACK - away));
+      }
+    }
+  }
+  
+  fn update(&mut self, _is_player: bool, _delta_time: f64) -> Vec<Box<dyn GenericObject>> {
+    Vec::new()
+  }
+  
+  fn physics_update(&mut self, _delta_time: f64) {
+  
+  }
+  
+  fn additional_draws(&self, _draw_calls: &mut Vec<DrawCall>) {
+  
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Character, CollisionGrid, CollisionMatrix, TwinstickGame, TickObjects, Section, SECTION_SIZE};
+  
+  fn lift() -> MovingPlatform {
+    let top = Section::floor_top(0);
+    MovingPlatform::new(vec!(Vector3::new(0.0, top + 0.5, 0.0), Vector3::new(10.0, top + 0.5, 0.0), Vector3::new(10.0, top + 6.5, 0.0)),
+                        Vector3::new(4.0, 1.0, 4.0), 5.0, "unit_floor".to_string()).unwrap()
+  }
+  
+  fn run(players: &mut Vec<Box<dyn GenericObject>>, platforms: &mut Vec<Box<dyn GenericObject>>, seconds: f64) {
+    let mut statics = Section::new(0, 0, SECTION_SIZE).floor().static_objects();
+    let grid = CollisionGrid::build(SECTION_SIZE, &statics);
+    for _ in 0..(seconds*60.0).round() as usize {
+      let objects = TickObjects {
+        players,
+        enemies: &mut Vec::new(),
+        player_bullets: &mut Vec::new(),
+        enemy_bullets: &mut Vec::new(),
+        static_objects: &mut statics,
+        static_grid: &grid,
+        interactives: &mut Vec::new(),
+        platforms,
+        dynamic_objects: &mut Vec::new(),
+      };
+      TwinstickGame::update(objects, &CollisionMatrix::new(), None, 1.0/60.0);
+    }
+  }
+  
+  #[test]
+  fn platforms_follow_their_path_around_and_back() {
+    let mut platform = lift();
+    let top = Section::floor_top(0);
+    
+    assert_eq!(platform.advance(1.0), Vector3::new(5.0, 0.0, 0.0));
+    // Turns the corner part way through a step
+    platform.advance(1.2);
+    assert_eq!(platform.next_waypoint(), 2);
+    assert!(platform.position().sub(&Vector3::new(10.0, top + 1.5, 0.0)).magnitude() < 1e-9);
+    
+    // Back along the diagonal to the first waypoint and on towards the second
+    for _ in 0..50 {
+      platform.advance(0.1);
+    }
+    assert_eq!(platform.next_waypoint(), 1);
+  }
+  
+  #[test]
+  fn paths_without_two_waypoints_are_refused() {
+    let new = |path| MovingPlatform::new(path, Vector3::new_same(1.0), 1.0, "unit_floor".to_string());
+    assert!(new(Vec::new()).is_err());
+    assert!(new(vec!(Vector3::new_same(0.0))).is_err());
+    
+    // Such as from a bad or tampered packet
+    let mut sent = lift().send_platform();
+    sent.path.truncate(1);
+    match sent.to_platform() {
+      Err(TwinstickError::InvalidPlatform(reason)) => assert!(reason.contains("1 waypoints"), "{}", reason),
+      other => panic!("expected an invalid platform, got {:?}", other),
+    }
+  }
+  
+  #[test]
+  fn riders_are_carried_and_hazards_throw_things_off() {
+    let top = Section::floor_top(0);
+    let mut players: Vec<Box<dyn GenericObject>> = vec!(Box::new(Character::new(Vector3::new(0.0, top + 4.0, 0.0), Vector3::new_same(1.0))));
+    let mut platforms: Vec<Box<dyn GenericObject>> = vec!(Box::new(lift()));
+    
+    // Lands on it and rides to the corner, then up
+    run(&mut players, &mut platforms, 0.2);
+    let offset = players[0].position().x - platforms[0].position().x;
+    run(&mut players, &mut platforms, 1.7);
+    assert!((players[0].position().x - platforms[0].position().x - offset).abs() < 0.01);
+    run(&mut players, &mut platforms, 1.0);
+    assert!(players[0].position().y > top + 5.0);
+    assert!(players[0].position().x > 9.0);
+    
+    let mut players: Vec<Box<dyn GenericObject>> = vec!(Box::new(Character::new(Vector3::new(5.0, top + 2.0, 0.0), Vector3::new_same(1.0))));
+    players[0].mut_data().life = 3;
+    let mut platforms: Vec<Box<dyn GenericObject>> = vec!(Box::new(lift().hazard(60)));
+    run(&mut players, &mut platforms, 1.0);
+    assert!(players[0].data().life < 3);
+    assert!(players[0].position().x > 7.0);
+  }
+  
+  // Something pinned against a hazard for a second, its velocity after every tick
+  fn pinned_against_hazard(tick_rate: f64) -> (i32, Vec<f64>) {
+    let mut hazard = lift().hazard(10);
+    let mut player: Box<dyn GenericObject> = Box::new(Character::new(Vector3::new(0.0, 5.0, 0.0), Vector3::new_same(1.0)));
+    player.mut_data().life = 100;
+    let contact = Contact::new(Vector3::new(0.0, 1.0, 0.0), 0.1, Vector3::new_same(0.0));
+    
+    let mut speeds = Vec::new();
+    for _ in 0..tick_rate as usize {
+      hazard.advance(1.0/tick_rate);
+      hazard.collided_with_dynamic_object(&mut player, &contact);
+      speeds.push(player.data().vel.y);
+    }
+    
+    (player.data().life, speeds)
+  }
+  
+  #[test]
+  fn hazards_hurt_and_knock_back_the_same_at_30_and_120_hz() {
+    for tick_rate in [30.0, 120.0].iter() {
+      let (life, speeds) = pinned_against_hazard(*tick_rate);
+      assert_eq!(life, 90, "{} hz", tick_rate);
+      assert!(speeds.iter().all(|speed| *speed == HAZARD_KNOCKBACK), "{} hz {:?}", tick_rate, speeds);
+    }
+    
+    // Brushing past it over and over never adds up to a hit
+    let mut hazard = lift().hazard(10);
+    let mut player: Box<dyn GenericObject> = Box::new(Character::new(Vector3::new(0.0, 5.0, 0.0), Vector3::new_same(1.0)));
+    player.mut_data().life = 100;
+    let contact = Contact::new(Vector3::new(0.0, 1.0, 0.0), 0.1, Vector3::new_same(0.0));
+    for tick in 0..120 {
+      hazard.advance(1.0/60.0);
+      if tick % 2 == 0 {
+        hazard.collided_with_dynamic_object(&mut player, &contact);
+      }
+    }
+    assert_eq!(player.data().life, 100);
+  }
+  
+  #[test]
+  fn clients_ease_towards_the_server() {
+    let mut server = lift();
+    let mut client = lift();
+    server.advance(0.5);
+    client.advance(0.45);
+    
+    client.sync(server.position().clone(), server.next_waypoint());
+    for _ in 0..60 {
+      server.advance(1.0/60.0);
+      client.advance(1.0/60.0);
+    }
+    assert!(client.position().sub(server.position()).magnitude() < 0.01);
+    
+    // Too far to ease, such as after the map changed
+    client.sync(Vector3::new(50.0, 0.0, 0.0), 0);
+    assert_eq!(client.position(), &Vector3::new(50.0, 0.0, 0.0));
+  }
+}
@@ -1,6 +1,6 @@
-use crate::SPEED;
+use crate::{SPEED, STEP_HEIGHT, Categories, Hitbox, HitRegion, physics};
 use crate::Input;
-use crate::{math, DrawCall};
+use crate::{math, DrawCall, Contact};
 
 use crate::{Vector2, Vector3, GenericObject, ObjectData, Bullet};
 
@@ -10,14 +10,32 @@ const WEAPON_COOLDOWN: f64 = 0.06;
 pub struct Character {
   pub data: ObjectData,
   weapon_cooldown: f64,
+  // Where it last stood, falling out of the world puts it back here
+  last_safe: Vector3,
 }
 
 impl Character {
   pub fn new(pos: Vector3, size: Vector3) -> Character {
-    let mut data = ObjectData::new(pos, size.clone(), "main_char".to_string()).dynamic_physics().hitbox_size(Vector3::new(size.x, size.y*3.5, size.z));
+    let height = size.y*3.5;
+    // The head is the top fifth and a little narrower than the body under it
+    let hitboxes = vec!(
+      Hitbox::new(Vector3::new(0.0, -height*0.1, 0.0), Vector3::new(size.x, height*0.8, size.z)),
+      Hitbox::new(Vector3::new(0.0, height*0.4, 0.0), Vector3::new(size.x*0.8, height*0.2, size.z*0.8)).region(HitRegion::Head),
+    );
+    let mut data = ObjectData::new(pos, size.clone(), "main_char".to_string()).dynamic_physics().hitbox_size(Vector3::new(size.x, height, size.z))
+                     .hitboxes(hitboxes).step_height(STEP_HEIGHT).layer(Categories::PLAYERS);
     data.rotation.y = 180.0;
     
     Character {
+      last_safe: data.pos.clone(),
+      data,
+      weapon_cooldown: WEAPON_COOLDOWN,
+    }
+  }
+  
+  pub fn from_data(data: ObjectData) -> Character {
+    Character {
+      last_safe: data.pos.clone(),
       data,
       weapon_cooldown: WEAPON_COOLDOWN,
     }
@@ -60,11 +78,11 @@ impl GenericObject for Character {
     &mut self.data
   }
   
-  fn collided_with_static_object(&mut self, static_object: &mut Box<dyn GenericObject>) {
+  fn collided_with_static_object(&mut self, _static_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
     
   }
   
-  fn collided_with_dynamic_object(&mut self, _dynamic_object: &mut Box<dyn GenericObject>) {
+  fn collided_with_dynamic_object(&mut self, _dynamic_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
     
   }
   
@@ -106,7 +124,9 @@ impl GenericObject for Character {
             left_click = true;
             self.mut_data().is_firing = true;
           }
-        }
+        },
+        // Handled by the game, it needs to see the switches
+        Input::Interact => {},
       }
     }
     
@@ -131,21 +151,17 @@ impl GenericObject for Character {
   }
   
   fn physics_update(&mut self, delta_time: f64) {
-    if !self.data().grounded {
-      self.mut_data().vel.y -= 9.8;
+    if self.data().grounded {
+      self.last_safe = self.position().clone();
     }
     
-    if self.data().vel.y < -9.8 {
-      self.mut_data().vel.y = -9.8;
+    // rel_vel is relative to facing 180, which turns it around on both axes
+    let wish = Vector3::new(-self.data().rel_vel.x, 0.0, -self.data().rel_vel.z);
+    if physics::integrate(self.mut_data(), &wish, delta_time) {
+      let safe = self.last_safe.clone();
+      self.set_position(safe);
+      self.mut_data().vel = Vector3::new_same(0.0);
     }
-    
-    if self.data().pos.y < self.hitbox_size().y*0.5 {
-      self.mut_data().pos.y = self.hitbox_size().y*0.5;
-      self.mut_data().grounded = true;
-    }
-    
-    self.y_rot_movement(delta_time);
-    self.axis_movement(delta_time);
   }
   
   fn additional_draws(&self, draw_calls: &mut Vec<DrawCall>) {
@@ -0,0 +1,425 @@
+// Wire protocol negotiation.
+//
+// Bump PROTOCOL_VERSION whenever the encoding of an existing DataType variant or
+// Send* struct changes, and add golden messages for the new version in the tests
+// below. The older versions' golden messages are left as they are.
+// Appending new DataType variants does not need a bump as long as they are only
+// sent to peers that advertised the matching feature flag.
+
+pub const PROTOCOL_VERSION: u32 = 7;
+pub const MIN_PROTOCOL_VERSION: u32 = 7;
+
+// The single version number sent by clients before handshakes existed.
+pub const LEGACY_VERSION: u32 = 4;
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct ProtocolRange {
+  pub min: u32,
+  pub max: u32,
+}
+
+impl ProtocolRange {
+  pub fn new(min: u32, max: u32) -> ProtocolRange {
+    ProtocolRange {
+      min,
+      max,
+    }
+  }
+
+  pub fn supported() -> ProtocolRange {
+    ProtocolRange::new(MIN_PROTOCOL_VERSION, PROTOCOL_VERSION)
+  }
+
+  pub fn single(version: u32) -> ProtocolRange {
+    ProtocolRange::new(version, version)
+  }
+
+  pub fn contains(&self, version: u32) -> bool {
+    version >= self.min && version <= self.max
+  }
+
+  // Highest version both sides understand
+  pub fn negotiate(&self, other: &ProtocolRange) -> Option<u32> {
+    let min = self.min.max(other.min);
+    let max = self.max.min(other.max);
+
+    if min <= max {
+      Some(max)
+    } else {
+      None
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Features(pub u32);
+
+impl Features {
+  pub const NONE: Features = Features(0);
+  pub const ENEMIES: Features = Features(1 << 0);
+  pub const STATIC_OBJECT_STREAM: Features = Features(1 << 1);
+  // Admin, AdminResponse
+  pub const ADMIN: Features = Features(1 << 2);
+  // Chat
+  pub const CHAT: Features = Features(1 << 3);
+  // Kicked, ServerShutdown, ClearStaticObjects
+  pub const SERVER_NOTICES: Features = Features(1 << 4);
+  // DropStaticObjects
+  pub const SECTION_UNLOADING: Features = Features(1 << 5);
+  // LoadSection, UnloadSection and SectionLoaded acks, replaces StaticObject and DropStaticObjects
+  pub const SECTION_STREAMING: Features = Features(1 << 6);
+  // AddInteractive, InteractiveState, ClearInteractives and Input::Interact
+  pub const INTERACTIVES: Features = Features(1 << 7);
+  // AddPlatform, PlatformState, ClearPlatforms
+  pub const MOVING_PLATFORMS: Features = Features(1 << 8);
+
+  pub fn supported() -> Features {
+    Features::ENEMIES.with(Features::STATIC_OBJECT_STREAM)
+                     .with(Features::ADMIN)
+                     .with(Features::CHAT)
+                     .with(Features::SERVER_NOTICES)
+                     .with(Features::SECTION_UNLOADING)
+                     .with(Features::SECTION_STREAMING)
+                     .with(Features::INTERACTIVES)
+                     .with(Features::MOVING_PLATFORMS)
+  }
+
+  pub fn with(self, other: Features) -> Features {
+    Features(self.0 | other.0)
+  }
+
+  pub fn intersection(self, other: Features) -> Features {
+    Features(self.0 & other.0)
+  }
+
+  pub fn contains(self, other: Features) -> bool {
+    self.0 & other.0 == other.0
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct Handshake {
+  pub versions: ProtocolRange,
+  pub features: Features,
+}
+
+impl Handshake {
+  pub fn new() -> Handshake {
+    Handshake {
+      versions: ProtocolRange::supported(),
+      features: Features::supported(),
+    }
+  }
+
+  // What the server should answer to a client handshake
+  pub fn respond(&self, client: &Handshake) -> Result<HandshakeAccepted, ConnectionRejection> {
+    match self.versions.negotiate(&client.versions) {
+      Some(version) => {
+        Ok(HandshakeAccepted {
+          version,
+          features: self.features.intersection(client.features),
+        })
+      },
+      None => {
+        Err(ConnectionRejection::VersionMismatch {
+          server: self.versions,
+          client: client.versions,
+        })
+      }
+    }
+  }
+}
+
+impl Default for Handshake {
+  fn default() -> Handshake {
+    Handshake::new()
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub struct HandshakeAccepted {
+  pub version: u32,
+  pub features: Features,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone)]
+pub enum ConnectionRejection {
+  VersionMismatch { server: ProtocolRange, client: ProtocolRange },
+  Banned,
+}
+
+impl std::fmt::Display for ConnectionRejection {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    match self {
+      ConnectionRejection::VersionMismatch { server, client } => {
+        if client.max < server.min {
+          write!(f, "Client is out of date (server supports protocol {}-{}, client {}-{})",
+                 server.min, server.max, client.min, client.max)
+        } else {
+          write!(f, "Server is out of date (server supports protocol {}-{}, client {}-{})",
+                 server.min, server.max, client.min, client.max)
+        }
+      },
+      ConnectionRejection::Banned => {
+        write!(f, "You have been banned from this server")
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{DataType, Input, Vector3, SendStaticObject, SendSection, SendPlayerObjectUpdate,
+              SendDynamicObjectUpdate, SendDynamicObject, SendInteractive, InteractiveKind, SendPlatform, AdminCommand};
+
+  fn handshake(min: u32, max: u32) -> Handshake {
+    Handshake {
+      versions: ProtocolRange::new(min, max),
+      features: Features::supported(),
+    }
+  }
+
+  #[test]
+  fn compatibility_matrix() {
+    // (server range, client range, negotiated version)
+    let matrix = [
+      ((5, 5), (5, 5), Some(5)),
+      ((5, 7), (5, 5), Some(5)),
+      ((5, 7), (6, 9), Some(7)),
+      ((5, 5), (4, 4), None),
+      ((5, 5), (6, 6), None),
+      ((6, 8), (3, 5), None),
+    ];
+
+    for ((s_min, s_max), (c_min, c_max), expected) in matrix.iter() {
+      let server = handshake(*s_min, *s_max);
+      let client = handshake(*c_min, *c_max);
+      match server.respond(&client) {
+        Ok(accepted) => {
+          assert_eq!(Some(accepted.version), *expected);
+        },
+        Err(rejection) => {
+          assert_eq!(*expected, None);
+          assert_eq!(rejection, ConnectionRejection::VersionMismatch {
+                                  server: server.versions,
+                                  client: client.versions,
+                                });
+        },
+      }
+    }
+  }
+
+  #[test]
+  fn features_are_intersected() {
+    let server = Handshake::new();
+    let mut client = Handshake::new();
+    client.features = Features::ENEMIES;
+
+    let accepted = server.respond(&client).unwrap();
+    assert!(accepted.features.contains(Features::ENEMIES));
+    assert!(!accepted.features.contains(Features::STATIC_OBJECT_STREAM));
+  }
+
+  #[test]
+  fn rejection_explains_who_is_outdated() {
+    let rejection = Handshake::new().respond(&handshake(1, 2)).unwrap_err();
+    assert!(rejection.to_string().starts_with("Client is out of date"));
+  }
+
+  #[test]
+  fn truncated_message_is_an_error() {
+    let bytes = DataType::PlayerNum(2).serialise().unwrap();
+    assert!(DataType::deserialise(&bytes[..6]).is_err());
+  }
+
+  // Messages a client built for protocol 4 sends, these must keep decoding so
+  // old clients can be told why they were turned away.
+  #[test]
+  fn legacy_v4_try_connect_decodes() {
+    let bytes = [0, 0, 0, 0, 4, 0, 0, 0];
+    assert_eq!(DataType::deserialise(&bytes).unwrap(), DataType::TryConnect(LEGACY_VERSION));
+  }
+
+  // Every version from MIN_PROTOCOL_VERSION up needs its own list
+  fn golden_messages(version: u32) -> Option<Vec<(DataType, Vec<u8>)>> {
+    match version {
+      7 => Some(golden_messages_v7()),
+      _ => None,
+    }
+  }
+
+  // Frozen, entries for variants appended since can be added but existing bytes never change
+  fn golden_messages_v7() -> Vec<(DataType, Vec<u8>)> {
+    vec!(
+      (DataType::TryConnect(4),
+       vec!(0, 0, 0, 0, 4, 0, 0, 0)),
+      (DataType::ConfirmConnect(4),
+       vec!(1, 0, 0, 0, 4, 0, 0, 0)),
+      (DataType::PlayerNum(2),
+       vec!(2, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0)),
+      (DataType::PlayerRotation(90.0, 1),
+       vec!(3, 0, 0, 0, 0, 0, 0, 0, 0, 128, 86, 64, 1, 0, 0, 0, 0, 0, 0, 0)),
+      (DataType::AddPlayer(SendDynamicObject {
+         x: 1.0, y: 2.0, z: 3.0,
+         size_x: 1.0, size_y: 1.0, size_z: 1.0,
+         hitbox_x: 1.0, hitbox_y: 3.5, hitbox_z: 1.0,
+         rotation: 180.0,
+         model: "a".to_string(),
+       }),
+       vec!(4, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 12, 64, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 128, 102, 64,
+            1, 0, 0, 0, 0, 0, 0, 0, 97)),
+      (DataType::Player(SendPlayerObjectUpdate { x: 1.0, y: 2.0, z: 3.0, rotation: 0.0, is_firing: true }, 0),
+       vec!(5, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            0, 0, 0, 0, 0, 0, 0, 0, 1,
+            0, 0, 0, 0, 0, 0, 0, 0)),
+      (DataType::RemovePlayer(3),
+       vec!(6, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0)),
+      (DataType::Enemy(SendDynamicObjectUpdate { x: 1.0, y: 2.0, z: 3.0, rotation: 0.0 }, 1),
+       vec!(8, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            0, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0)),
+      (DataType::Input(Input::LeftClick),
+       vec!(9, 0, 0, 0, 5, 0, 0, 0)),
+      (DataType::Input(Input::Interact),
+       vec!(9, 0, 0, 0, 6, 0, 0, 0)),
+      (DataType::StaticObject(SendStaticObject {
+         pos: Vector3::new(1.0, 2.0, 3.0),
+         size: Vector3::new_same(1.0),
+         hitbox_scale: Vector3::new_same(1.0),
+         rotation: Vector3::new(0.0, 90.0, 0.0),
+         model: "a".to_string(),
+         trigger: true,
+       }),
+       vec!(10, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 86, 64, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0, 97, 1)),
+      (DataType::Exit,
+       vec!(11, 0, 0, 0)),
+      (DataType::Handshake(Handshake {
+         versions: ProtocolRange::new(5, 5),
+         features: Features(3),
+       }),
+       vec!(13, 0, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0, 3, 0, 0, 0)),
+      (DataType::HandshakeAccepted(HandshakeAccepted { version: 5, features: Features(1) }),
+       vec!(14, 0, 0, 0, 5, 0, 0, 0, 1, 0, 0, 0)),
+      (DataType::Rejected(ConnectionRejection::VersionMismatch {
+         server: ProtocolRange::new(5, 5),
+         client: ProtocolRange::new(4, 4),
+       }),
+       vec!(15, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 0, 5, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0)),
+      (DataType::Rejected(ConnectionRejection::Banned),
+       vec!(15, 0, 0, 0, 1, 0, 0, 0)),
+      (DataType::Admin("t".to_string(), AdminCommand::TickRate(30)),
+       vec!(16, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 116, 4, 0, 0, 0, 30, 0, 0, 0)),
+      (DataType::AdminResponse("ok".to_string()),
+       vec!(17, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 111, 107)),
+      (DataType::Chat("hi".to_string()),
+       vec!(18, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 104, 105)),
+      (DataType::Kicked("x".to_string()),
+       vec!(19, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 120)),
+      (DataType::ServerShutdown("x".to_string()),
+       vec!(20, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 120)),
+      (DataType::ClearStaticObjects,
+       vec!(21, 0, 0, 0)),
+      (DataType::DropStaticObjects(Vector3::new(-20.0, 5.0, 20.0), Vector3::new(20.0, 15.0, 60.0)),
+       vec!(22, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 52, 192, 0, 0, 0, 0, 0, 0, 20, 64, 0, 0, 0, 0, 0, 0, 52, 64,
+            0, 0, 0, 0, 0, 0, 52, 64, 0, 0, 0, 0, 0, 0, 46, 64, 0, 0, 0, 0, 0, 0, 78, 64)),
+      (DataType::LoadSection(SendSection {
+         location: (1, -1, 2),
+         part: 1,
+         parts: 2,
+         objects: vec!(SendStaticObject {
+           pos: Vector3::new(1.0, 2.0, 3.0),
+           size: Vector3::new_same(1.0),
+           hitbox_scale: Vector3::new_same(1.0),
+           rotation: Vector3::new(0.0, 90.0, 0.0),
+           model: "a".to_string(),
+           trigger: false,
+         }),
+       }),
+       vec!(23, 0, 0, 0,
+            1, 0, 0, 0, 255, 255, 255, 255, 2, 0, 0, 0,
+            1, 0, 0, 0, 2, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 86, 64, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0, 97, 0)),
+      (DataType::UnloadSection(1, -1, 2),
+       vec!(24, 0, 0, 0, 1, 0, 0, 0, 255, 255, 255, 255, 2, 0, 0, 0)),
+      (DataType::AddInteractive(SendInteractive {
+         kind: InteractiveKind::Switch,
+         pos: Vector3::new(1.0, 2.0, 3.0),
+         size: Vector3::new_same(1.0),
+         rotation: Vector3::new(0.0, 90.0, 0.0),
+         active: true,
+       }),
+       vec!(25, 0, 0, 0, 1, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 86, 64, 0, 0, 0, 0, 0, 0, 0, 0,
+            1)),
+      (DataType::InteractiveState(3, true),
+       vec!(26, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1)),
+      (DataType::ClearInteractives,
+       vec!(27, 0, 0, 0)),
+      (DataType::AddPlatform(SendPlatform {
+         path: vec!(Vector3::new(1.0, 2.0, 3.0), Vector3::new_same(1.0)),
+         size: Vector3::new_same(1.0),
+         rotation: Vector3::new(0.0, 90.0, 0.0),
+         model: "a".to_string(),
+         speed: 2.0,
+         hazard: Some(1),
+         pos: Vector3::new(1.0, 2.0, 3.0),
+         next: 1,
+       }),
+       vec!(28, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 240, 63,
+            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 128, 86, 64, 0, 0, 0, 0, 0, 0, 0, 0,
+            1, 0, 0, 0, 0, 0, 0, 0, 97, 0, 0, 0, 0, 0, 0, 0, 64, 1, 1, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            1, 0, 0, 0, 0, 0, 0, 0)),
+      (DataType::PlatformState(3, Vector3::new(1.0, 2.0, 3.0), 1),
+       vec!(29, 0, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0,
+            0, 0, 0, 0, 0, 0, 240, 63, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 0, 0, 0, 8, 64,
+            1, 0, 0, 0, 0, 0, 0, 0)),
+      (DataType::ClearPlatforms,
+       vec!(30, 0, 0, 0)),
+      (DataType::SectionLoaded(1, -1, 2),
+       vec!(31, 0, 0, 0, 1, 0, 0, 0, 255, 255, 255, 255, 2, 0, 0, 0)),
+    )
+  }
+
+  #[test]
+  fn every_supported_version_has_golden_messages() {
+    for version in MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION {
+      assert!(golden_messages(version).is_some(), "no golden messages for version {}", version);
+    }
+  }
+
+  // If this fails the wire format changed, bump PROTOCOL_VERSION and add golden
+  // messages for it rather than editing an older version's to match.
+  #[test]
+  fn golden_messages_every_supported_version() {
+    for version in MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION {
+      for (message, bytes) in golden_messages(version).unwrap_or_default() {
+        assert_eq!(message.serialise().unwrap(), bytes, "version {} encoding of {:?}", version, message);
+        assert_eq!(DataType::deserialise(&bytes).unwrap(), message);
+      }
+    }
+  }
+}
@@ -0,0 +1,243 @@
+use crate::{GenericObject, CollisionGrid, CollisionInfo, Vector3};
+use crate::narrow_phase;
+
+// Kinds of object, combined the same way as protocol Features. Queries filter on
+// them and collisions use them as layers and masks.
+#[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct Categories(pub u32);
+
+impl Categories {
+  pub const NONE: Categories = Categories(0);
+  pub const PLAYERS: Categories = Categories(1 << 0);
+  pub const ENEMIES: Categories = Categories(1 << 1);
+  pub const PLAYER_BULLETS: Categories = Categories(1 << 2);
+  pub const ENEMY_BULLETS: Categories = Categories(1 << 3);
+  pub const STATIC: Categories = Categories(1 << 4);
+  pub const TRIGGERS: Categories = Categories(1 << 5);
+  // Only used to tell which Vec an EntityId is in, interactives collide as STATIC or TRIGGERS
+  pub const INTERACTIVES: Categories = Categories(1 << 6);
+  // The same for moving platforms, which collide as STATIC
+  pub const PLATFORMS: Categories = Categories(1 << 7);
+  pub const ALL: Categories = Categories(!0);
+  
+  pub fn with(self, other: Categories) -> Categories {
+    Categories(self.0 | other.0)
+  }
+  
+  pub fn without(self, other: Categories) -> Categories {
+    Categories(self.0 & !other.0)
+  }
+  
+  pub fn contains(self, other: Categories) -> bool {
+    self.0 & other.0 == other.0
+  }
+  
+  pub fn intersects(self, other: Categories) -> bool {
+    self.0 & other.0 != 0
+  }
+}
+
+// The category an object is in and its index in that category's Vec
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Hash)]
+pub struct EntityId {
+  pub category: Categories,
+  pub index: usize,
+}
+
+impl EntityId {
+  pub fn new(category: Categories, index: usize) -> EntityId {
+    EntityId {
+      category,
+      index,
+    }
+  }
+}
+
+// For raycasts distance is along the ray, the normal points out of the surface
+// that was hit and point is where. For overlaps distance is from the centre of
+// the query shape to the object's position and the normal is the way to push the
+// object out of the query shape.
+#[derive(PartialEq, Debug, Clone)]
+pub struct QueryHit {
+  pub entity: EntityId,
+  pub distance: f64,
+  pub normal: Vector3,
+  pub point: Vector3,
+}
+
+// One category's objects, with their grid when they have one
+pub struct QuerySet<'a> {
+  pub category: Categories,
+  pub objects: &'a Vec<Box<dyn GenericObject>>,
+  pub grid: Option<&'a CollisionGrid>,
+}
+
+impl<'a> QuerySet<'a> {
+  pub fn new(category: Categories, objects: &'a Vec<Box<dyn GenericObject>>, grid: Option<&'a CollisionGrid>) -> QuerySet<'a> {
+    QuerySet {
+      category,
+      objects,
+      grid,
+    }
+  }
+  
+  // Things move every tick so sets without a grid are checked one by one
+  fn candidates(&self, bounds: &CollisionInfo) -> Vec<usize> {
+    match self.grid {
+      Some(grid) => grid.query(bounds).into_iter().filter(|i| *i < self.objects.len()).collect(),
+      None => (0..self.objects.len()).collect(),
+    }
+  }
+}
+
+fn centre_of(shape: &CollisionInfo) -> Vector3 {
+  match shape {
+    CollisionInfo::AABB(pos, _, _) => pos.clone(),
+    CollisionInfo::Sphere(sphere) => Vector3::new(sphere.x, sphere.y, sphere.z),
+    CollisionInfo::Point(p) => p.clone(),
+    CollisionInfo::Compound(parts) => {
+      let sum = parts.iter().fold(Vector3::new_same(0.0), |sum, (part, _)| sum.add(&centre_of(part)));
+      sum.scale(1.0 / parts.len().max(1) as f64)
+    },
+  }
+}
+
+// Nearest thing along the ray within max_distance, anything the ray starts inside
+// is hit at distance 0
+pub fn raycast(sets: &[QuerySet], origin: &Vector3, direction: &Vector3, max_distance: f64, filter: Categories) -> Option<QueryHit> {
+  let length = direction.magnitude();
+  if length == 0.0 || max_distance <= 0.0 {
+    return None;
+  }
+  
+  let end = origin.add(&direction.scale(max_distance/length));
+  let no_size = Vector3::new_same(0.0);
+  let centre = origin.add(&end).scale(0.5);
+  let d = end.sub(origin);
+  let bounds = CollisionInfo::AABB(centre, Vector3::new(d.x.abs(), d.y.abs(), d.z.abs()), narrow_phase::identity());
+  
+  let mut nearest: Option<QueryHit> = None;
+  for set in sets.iter().filter(|s| filter.contains(s.category)) {
+    for i in set.candidates(&bounds) {
+      if let Some((t, contact)) = narrow_phase::sweep(origin, &end, &no_size, &set.objects[i].collision_data()) {
+        let distance = t*max_distance;
+        if nearest.as_ref().map(|hit| distance < hit.distance).unwrap_or(true) {
+          nearest = Some(QueryHit {
+            entity: EntityId::new(set.category, i),
+            distance,
+            normal: contact.normal,
+            point: contact.point,
+          });
+        }
+      }
+    }
+  }
+  
+  nearest
+}
+
+// Everything overlapping the shape, closest first
+pub fn overlap(sets: &[QuerySet], shape: &CollisionInfo, filter: Categories) -> Vec<QueryHit> {
+  let centre = centre_of(shape);
+  
+  let mut hits = Vec::new();
+  for set in sets.iter().filter(|s| filter.contains(s.category)) {
+    for i in set.candidates(shape) {
+      if let Some(contact) = narrow_phase::contact(shape, &set.objects[i].collision_data()) {
+        hits.push(QueryHit {
+          entity: EntityId::new(set.category, i),
+          distance: set.objects[i].position().sub(&centre).magnitude(),
+          normal: contact.normal,
+          point: contact.point,
+        });
+      }
+    }
+  }
+  
+  hits.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(std::cmp::Ordering::Equal));
+  hits
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Section, Enemy, Vector4, SECTION_SIZE};
+  
+  // Static objects, their grid and the enemies
+  type Scene = (Vec<Box<dyn GenericObject>>, CollisionGrid, Vec<Box<dyn GenericObject>>);
+  
+  fn scene() -> Scene {
+    // Floor top at 5.5, wall face at x = 19
+    let statics = Section::new(0, 0, SECTION_SIZE).floor().left_wall().static_objects();
+    let grid = CollisionGrid::build(SECTION_SIZE, &statics);
+    let enemies = vec!(
+      Box::new(Enemy::new(Vector3::new(10.0, 7.5, 0.0), Vector3::new_same(2.0), "enemy".to_string())) as Box<dyn GenericObject>,
+      Box::new(Enemy::new(Vector3::new(-6.0, 7.5, 3.0), Vector3::new_same(2.0), "enemy".to_string())) as Box<dyn GenericObject>,
+    );
+    
+    (statics, grid, enemies)
+  }
+  
+  fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 1e-9
+  }
+  
+  #[test]
+  fn raycasts_find_the_nearest_hit() {
+    let (statics, grid, enemies) = scene();
+    let sets = [QuerySet::new(Categories::STATIC, &statics, Some(&grid)), QuerySet::new(Categories::ENEMIES, &enemies, None)];
+    let origin = Vector3::new(0.0, 7.5, 0.0);
+    let right = Vector3::new(1.0, 0.0, 0.0);
+    
+    let hit = raycast(&sets, &origin, &right, 100.0, Categories::ALL).unwrap();
+    assert_eq!(hit.entity, EntityId::new(Categories::ENEMIES, 0));
+    assert!(close(hit.distance, 9.0));
+    assert_eq!(hit.normal, Vector3::new(-1.0, 0.0, 0.0));
+    
+    // Looking past the enemy finds the wall
+    let hit = raycast(&sets, &origin, &right.scale(5.0), 100.0, Categories::STATIC).unwrap();
+    assert_eq!(hit.entity.category, Categories::STATIC);
+    assert!(close(hit.distance, 19.0));
+    assert!(close(hit.point.x, 19.0));
+    
+    // Down to the floor, and not far enough
+    let hit = raycast(&sets, &origin, &Vector3::new(0.0, -1.0, 0.0), 100.0, Categories::ALL).unwrap();
+    assert!(close(hit.distance, 2.0));
+    assert_eq!(hit.normal, Vector3::new(0.0, 1.0, 0.0));
+    assert_eq!(raycast(&sets, &origin, &Vector3::new(0.0, -1.0, 0.0), 1.5, Categories::ALL), None);
+    
+    assert_eq!(raycast(&sets, &origin, &right, 100.0, Categories::PLAYERS), None);
+    assert_eq!(raycast(&sets, &origin, &Vector3::new_same(0.0), 100.0, Categories::ALL), None);
+  }
+  
+  #[test]
+  fn overlaps_are_sorted_and_filtered() {
+    let (statics, grid, enemies) = scene();
+    let sets = [QuerySet::new(Categories::STATIC, &statics, Some(&grid)), QuerySet::new(Categories::ENEMIES, &enemies, None)];
+    
+    let blast = CollisionInfo::Sphere(Vector4::new(0.0, 7.5, 0.0, 12.0));
+    let hits = overlap(&sets, &blast, Categories::ENEMIES);
+    assert_eq!(hits.iter().map(|h| h.entity.index).collect::<Vec<usize>>(), vec!(1, 0));
+    assert!(close(hits[0].distance, 45.0_f64.sqrt()));
+    
+    // The floor is in range but the wall isn't
+    let hits = overlap(&sets, &blast, Categories::STATIC);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].normal, Vector3::new(0.0, -1.0, 0.0));
+    
+    let area = CollisionInfo::AABB(Vector3::new(10.0, 7.5, 0.0), Vector3::new_same(1.0), narrow_phase::identity());
+    let hits = overlap(&sets, &area, Categories::ALL.without(Categories::STATIC));
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].entity, EntityId::new(Categories::ENEMIES, 0));
+    assert!(close(hits[0].distance, 0.0));
+  }
+  
+  #[test]
+  fn categories_combine() {
+    let bullets = Categories::PLAYER_BULLETS.with(Categories::ENEMY_BULLETS);
+    assert!(bullets.contains(Categories::ENEMY_BULLETS));
+    assert!(!bullets.contains(Categories::STATIC));
+    assert!(bullets.intersects(Categories::PLAYER_BULLETS.with(Categories::STATIC)));
+    assert!(!Categories::ALL.without(bullets).intersects(bullets));
+  }
+}
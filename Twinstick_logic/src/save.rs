@@ -0,0 +1,138 @@
+use std::fs;
+use std::path::Path;
+
+use crate::{Section, ObjectData, TwinstickError};
+
+// Bump when SaveGame changes shape, old saves are refused rather than misread
+pub const SAVE_VERSION: u32 = 7;
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SaveGame {
+  pub version: u32,
+  pub section_size: f64,
+  pub sections: Vec<Section>,
+  pub enemies: Vec<ObjectData>,
+  pub players: Vec<ObjectData>,
+  pub enemy_tick: f32,
+}
+
+impl SaveGame {
+  pub fn to_bytes(&self) -> Result<Vec<u8>, TwinstickError> {
+    bincode::serialize(&self).map_err(TwinstickError::Serialise)
+  }
+  
+  pub fn from_bytes(bytes: &[u8]) -> Result<SaveGame, TwinstickError> {
+    let save: SaveGame = bincode::deserialize(bytes).map_err(TwinstickError::Deserialise)?;
+    if save.version != SAVE_VERSION {
+      return Err(TwinstickError::IncompatibleSave(save.version));
+    }
+    
+    Ok(save)
+  }
+  
+  // Written next to the real file then renamed, so a crash mid-save keeps the last good one
+  pub fn write_to<P: AsRef<Path>>(&self, path: P) -> Result<(), TwinstickError> {
+    let path = path.as_ref();
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, self.to_bytes()?)?;
+    fs::rename(&tmp, path)?;
+    
+    Ok(())
+  }
+  
+  pub fn read_from<P: AsRef<Path>>(path: P) -> Result<SaveGame, TwinstickError> {
+    SaveGame::from_bytes(&fs::read(path)?)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{TwinstickGame, GenericObject, Character, Vector3};
+  
+  fn played_game() -> TwinstickGame {
+    let mut game = TwinstickGame::new();
+    game.add_player();
+    game.add_player();
+    game.spawn_enemy(Vector3::new(12.0, 20.0, -7.0));
+    game.update_server(1.0/60.0);
+    game
+  }
+  
+  #[test]
+  fn game_round_trips_through_a_save() {
+    let game = played_game();
+    let save = game.save();
+    let restored = TwinstickGame::from_save(SaveGame::from_bytes(&save.to_bytes().unwrap()).unwrap());
+    
+    assert_eq!(restored.save(), save);
+    assert_eq!(restored.static_objects().len(), game.static_objects().len());
+    assert_eq!(restored.enemies().len(), game.enemies().len());
+    for i in 0..game.enemies().len() {
+      assert_eq!(restored.enemies()[i].data(), game.enemies()[i].data());
+    }
+    // Players come back as they reconnect
+    assert_eq!(restored.players().len(), 0);
+  }
+  
+  #[test]
+  fn reconnecting_players_get_their_saved_state() {
+    let mut game = played_game();
+    game.add_input(1, crate::Input::W);
+    game.update_server(1.0/60.0);
+    let saved_position = game.players()[1].position().clone();
+    
+    let mut restored = TwinstickGame::from_save(game.save());
+    restored.add_player();
+    restored.add_player();
+    restored.add_player();
+    
+    assert_eq!(restored.players()[1].position(), &saved_position);
+    assert_eq!(restored.players()[2].position(), TwinstickGame::new_player().position());
+  }
+  
+  #[test]
+  fn saved_characters_go_to_whoever_reconnects_first() {
+    let mut game = played_game();
+    game.set_player(0, Character::new(Vector3::new(-10.0, 9.0, 0.0), Vector3::new_same(1.0)));
+    game.set_player(1, Character::new(Vector3::new(10.0, 9.0, 0.0), Vector3::new_same(1.0)));
+    let save = game.save();
+    
+    // Whichever client connects first is handed the first character, even if it played the second
+    let mut restored = TwinstickGame::from_save(save.clone());
+    restored.add_player();
+    assert_eq!(restored.players()[0].position().x, -10.0);
+    
+    // The one not reconnected yet is kept for the next save
+    let resaved = restored.save();
+    assert_eq!(resaved.players.len(), 2);
+    assert_eq!(resaved.players[1].pos.x, 10.0);
+    
+    let mut restored = TwinstickGame::from_save(resaved);
+    restored.add_player();
+    restored.add_player();
+    assert_eq!(restored.players()[0].position().x, -10.0);
+    assert_eq!(restored.players()[1].position().x, 10.0);
+  }
+  
+  #[test]
+  fn save_files_are_written_and_read_back() {
+    let path = std::env::temp_dir().join(format!("twinstick_save_test_{}.bin", std::process::id()));
+    let save = played_game().save();
+    save.write_to(&path).unwrap();
+    
+    assert_eq!(SaveGame::read_from(&path).unwrap(), save);
+    let _ = fs::remove_file(&path);
+  }
+  
+  #[test]
+  fn other_save_versions_are_refused() {
+    let mut save = played_game().save();
+    save.version = SAVE_VERSION + 1;
+    
+    match SaveGame::from_bytes(&save.to_bytes().unwrap()) {
+      Err(TwinstickError::IncompatibleSave(v)) => assert_eq!(v, SAVE_VERSION + 1),
+      other => panic!("expected an incompatible save, got {:?}", other),
+    }
+  }
+}
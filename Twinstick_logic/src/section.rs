@@ -3,38 +3,133 @@ use crate::{Vector3, StaticObject, GenericObject};
 const MINIMUM_THICKNESS: f64 = 1.0;
 const Y: f64 = 5.0;
 const HEIGHT: f64 = 5.0;
+const ELEVATOR_SIZE: f64 = 4.0;
+// Dropped in from just above the floor
+const ARRIVAL_HEIGHT: f64 = 2.5;
+// From one floor of the tower to the next
+pub const FLOOR_HEIGHT: f64 = 10.0;
+// Gap left in the middle of a wall by the door builders, wide enough for enemies to follow through
+pub const DOOR_WIDTH: f64 = 8.0;
 
-#[derive(Clone)]
+// Which edge of a section, named after the wall builders
+#[derive(Serialize, Deserialize, PartialEq, Eq, Hash, Debug, Clone, Copy)]
+pub enum Wall {
+  Left,
+  Right,
+  Front,
+  Back,
+}
+
+impl Wall {
+  pub const ALL: [Wall; 4] = [Wall::Left, Wall::Right, Wall::Front, Wall::Back];
+  
+  // Step to the section on the other side of this edge
+  pub fn offset(&self) -> (i32, i32) {
+    match self {
+      Wall::Left => (1, 0),
+      Wall::Right => (-1, 0),
+      Wall::Front => (0, 1),
+      Wall::Back => (0, -1),
+    }
+  }
+  
+  pub fn opposite(&self) -> Wall {
+    match self {
+      Wall::Left => Wall::Right,
+      Wall::Right => Wall::Left,
+      Wall::Front => Wall::Back,
+      Wall::Back => Wall::Front,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct Section {
   x: i32,
   z: i32,
+  // Which floor of the tower, 0 is the ground
+  level: i32,
   size: f64,
   objects: Vec<StaticObject>,
   has_floor: bool,
+  // Grows with distance from spawn, enemies spawned here are tougher for it
+  difficulty: u32,
 }
 
 impl Section {
   pub fn new(x: i32, z: i32, size: f64) -> Section {
+    Section::new_on_floor(x, 0, z, size)
+  }
+  
+  pub fn new_on_floor(x: i32, level: i32, z: i32, size: f64) -> Section {
     Section {
       x,
       z,
+      level,
       size,
       objects: Vec::new(),
       has_floor: false,
+      difficulty: 0,
     }
   }
   
+  pub fn difficulty(mut self, difficulty: u32) -> Section {
+    self.difficulty = difficulty;
+    self
+  }
+  
+  pub fn difficulty_level(&self) -> u32 {
+    self.difficulty
+  }
+  
   pub fn has_floor(&self) -> bool {
     self.has_floor
   }
   
+  pub fn location(&self) -> (i32, i32, i32) {
+    (self.x, self.level, self.z)
+  }
+  
+  pub fn level(&self) -> i32 {
+    self.level
+  }
+  
+  // Which floor something at this height is on, anything between one floor and the next counts as the lower
+  pub fn floor_at(y: f64) -> i32 {
+    ((y - Y) / FLOOR_HEIGHT).floor() as i32
+  }
+  
+  // Where the top of a floor is, what things stand on
+  pub fn floor_top(level: i32) -> f64 {
+    Y + level as f64*FLOOR_HEIGHT + MINIMUM_THICKNESS*0.5
+  }
+  
+  // Corners of the space a section's objects sit in, min inclusive and max exclusive
+  pub fn bounds(x: i32, level: i32, z: i32, size: f64) -> (Vector3, Vector3) {
+    let y = Y + level as f64*FLOOR_HEIGHT;
+    (Vector3::new((x as f64 - 0.5)*size, y, (z as f64 - 0.5)*size),
+     Vector3::new((x as f64 + 0.5)*size, y + FLOOR_HEIGHT, (z as f64 + 0.5)*size))
+  }
+  
+  // Whether a position falls in the space given by bounds
+  pub fn contains(bounds: &(Vector3, Vector3), pos: &Vector3) -> bool {
+    let (min, max) = bounds;
+    pos.x >= min.x && pos.x < max.x &&
+    pos.y >= min.y && pos.y < max.y &&
+    pos.z >= min.z && pos.z < max.z
+  }
+  
+  fn base_y(&self) -> f64 {
+    Y + self.level as f64*FLOOR_HEIGHT
+  }
+  
   pub fn static_objects(&mut self) -> Vec<Box<dyn GenericObject>> {
     //&self.objects
     let objects = self.objects.drain(..).map(|s| Box::new(s) as Box<dyn GenericObject>).collect::<Vec<Box<dyn GenericObject>>>();
     objects
   }
   
-  pub fn set_pos(&mut self, x: i32, z: i32) {
+  pub fn set_pos(&mut self, x: i32, level: i32, z: i32) {
     let new_x = x as f64*self.size;
     let new_z = z as f64*self.size;
     
@@ -42,20 +137,30 @@ impl Section {
     let old_z = self.z as f64*self.size;
     
     let diff_x = new_x - old_x;
+    let diff_y = (level - self.level) as f64*FLOOR_HEIGHT;
     let diff_z = new_z - old_z;
     
     for object in &mut self.objects {
       let old_pos = object.position();
-      object.set_position(Vector3::new(old_pos.x+diff_x, old_pos.y, old_pos.z+diff_z));
+      object.set_position(Vector3::new(old_pos.x+diff_x, old_pos.y+diff_y, old_pos.z+diff_z));
+      if let Some(target) = object.teleport_target() {
+        object.set_teleport(Vector3::new(target.x+diff_x, target.y+diff_y, target.z+diff_z));
+      }
     }
     
     self.x = x;
+    self.level = level;
     self.z = z;
   }
   
+  // Anything that isn't a floor or a wall, already in world space
+  pub fn add_object(&mut self, object: StaticObject) {
+    self.objects.push(object);
+  }
+  
   pub fn floor(mut self) -> Section {
     let x = self.x as f64*self.size;
-    let y = Y;
+    let y = self.base_y();
     let z = self.z as f64*self.size;
     let length = self.size;
     let height = MINIMUM_THICKNESS;
@@ -67,7 +172,7 @@ impl Section {
   
   pub fn left_wall(mut self) -> Section {
     let x = self.x as f64*self.size + self.size*0.5 - MINIMUM_THICKNESS*0.5;
-    let y = Y+HEIGHT*0.5;
+    let y = self.base_y()+HEIGHT*0.5;
     let z = self.z as f64*self.size;
     let length = MINIMUM_THICKNESS;
     let height = HEIGHT;
@@ -78,7 +183,7 @@ impl Section {
   
   pub fn right_wall(mut self) -> Section {
     let x = self.x as f64*self.size - self.size*0.5 + MINIMUM_THICKNESS*0.5;
-    let y = Y+HEIGHT*0.5;
+    let y = self.base_y()+HEIGHT*0.5;
     let z = self.z as f64*self.size;
     let length = MINIMUM_THICKNESS;
     let height = HEIGHT;
@@ -89,7 +194,7 @@ impl Section {
   
   pub fn back_wall(mut self) -> Section {
     let x = self.x as f64*self.size;
-    let y = Y+HEIGHT*0.5;
+    let y = self.base_y()+HEIGHT*0.5;
     let z = self.z as f64*self.size - self.size*0.5 + MINIMUM_THICKNESS*0.5;
     let length = self.size;
     let height = HEIGHT;
@@ -100,7 +205,7 @@ impl Section {
   
   pub fn front_wall(mut self) -> Section {
     let x = self.x as f64*self.size;
-    let y = Y+HEIGHT*0.5;
+    let y = self.base_y()+HEIGHT*0.5;
     let z = self.z as f64*self.size + self.size*0.5 - MINIMUM_THICKNESS*0.5;
     let length = self.size;
     let height = HEIGHT;
@@ -108,4 +213,80 @@ impl Section {
     self.objects.push(StaticObject::new(Vector3::new(x, y, z), Vector3::new(length, height, width), "unit_floor".to_string()));
     self
   }
+  
+  pub fn wall(self, wall: Wall) -> Section {
+    match wall {
+      Wall::Left => self.left_wall(),
+      Wall::Right => self.right_wall(),
+      Wall::Front => self.front_wall(),
+      Wall::Back => self.back_wall(),
+    }
+  }
+  
+  // The same wall with a DOOR_WIDTH gap in the middle
+  pub fn door(self, wall: Wall) -> Section {
+    self.wall(wall).cut_door()
+  }
+  
+  // Splits the wall just added into the two pieces either side of the door
+  fn cut_door(mut self) -> Section {
+    let wall = self.objects.pop().unwrap();
+    let pos = wall.position().clone();
+    let size = wall.data().size.clone();
+    
+    let along_x = size.x > size.z;
+    let length = if along_x { size.x } else { size.z };
+    let piece = (length - DOOR_WIDTH)*0.5;
+    for side in &[-1.0, 1.0] {
+      let offset = side*(DOOR_WIDTH + piece)*0.5;
+      let (piece_pos, piece_size) = if along_x {
+        (Vector3::new(pos.x + offset, pos.y, pos.z), Vector3::new(piece, size.y, size.z))
+      } else {
+        (Vector3::new(pos.x, pos.y, pos.z + offset), Vector3::new(size.x, size.y, piece))
+      };
+      self.objects.push(StaticObject::new(piece_pos, piece_size, wall.model().to_string()));
+    }
+    
+    self
+  }
+  
+  // Slope from this floor up to the next, rising towards the given edge. The
+  // section above needs to leave a hole for it and the one past the top edge a
+  // floor to step out onto.
+  pub fn ramp(mut self, towards: Wall) -> Section {
+    let angle = FLOOR_HEIGHT.atan2(self.size);
+    let length = self.size.hypot(FLOOR_HEIGHT);
+    // Sunk a little so the bottom doesn't leave a lip to trip on
+    let top_centre = Vector3::new(self.x as f64*self.size, Section::floor_top(self.level) - 0.1 + FLOOR_HEIGHT*0.5, self.z as f64*self.size);
+    
+    let (dx, dz) = towards.offset();
+    let (dx, dz) = (dx as f64, dz as f64);
+    let normal = Vector3::new(-dx*angle.sin(), angle.cos(), -dz*angle.sin());
+    let pos = top_centre.sub(&normal.scale(MINIMUM_THICKNESS*0.5));
+    
+    let degrees = angle.to_degrees();
+    let (size, rotation) = if dx != 0.0 {
+      (Vector3::new(length, MINIMUM_THICKNESS, self.size - MINIMUM_THICKNESS*2.0), Vector3::new(0.0, 0.0, dx*degrees))
+    } else {
+      (Vector3::new(self.size - MINIMUM_THICKNESS*2.0, MINIMUM_THICKNESS, length), Vector3::new(-dz*degrees, 0.0, 0.0))
+    };
+    self.objects.push(StaticObject::new(pos, size, "unit_floor".to_string()).rotation(rotation));
+    self
+  }
+  
+  // A pad to one side of the middle that moves players the given number of floors,
+  // they arrive in the middle of the section there so they don't land on its pad.
+  // Pads going up are on the left and going down on the right.
+  pub fn elevator(mut self, floors: i32) -> Section {
+    let x = self.x as f64*self.size;
+    let z = self.z as f64*self.size;
+    let top = Section::floor_top(self.level);
+    let pad = Vector3::new(x + self.size*0.25*(floors.signum() as f64), top + ELEVATOR_SIZE*0.25, z);
+    let target = Vector3::new(x, Section::floor_top(self.level + floors) + ARRIVAL_HEIGHT, z);
+    
+    self.objects.push(StaticObject::new(pad, Vector3::new(ELEVATOR_SIZE, ELEVATOR_SIZE*0.5, ELEVATOR_SIZE), "unit_floor".to_string())
+                        .trigger()
+                        .teleport(target));
+    self
+  }
 }
@@ -1,39 +1,68 @@
-use crate::Section;
+use std::collections::HashMap;
 
+use crate::{Section, Dungeon, DungeonCell};
+
+// Dungeon chunks kept around once generated, sections are loaded a few at a time
+// from the same handful of chunks around each player
+const CACHED_CHUNKS: usize = 64;
+
+// Fills in everywhere the map doesn't say, either by repeating a small block of
+// sections or by generating a dungeon
 pub struct SectionLayout {
   size_x: u32,
   size_y: u32,
   section_size: f64,
   sections: Vec<Section>,
+  dungeon: Option<Dungeon>,
+  chunks: HashMap<(i32, i32, i32), Vec<Option<DungeonCell>>>,
 }
 
 impl SectionLayout {
-  pub fn grid(section_size: f64) -> SectionLayout {
-    let mut sections = Vec::new();
-    
-    sections.push(Section::new( 0, 0, section_size));
-    sections.push(Section::new(-1, 0, section_size).floor().left_wall().right_wall());
-    sections.push(Section::new(-2, 0, section_size));
-    
-    sections.push(Section::new( 0, 1, section_size).floor().front_wall().back_wall());
-    sections.push(Section::new(-1, 1, section_size).floor());
-    sections.push(Section::new(-2, 1, section_size).floor().front_wall().back_wall());
-    
-    sections.push(Section::new( 0, 2, section_size));
-    sections.push(Section::new(-1, 2, section_size).floor().left_wall().right_wall());
-    sections.push(Section::new(-2, 2, section_size));
-    
+  // Sections go row by row, size_x to a row, see index
+  pub fn new(size_x: u32, size_y: u32, section_size: f64, sections: Vec<Section>) -> SectionLayout {
     SectionLayout {
-      size_x: 3,
-      size_y: 3,
+      size_x,
+      size_y,
       section_size,
       sections,
+      dungeon: None,
+      chunks: HashMap::new(),
     }
   }
   
-  pub fn get_section(&self, x: i32, y: i32) -> Section {
-    let idx = (x.abs()%self.size_x as i32) as usize + self.size_y as usize*(y.abs()%self.size_y as i32) as usize;
+  pub fn dungeon(dungeon: Dungeon, section_size: f64) -> SectionLayout {
+    SectionLayout {
+      size_x: 0,
+      size_y: 0,
+      section_size,
+      sections: Vec::new(),
+      dungeon: Some(dungeon),
+      chunks: HashMap::new(),
+    }
+  }
+  
+  pub fn index(size_x: u32, size_y: u32, x: i32, y: i32) -> usize {
+    (x.abs()%size_x as i32) as usize + size_x as usize*(y.abs()%size_y as i32) as usize
+  }
+  
+  pub fn section_size(&self) -> f64 {
+    self.section_size
+  }
+  
+  // Tiles only cover the ground floor, there is nothing above or below them
+  pub fn get_section(&mut self, x: i32, level: i32, y: i32) -> Section {
+    if let Some(dungeon) = &self.dungeon {
+      let (cx, cz) = dungeon.chunk_of(x, y);
+      if self.chunks.len() >= CACHED_CHUNKS && !self.chunks.contains_key(&(cx, level, cz)) {
+        self.chunks.clear();
+      }
+      let chunk = self.chunks.entry((cx, level, cz)).or_insert_with(|| dungeon.chunk(cx, level, cz));
+      return dungeon.section_in_chunk(chunk, x, level, y, self.section_size);
+    }
+    if level != 0 {
+      return Section::new_on_floor(x, level, y, self.section_size);
+    }
     
-    self.sections[idx].clone()
+    self.sections[SectionLayout::index(self.size_x, self.size_y, x, y)].clone()
   }
 }
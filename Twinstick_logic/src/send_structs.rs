@@ -1,18 +1,109 @@
 
-use crate::Vector3;
-use crate::StaticObject;
+use crate::{Vector3, StaticObject, Interactive, InteractiveKind, MovingPlatform, DataType, TwinstickError, BUFFER_SIZE};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct SendStaticObject {
   pub pos: Vector3,
   pub size: Vector3,
   pub hitbox_scale: Vector3,
+  pub rotation: Vector3,
   pub model: String,
+  // Elevator pads and the like, walked through rather than stood on
+  pub trigger: bool,
 }
 
 impl SendStaticObject {
   pub fn to_static_object(&self) -> StaticObject {
-    StaticObject::new(self.pos.clone(), self.size.clone(), self.model.to_string()).hitbox_scale(self.hitbox_scale.clone())
+    let object = StaticObject::new(self.pos.clone(), self.size.clone(), self.model.to_string()).hitbox_scale(self.hitbox_scale.clone()).rotation(self.rotation.clone());
+    if self.trigger {
+      object.trigger()
+    } else {
+      object
+    }
+  }
+}
+
+// Only what clients need to draw and collide with it, doors are opened by the server
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SendInteractive {
+  pub kind: InteractiveKind,
+  pub pos: Vector3,
+  pub size: Vector3,
+  pub rotation: Vector3,
+  pub active: bool,
+}
+
+impl SendInteractive {
+  pub fn to_interactive(&self) -> Interactive {
+    let mut interactive = Interactive::new(self.kind, self.pos.clone(), self.size.clone()).rotation(self.rotation.clone());
+    interactive.set_active(self.active);
+    interactive
+  }
+}
+
+// Everything clients need to move it along its path themselves between PlatformStates
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SendPlatform {
+  pub path: Vec<Vector3>,
+  pub size: Vector3,
+  pub rotation: Vector3,
+  pub model: String,
+  pub speed: f64,
+  // Damage a second, if it is a hazard
+  pub hazard: Option<i32>,
+  pub pos: Vector3,
+  pub next: usize,
+}
+
+impl SendPlatform {
+  pub fn to_platform(&self) -> Result<MovingPlatform, TwinstickError> {
+    let mut platform = MovingPlatform::new(self.path.clone(), self.size.clone(), self.speed, self.model.to_string())?.rotation(self.rotation.clone());
+    if let Some(damage) = self.hazard {
+      platform = platform.hazard(damage);
+    }
+    platform.sync(self.pos.clone(), self.next);
+    Ok(platform)
+  }
+}
+
+// One piece of a section's geometry, sections with more than fits in a packet are split
+// across several and put back together by SectionAssembler
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct SendSection {
+  pub location: (i32, i32, i32),
+  pub part: u32,
+  pub parts: u32,
+  pub objects: Vec<SendStaticObject>,
+}
+
+impl SendSection {
+  // As few pieces as will each fit in BUFFER_SIZE once wrapped in a LoadSection, a
+  // section with nothing in it is still sent so the client knows it is there
+  pub fn fragments(location: (i32, i32, i32), objects: Vec<SendStaticObject>) -> Vec<SendSection> {
+    let empty = SendSection { location, part: 0, parts: 1, objects: Vec::new() };
+    let header = bincode::serialized_size(&DataType::LoadSection(empty)).unwrap_or(0) as usize;
+    
+    let mut pieces: Vec<Vec<SendStaticObject>> = vec!(Vec::new());
+    let mut used = header;
+    for object in objects {
+      let size = bincode::serialized_size(&object).unwrap_or(0) as usize;
+      if used + size > BUFFER_SIZE && !pieces.last().unwrap().is_empty() {
+        pieces.push(Vec::new());
+        used = header;
+      }
+      used += size;
+      pieces.last_mut().unwrap().push(object);
+    }
+    
+    let parts = pieces.len() as u32;
+    pieces.into_iter().enumerate().map(|(i, objects)| {
+      SendSection {
+        location,
+        part: i as u32,
+        parts,
+        objects,
+      }
+    }).collect()
   }
 }
 
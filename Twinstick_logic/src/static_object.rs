@@ -1,20 +1,38 @@
 pub use bincode::{deserialize, serialize};
 
-use crate::{DrawCall, ObjectData, CollisionInfo, GenericObject, Vector3};
-use crate::{FPS_60};
+use crate::{DrawCall, ObjectData, GenericObject, Vector3, Contact, Categories};
+
+// Surfaces facing further up than this can be stood on, a little steeper than 45 degrees
+const WALKABLE_SLOPE: f64 = 0.7;
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct StaticObject {
   data: ObjectData,
+  teleport: Option<Vector3>,
 }
 
 impl StaticObject {
   pub fn new(pos: Vector3, size: Vector3, model: String) -> StaticObject {
     StaticObject {
-      data: ObjectData::new(pos, size, model).static_physics(),
+      data: ObjectData::new(pos, size, model).static_physics().layer(Categories::STATIC),
+      teleport: None,
     }
   }
   
+  // Reports what walks into it without getting in the way
+  pub fn trigger(mut self) -> StaticObject {
+    self.data = self.data.layer(Categories::TRIGGERS).trigger();
+    
+    self
+  }
+  
+  // Only does anything on a trigger
+  pub fn teleport(mut self, target: Vector3) -> StaticObject {
+    self.teleport = Some(target);
+    
+    self
+  }
+  
   pub fn size(mut self, scale: Vector3) -> StaticObject {
     self.data.size = scale;
     self
@@ -41,54 +59,52 @@ impl GenericObject for StaticObject {
     &mut self.data
   }
   
-  fn collided_with_static_object(&mut self, static_object: &mut Box<dyn GenericObject>) {
-    
-  }
-  
-  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>) {
-    let static_collision_info = &self.collision_data();
-    let player_collision_info = &dynamic_object.collision_data();
-    
-    match static_collision_info {
-      CollisionInfo::AABB(static_pos, static_size, _static_rotation) => {
-         match player_collision_info {
-          CollisionInfo::AABB(dyn_pos, dyn_size, _dyn_rotation) => {
-            // Check if dyn collision happens on the lower 25% of the collision model
-            // if so push to top
-            let object_pos_x = dynamic_object.position().x;
-            let object_pos_z = dynamic_object.position().z;
-            
-            if static_pos.y+static_size.y*0.5 < dyn_pos.y-dyn_size.y*0.25 {
-              // player fell ontop
-              dynamic_object.set_position(Vector3::new(object_pos_x, static_pos.y+static_size.y*0.5+dyn_size.y*0.5, object_pos_z));
-              dynamic_object.mut_data().grounded = true;
-            } else if static_pos.y-static_size.y*0.25 > dyn_pos.y+dyn_size.y*0.5 {
-              // player coming from bototm
-              dynamic_object.set_position(Vector3::new(object_pos_x, static_pos.y-static_size.y*0.5-dyn_size.y*0.5, object_pos_z));
-            } else { // TODO: Real Physics
-              let object_y = dynamic_object.position().y;
-              
-              dynamic_object.physics_update(-FPS_60);
-              let pos_x = dynamic_object.position().x;
-              let pos_z = dynamic_object.position().z;
-              dynamic_object.set_position(Vector3::new(pos_x, object_y, pos_z));
-            }
-            
-          },
-          CollisionInfo::Sphere(_dyn_pos_rad) => {
-            //dynamic_object.set_position(Vector3::new(dyn_pos.x, pos_rad.y+pos_rad.w, dyn_pos.z));
-          },
-          CollisionInfo::Point(_dyn_pos) => {
-            //dynamic_object.set_position(Vector3::new(dyn_pos.x, pos.y, dyn_pos.z));
-          }
-        }
-      },
-      CollisionInfo::Sphere(_static_pos_rad) => {
-      
-      },
-      CollisionInfo::Point(_static_pos) => {
-        
+  fn teleport_target(&self) -> Option<Vector3> {
+    self.teleport.clone()
+  }
+  
+  fn set_teleport(&mut self, target: Vector3) {
+    self.teleport = Some(target);
+  }
+  
+  fn collided_with_static_object(&mut self, _static_object: &mut Box<dyn GenericObject>, _contact: &Contact) {
+    
+  }
+  
+  fn collided_with_dynamic_object(&mut self, dynamic_object: &mut Box<dyn GenericObject>, contact: &Contact) {
+    if contact.normal.y.abs() < 1e-6 {
+      // Walked into the side, anything low enough is stepped up onto instead
+      let top = self.position().y + self.hitbox_size().y*0.5;
+      let feet = dynamic_object.position().y - dynamic_object.hitbox_size().y*0.5;
+      let rise = top - feet;
+      if rise > 0.0 && rise <= dynamic_object.data().step_height && dynamic_object.data().vel.y <= 0.0 {
+        dynamic_object.mut_data().pos.y += rise;
+        dynamic_object.mut_data().vel.y = 0.0;
+        dynamic_object.mut_data().grounded = true;
+        return;
+      }
+    }
+    
+    if contact.normal.y >= WALKABLE_SLOPE {
+      // Landed on top, lifting straight up keeps things from sliding down ramps
+      dynamic_object.mut_data().pos.y += contact.depth / contact.normal.y;
+      if dynamic_object.data().vel.y < 0.0 {
+        dynamic_object.mut_data().vel.y = 0.0;
       }
+      dynamic_object.mut_data().grounded = true;
+      return;
+    }
+    
+    // The normal points out of this object towards the dynamic one, moving it
+    // by the contact depth along that is always enough to separate them
+    let pos = dynamic_object.position().add(&contact.separation());
+    dynamic_object.set_position(pos);
+    
+    // Only the part of the velocity going into the surface is lost, the rest slides along it
+    let into = dynamic_object.data().vel.dot(&contact.normal);
+    if into < 0.0 {
+      let vel = dynamic_object.data().vel.sub(&contact.normal.scale(into));
+      dynamic_object.mut_data().vel = vel;
     }
   }
   
@@ -104,3 +120,273 @@ impl GenericObject for StaticObject {
     
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Section, Wall, Character, Enemy, Input, CollisionGrid, CollisionMatrix, TwinstickGame, TickObjects, SECTION_SIZE, FLOOR_HEIGHT};
+  
+  const FLOOR_TOP: f64 = 5.5;
+  // Half of the characters 3.5 tall hitbox
+  const STANDING: f64 = FLOOR_TOP + 1.75;
+  // Ground lost while getting up to speed, a little more on the first tick before landing
+  const SPIN_UP: f64 = 0.7;
+  
+  struct Scene {
+    players: Vec<Box<dyn GenericObject>>,
+    static_objects: Vec<Box<dyn GenericObject>>,
+    grid: CollisionGrid,
+  }
+  
+  impl Scene {
+    fn new(mut sections: Vec<Section>, extra: Vec<StaticObject>, start: Vector3) -> Scene {
+      let mut static_objects = Vec::new();
+      for section in &mut sections {
+        static_objects.append(&mut section.static_objects());
+      }
+      for object in extra {
+        static_objects.push(Box::new(object) as Box<dyn GenericObject>);
+      }
+      
+      let mut grid = CollisionGrid::new(SECTION_SIZE);
+      for object in &static_objects {
+        grid.insert(&object.collision_data());
+      }
+      
+      Scene {
+        players: vec!(Box::new(Character::new(start, Vector3::new_same(1.0))) as Box<dyn GenericObject>),
+        static_objects,
+        grid,
+      }
+    }
+    
+    fn run(&mut self, inputs: &[Input], seconds: f64) {
+      let delta_time = 1.0/60.0;
+      for _ in 0..(seconds/delta_time).round() as usize {
+        for input in inputs {
+          self.players[0].add_input(input.clone());
+        }
+        let objects = TickObjects {
+          players: &mut self.players,
+          enemies: &mut Vec::new(),
+          player_bullets: &mut Vec::new(),
+          enemy_bullets: &mut Vec::new(),
+          static_objects: &mut self.static_objects,
+          static_grid: &self.grid,
+          interactives: &mut Vec::new(),
+          platforms: &mut Vec::new(),
+          dynamic_objects: &mut Vec::new(),
+        };
+        TwinstickGame::update(objects, &CollisionMatrix::new(), Some(0), delta_time);
+      }
+    }
+    
+    fn player(&self) -> &dyn GenericObject {
+      self.players[0].as_ref()
+    }
+  }
+  
+  fn close(a: f64, b: f64) -> bool {
+    (a - b).abs() < 0.05
+  }
+  
+  fn block(x: f64, z: f64, height: f64) -> StaticObject {
+    StaticObject::new(Vector3::new(x, FLOOR_TOP + height*0.5, z), Vector3::new(4.0, height, 4.0), "unit_floor".to_string())
+  }
+  
+  #[test]
+  fn lands_and_stays_on_the_floor() {
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), Vec::new(), Vector3::new(0.0, 10.0, 0.0));
+    scene.run(&[], 2.0);
+    
+    assert!(close(scene.player().position().y, STANDING));
+    assert!(scene.player().data().grounded);
+    assert!(scene.player().data().vel.y <= 0.0);
+  }
+  
+  #[test]
+  fn slides_along_a_wall() {
+    let section = Section::new(0, 0, SECTION_SIZE).floor().back_wall();
+    let mut scene = Scene::new(vec!(section), Vec::new(), Vector3::new(-10.0, STANDING, 0.0));
+    // W heads for the back wall, A keeps running along it
+    scene.run(&[Input::W, Input::A], 2.0);
+    
+    let pos = scene.player().position();
+    assert!(close(pos.z, -18.5), "z {}", pos.z);
+    // Running diagonally takes longer to get going
+    assert!(close(pos.x, 14.0 - SPIN_UP*1.4), "x {}", pos.x);
+    assert!(close(pos.y, STANDING));
+  }
+  
+  #[test]
+  fn stops_in_a_corner() {
+    let section = Section::new(0, 0, SECTION_SIZE).floor().back_wall().left_wall();
+    let mut scene = Scene::new(vec!(section), Vec::new(), Vector3::new(0.0, STANDING, 0.0));
+    scene.run(&[Input::W, Input::A], 3.0);
+    
+    let pos = scene.player().position();
+    assert!(close(pos.x, 18.5), "x {}", pos.x);
+    assert!(close(pos.z, -18.5), "z {}", pos.z);
+    assert!(close(pos.y, STANDING));
+  }
+  
+  #[test]
+  fn walks_across_section_seams() {
+    let sections = vec!(Section::new(0, 0, SECTION_SIZE).floor(), Section::new(1, 0, SECTION_SIZE).floor());
+    let mut scene = Scene::new(sections, Vec::new(), Vector3::new(10.0, STANDING, 0.0));
+    scene.run(&[Input::A], 2.0);
+    
+    let pos = scene.player().position();
+    assert!(close(pos.x, 34.0 - SPIN_UP), "x {}", pos.x);
+    assert!(close(pos.y, STANDING));
+  }
+  
+  #[test]
+  fn steps_up_small_ledges() {
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(block(6.0, 0.0, 0.5)), Vector3::new(0.0, STANDING, 0.0));
+    scene.run(&[Input::A], 0.5);
+    
+    let pos = scene.player().position();
+    assert!(close(pos.x, 6.0 - SPIN_UP), "x {}", pos.x);
+    assert!(close(pos.y, STANDING + 0.5), "y {}", pos.y);
+    assert!(scene.player().data().grounded);
+  }
+  
+  #[test]
+  fn knee_high_ledges_block() {
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(block(6.0, 0.0, 1.5)), Vector3::new(0.0, STANDING, 0.0));
+    scene.run(&[Input::A], 1.0);
+    
+    let pos = scene.player().position();
+    assert!(close(pos.x, 3.5), "x {}", pos.x);
+    assert!(close(pos.y, STANDING));
+  }
+  
+  #[test]
+  fn step_height_is_configurable() {
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(block(6.0, 0.0, 0.5)), Vector3::new(0.0, STANDING, 0.0));
+    scene.players[0].mut_data().step_height = 0.25;
+    scene.run(&[Input::A], 1.0);
+    
+    assert!(close(scene.player().position().x, 3.5));
+    
+    let mut scene_high = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(block(6.0, 0.0, 1.5)), Vector3::new(0.0, STANDING, 0.0));
+    scene_high.players[0].mut_data().step_height = 1.5;
+    scene_high.run(&[Input::A], 0.5);
+    
+    assert!(close(scene_high.player().position().y, STANDING + 1.5));
+  }
+  
+  // Slope rising towards +x whose top surface starts just under the floor at x = 2
+  fn ramp(degrees: f64, length: f64) -> StaticObject {
+    let r = degrees.to_radians();
+    let top_centre = Vector3::new(2.0 + length*0.5*r.cos(), FLOOR_TOP - 0.1 + length*0.5*r.sin(), 0.0);
+    let pos = top_centre.sub(&Vector3::new(-r.sin(), r.cos(), 0.0).scale(0.5));
+    StaticObject::new(pos, Vector3::new(length, 1.0, 6.0), "flat_ramp".to_string()).rotation(Vector3::new(0.0, 0.0, degrees))
+  }
+  
+  #[test]
+  fn walks_up_ramps() {
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(ramp(30.0, 12.0)), Vector3::new(-2.0, STANDING, 0.0));
+    scene.run(&[Input::A], 0.75);
+    
+    // Resting on the uphill corner of its feet
+    let pos = scene.player().position().clone();
+    let surface = FLOOR_TOP - 0.1 + (pos.x + 0.5 - 2.0)*30.0_f64.to_radians().tan();
+    assert!(close(pos.x, 7.0 - SPIN_UP), "x {}", pos.x);
+    assert!((pos.y - (surface + 1.75)).abs() < 0.2, "y {} surface {}", pos.y, surface);
+    assert!(scene.player().data().grounded);
+    
+    // And stays put when let go, once it has come to a stop
+    scene.run(&[], 0.2);
+    let pos = scene.player().position().clone();
+    scene.run(&[], 1.0);
+    assert!(close(scene.player().position().x, pos.x));
+    assert!(close(scene.player().position().y, pos.y));
+  }
+  
+  #[test]
+  fn steep_ramps_cant_be_climbed() {
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(ramp(60.0, 12.0)), Vector3::new(-2.0, STANDING, 0.0));
+    scene.run(&[Input::A], 2.0);
+    
+    assert!(scene.player().position().x < 3.0, "x {}", scene.player().position().x);
+    assert!(scene.player().position().y < STANDING + 1.0, "y {}", scene.player().position().y);
+  }
+  
+  #[test]
+  fn section_ramps_lead_up_to_the_next_floor() {
+    for (wall, input) in &[(Wall::Left, Input::A), (Wall::Right, Input::D), (Wall::Front, Input::S), (Wall::Back, Input::W)] {
+      let (dx, dz) = wall.offset();
+      let sections = vec!(
+        Section::new(0, 0, SECTION_SIZE).floor(),
+        Section::new(dx, dz, SECTION_SIZE).ramp(*wall),
+        Section::new_on_floor(dx*2, 1, dz*2, SECTION_SIZE).floor(),
+      );
+      let mut scene = Scene::new(sections, Vec::new(), Vector3::new(0.0, STANDING, 0.0));
+      scene.run(std::slice::from_ref(input), 6.0);
+      
+      // Past the top of the ramp and standing on the floor above
+      let pos = scene.player().position();
+      let along = pos.x*dx as f64 + pos.z*dz as f64;
+      assert!(along > 60.0, "{:?} got {} along", wall, along);
+      assert!(close(pos.y, STANDING + FLOOR_HEIGHT), "{:?} y {}", wall, pos.y);
+      assert_eq!(Section::floor_at(pos.y), 1);
+      assert!(scene.player().data().grounded);
+    }
+  }
+  
+  #[test]
+  fn slides_along_rotated_walls() {
+    // Diagonal wall running from the back right to the front left
+    let wall = StaticObject::new(Vector3::new(0.0, 7.5, 0.0), Vector3::new(1.0, 5.0, 30.0), "unit_floor".to_string()).rotation(Vector3::new(0.0, 45.0, 0.0));
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(wall), Vector3::new(-6.0, STANDING, 0.0));
+    scene.run(&[Input::A], 1.0);
+    
+    // Pushed along the wall (the line x = z) rather than stopped dead or through it,
+    // ending half the wall's thickness plus the box's half diagonal from the line
+    let pos = scene.player().position();
+    assert!(pos.z > 1.0, "z {}", pos.z);
+    assert!((pos.z - pos.x) / 2.0_f64.sqrt() > 0.5 + 0.5_f64.sqrt() - 0.05, "x {} z {}", pos.x, pos.z);
+    assert!(close(pos.y, STANDING));
+  }
+  
+  #[test]
+  fn ceilings_stop_jumps() {
+    let ceiling = StaticObject::new(Vector3::new(0.0, 10.0, 0.0), Vector3::new(10.0, 1.0, 10.0), "unit_floor".to_string());
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), vec!(ceiling), Vector3::new(0.0, STANDING, 0.0));
+    
+    scene.run(&[Input::Space], 1.0/60.0);
+    let mut highest = scene.player().position().y;
+    for _ in 0..60 {
+      scene.run(&[], 1.0/60.0);
+      highest = highest.max(scene.player().position().y);
+    }
+    
+    // Head stays under the ceiling's bottom at 9.5
+    assert!(highest <= 9.5 - 1.75 + 0.001, "highest {}", highest);
+    assert!(close(scene.player().position().y, STANDING));
+    assert!(scene.player().data().grounded);
+  }
+  
+  #[test]
+  fn falling_out_of_the_world() {
+    // Section (0, 0) ends at x = 20 and there is nothing past it
+    let mut scene = Scene::new(vec!(Section::new(0, 0, SECTION_SIZE).floor()), Vec::new(), Vector3::new(15.0, STANDING, 0.0));
+    scene.run(&[Input::A], 1.0);
+    assert!(scene.player().position().y < STANDING - 1.0);
+    
+    // Players are put back where they last stood
+    scene.run(&[], 8.0);
+    let pos = scene.player().position();
+    assert!(pos.x > 19.5 && pos.x < 21.0, "x {}", pos.x);
+    assert!(close(pos.y, STANDING), "y {}", pos.y);
+    
+    // Enemies are gone
+    let mut enemy = Enemy::new(Vector3::new(60.0, 10.0, 0.0), Vector3::new_same(2.0), "enemy".to_string());
+    for _ in 0..8*60 {
+      enemy.update(true, 1.0/60.0);
+    }
+    assert!(enemy.is_dead());
+  }
+}
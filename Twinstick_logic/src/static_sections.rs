@@ -0,0 +1,244 @@
+use std::collections::HashMap;
+
+use crate::{GenericObject, CollisionGrid, SendSection, SendStaticObject};
+
+// Static objects grouped by the section they came from so a whole section can be
+// dropped at once. They stay in one list with a grid over it for the collision code.
+pub struct StaticSections {
+  objects: Vec<Box<dyn GenericObject>>,
+  owners: Vec<(i32, i32, i32)>,
+  // How many objects each section has, sections with none are still loaded
+  sections: HashMap<(i32, i32, i32), usize>,
+  grid: CollisionGrid,
+}
+
+impl StaticSections {
+  pub fn new(cell_size: f64) -> StaticSections {
+    StaticSections {
+      objects: Vec::new(),
+      owners: Vec::new(),
+      sections: HashMap::new(),
+      grid: CollisionGrid::new(cell_size),
+    }
+  }
+  
+  // Replaces whatever the section had before
+  pub fn insert(&mut self, location: (i32, i32, i32), objects: Vec<Box<dyn GenericObject>>) {
+    if self.sections.contains_key(&location) {
+      self.remove(&vec!(location));
+    }
+    
+    self.sections.insert(location, 0);
+    for object in objects {
+      self.push(location, object);
+    }
+  }
+  
+  // Static objects never move, so they go in the grid once as they are added
+  pub fn push(&mut self, location: (i32, i32, i32), object: Box<dyn GenericObject>) {
+    self.grid.insert(&object.collision_data());
+    self.objects.push(object);
+    self.owners.push(location);
+    *self.sections.entry(location).or_insert(0) += 1;
+  }
+  
+  pub fn remove(&mut self, locations: &Vec<(i32, i32, i32)>) {
+    for location in locations {
+      self.sections.remove(location);
+    }
+    
+    self.retain_owned(|owner, _| !locations.contains(owner));
+  }
+  
+  // Sections an object is dropped from stay loaded, even once they are empty
+  pub fn retain<F: Fn(&Box<dyn GenericObject>) -> bool>(&mut self, keep: F) {
+    self.retain_owned(|_, object| keep(object));
+    for count in self.sections.values_mut() {
+      *count = 0;
+    }
+    for owner in &self.owners {
+      *self.sections.get_mut(owner).unwrap() += 1;
+    }
+  }
+  
+  fn retain_owned<F: Fn(&(i32, i32, i32), &Box<dyn GenericObject>) -> bool>(&mut self, keep: F) {
+    let objects = self.objects.drain(..).collect::<Vec<Box<dyn GenericObject>>>();
+    let owners = self.owners.drain(..).collect::<Vec<(i32, i32, i32)>>();
+    for (object, owner) in objects.into_iter().zip(owners) {
+      if keep(&owner, &object) {
+        self.objects.push(object);
+        self.owners.push(owner);
+      }
+    }
+    
+    self.rebuild_grid();
+  }
+  
+  pub fn clear(&mut self) {
+    self.objects.clear();
+    self.owners.clear();
+    self.sections.clear();
+    self.grid.clear();
+  }
+  
+  // Needed after objects are changed through mut_objects
+  pub fn rebuild_grid(&mut self) {
+    self.grid.clear();
+    for object in &self.objects {
+      self.grid.insert(&object.collision_data());
+    }
+  }
+  
+  pub fn contains(&self, location: (i32, i32, i32)) -> bool {
+    self.sections.contains_key(&location)
+  }
+  
+  pub fn len(&self) -> usize {
+    self.sections.len()
+  }
+  
+  pub fn is_empty(&self) -> bool {
+    self.sections.is_empty()
+  }
+  
+  pub fn locations(&self) -> Vec<(i32, i32, i32)> {
+    let mut locations = self.sections.keys().cloned().collect::<Vec<(i32, i32, i32)>>();
+    locations.sort();
+    locations
+  }
+  
+  pub fn section_objects(&self, location: (i32, i32, i32)) -> Vec<&dyn GenericObject> {
+    self.objects.iter().zip(&self.owners).filter(|(_, owner)| **owner == location).map(|(object, _)| object.as_ref()).collect()
+  }
+  
+  pub fn objects(&self) -> &Vec<Box<dyn GenericObject>> {
+    &self.objects
+  }
+  
+  pub fn mut_objects(&mut self) -> &mut Vec<Box<dyn GenericObject>> {
+    &mut self.objects
+  }
+  
+  pub fn grid(&self) -> &CollisionGrid {
+    &self.grid
+  }
+  
+  pub fn objects_and_grid(&mut self) -> (&mut Vec<Box<dyn GenericObject>>, &CollisionGrid) {
+    (&mut self.objects, &self.grid)
+  }
+}
+
+// Every piece of one section, None until it arrives
+type Pieces = Vec<Option<Vec<SendStaticObject>>>;
+
+// Collects the pieces of each section as they arrive, in whatever order
+#[derive(Default)]
+pub struct SectionAssembler {
+  pending: HashMap<(i32, i32, i32), Pieces>,
+}
+
+impl SectionAssembler {
+  pub fn new() -> SectionAssembler {
+    SectionAssembler {
+      pending: HashMap::new(),
+    }
+  }
+  
+  // The whole section once its last piece is in
+  pub fn add(&mut self, section: SendSection) -> Option<((i32, i32, i32), Vec<SendStaticObject>)> {
+    if section.part >= section.parts {
+      return None;
+    }
+    
+    let parts = section.parts as usize;
+    let pieces = self.pending.entry(section.location).or_insert_with(|| vec!(None; parts));
+    // Sent again with a different split, start over
+    if pieces.len() != parts {
+      *pieces = vec!(None; parts);
+    }
+    pieces[section.part as usize] = Some(section.objects);
+    
+    if pieces.iter().any(|piece| piece.is_none()) {
+      return None;
+    }
+    
+    let pieces = self.pending.remove(&section.location).unwrap();
+    Some((section.location, pieces.into_iter().flat_map(|piece| piece.unwrap()).collect()))
+  }
+  
+  // Pieces of a section that was unloaded before it all arrived
+  pub fn forget(&mut self, location: (i32, i32, i32)) {
+    self.pending.remove(&location);
+  }
+  
+  pub fn clear(&mut self) {
+    self.pending.clear();
+  }
+  
+  pub fn pending(&self) -> usize {
+    self.pending.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::{Section, DataType, BUFFER_SIZE, SECTION_SIZE, Wall};
+  
+  fn section_objects(section: Section) -> Vec<Box<dyn GenericObject>> {
+    section.clone().static_objects()
+  }
+  
+  #[test]
+  fn sections_are_dropped_whole() {
+    let mut statics = StaticSections::new(SECTION_SIZE);
+    statics.insert((0, 0, 0), section_objects(Section::new(0, 0, SECTION_SIZE).floor().left_wall()));
+    statics.insert((1, 0, 0), section_objects(Section::new(1, 0, SECTION_SIZE).floor()));
+    statics.insert((2, 0, 0), Vec::new());
+    assert_eq!(statics.objects().len(), 3);
+    assert_eq!(statics.section_objects((0, 0, 0)).len(), 2);
+    
+    // Loading a section again doesn't double it up
+    statics.insert((1, 0, 0), section_objects(Section::new(1, 0, SECTION_SIZE).floor()));
+    assert_eq!(statics.objects().len(), 3);
+    
+    statics.remove(&vec!((0, 0, 0)));
+    assert_eq!(statics.locations(), vec!((1, 0, 0), (2, 0, 0)));
+    assert_eq!(statics.objects().len(), 1);
+    assert_eq!(statics.grid().len(), 1);
+    assert_eq!(statics.objects()[0].position().x, SECTION_SIZE);
+  }
+  
+  #[test]
+  fn big_sections_are_split_into_packets_and_put_back_together() {
+    let mut section = Section::new(3, -2, SECTION_SIZE).floor();
+    for wall in Wall::ALL.iter() {
+      section = section.door(*wall);
+    }
+    section = section.elevator(1).elevator(-1);
+    let objects = section_objects(section).iter().map(|o| o.send_static_object()).collect::<Vec<SendStaticObject>>();
+    
+    let fragments = SendSection::fragments((3, 0, -2), objects.clone());
+    assert!(fragments.len() > 1);
+    for fragment in &fragments {
+      assert!(DataType::LoadSection(fragment.clone()).serialise().unwrap().len() <= BUFFER_SIZE);
+    }
+    
+    // Out of order, with a duplicate
+    let mut assembler = SectionAssembler::new();
+    let mut whole = None;
+    for i in (0..fragments.len()).rev() {
+      assert!(whole.is_none());
+      whole = assembler.add(fragments[i].clone());
+      if i == fragments.len() - 1 {
+        assert!(assembler.add(fragments[i].clone()).is_none());
+      }
+    }
+    assert_eq!(whole, Some(((3, 0, -2), objects)));
+    assert_eq!(assembler.pending(), 0);
+    
+    let empty = SendSection::fragments((0, 0, 0), Vec::new());
+    assert_eq!(empty.len(), 1);
+    assert_eq!(assembler.add(empty[0].clone()), Some(((0, 0, 0), Vec::new())));
+  }
+}
@@ -1,11 +1,24 @@
-use crate::{Section, SectionLayout, GenericObject, DrawCall};
+use std::collections::{HashMap, HashSet};
+
+use crate::{Section, SectionLayout, GenericObject, DrawCall, CollisionGrid, CollisionInfo, Vector3, Vector4};
+use crate::{Categories, QueryHit, QuerySet, ModelShapes, Map, StaticSections, SendSection};
+use crate::{query, narrow_phase, interest};
+
+// Floors either side of a player's own that are kept loaded
+pub const FLOOR_RANGE: i32 = 1;
+// Sections further than this from every player are unloaded, a little past what
+// gets loaded around them so walking back and forth over an edge doesn't churn
+pub const INTEREST_RADIUS: i32 = 3;
 
 pub struct World {
   section_size: f64,
-  locations: Vec<(i32, i32)>,
-  sections: Vec<Section>,
+  // Listed in the map, these are never unloaded. There are only as many as the map lists
+  // so they don't grow as players roam, the ones streamed in around players come and go
+  kept_sections: HashSet<(i32, i32, i32)>,
+  sections: HashMap<(i32, i32, i32), Section>,
   general_layout: SectionLayout,
-  static_objects: Vec<Box<dyn GenericObject>>,
+  statics: StaticSections,
+  model_shapes: ModelShapes,
 }
 
 // -x = right
@@ -14,59 +27,129 @@ pub struct World {
 // +z = down/towards
 
 impl World {
+  // The default map at a different scale
   pub fn new(section_size: f64) -> World {
-    let mut locations = vec!(
-                ( 1, -2), ( 0, -2), (-1, -2),
-      ( 2, -1), ( 1, -1), ( 0, -1), (-1, -1), (-2, -1), (-3, -1), (-4, -1), (-5, -1),
-                ( 1,  0), ( 0,  0), (-1,  0), (-2,  0), (-3,  0), (-4,  0), (-5,  0),
-      ( 2,  1), ( 1,  1), ( 0,  1), (-1,  1), (-2,  1), (-3,  1), (-4,  1), (-5,  1),
-                ( 1,  2),           (-1,  2)
-    );
-    
-    let mut sections = vec!(
-      Section::new( 1, -2, section_size).floor().front_wall().left_wall().right_wall(),
-      Section::new( 0, -2, section_size),
-      Section::new(-1, -2, section_size).floor().front_wall().left_wall().right_wall(),
-      Section::new( 2, -1, section_size).floor().right_wall().front_wall().back_wall(),
-      Section::new( 1, -1, section_size),
-      Section::new( 0, -1, section_size),
-      Section::new(-1, -1, section_size),
-      Section::new(-2, -1, section_size),
-      Section::new(-3, -1, section_size).floor().back_wall().left_wall(),
-      Section::new(-4, -1, section_size).floor(),
-      Section::new(-5, -1, section_size).floor().back_wall(),
-      Section::new( 1,  0, section_size),
-      Section::new( 0,  0, section_size).floor().left_wall().front_wall().back_wall(),
-      Section::new(-1,  0, section_size).floor().front_wall().back_wall(),
-      Section::new(-2,  0, section_size).floor().front_wall().back_wall(),
-      Section::new(-3,  0, section_size).floor(),
-      Section::new(-4,  0, section_size).floor(),
-      Section::new(-5,  0, section_size).floor().right_wall(),
-      Section::new( 2,  1, section_size).floor().right_wall().front_wall().back_wall(),
-      Section::new( 1,  1, section_size),
-      Section::new( 0,  1, section_size),
-      Section::new(-1,  1, section_size),
-      Section::new(-2,  1, section_size),
-      Section::new(-3,  1, section_size).floor().left_wall().front_wall(),
-      Section::new(-4,  1, section_size).floor(),
-      Section::new(-5,  1, section_size).floor().front_wall(),
-      Section::new( 1,  2, section_size).floor().back_wall().left_wall().right_wall(),
-      Section::new(-1,  2, section_size).floor().back_wall().left_wall().right_wall(),
-    );
-    
-    let mut static_objects: Vec<Box<dyn GenericObject>> = Vec::new();
-    
-    for section in &mut sections {
-      static_objects.append(&mut (section.static_objects()));
-    }
+    let mut map = Map::default_map();
+    map.section_size = section_size;
+    
+    World::from_map(&map)
+  }
+  
+  pub fn from_map(map: &Map) -> World {
+    let mut world = World::from_sections(map.section_size, map.sections(), map.layout());
+    world.keep_map_sections(map);
     
-    World {
+    world
+  }
+  
+  // Sections keep their objects so the world can be saved and rebuilt from them. None of
+  // them are kept loaded until keep_map_sections says which the map lists.
+  pub fn from_sections(section_size: f64, sections: Vec<Section>, general_layout: SectionLayout) -> World {
+    let mut world = World {
       section_size,
-      locations,
-      sections,
-      general_layout: SectionLayout::grid(section_size),
-      static_objects,
+      kept_sections: HashSet::new(),
+      sections: HashMap::new(),
+      general_layout,
+      statics: StaticSections::new(section_size),
+      model_shapes: ModelShapes::new(),
+    };
+    
+    for section in sections {
+      world.add_section(section);
+    }
+    
+    world
+  }
+  
+  // Anything loaded that the map doesn't list is left to be unloaded once players move away
+  pub fn keep_map_sections(&mut self, map: &Map) {
+    self.kept_sections = map.sections.iter().map(|s| (s.x, s.level, s.z)).collect();
+  }
+  
+  fn add_section(&mut self, section: Section) {
+    let location = section.location();
+    let mut objects = section.clone().static_objects();
+    for object in &mut objects {
+      self.model_shapes.attach(object.as_mut());
+    }
+    self.statics.insert(location, objects);
+    self.sections.insert(location, section);
+  }
+  
+  // Objects already loaded are given their shapes too, so the grid is rebuilt around them
+  pub fn set_model_shapes(&mut self, model_shapes: ModelShapes) {
+    self.model_shapes = model_shapes;
+    for object in self.statics.mut_objects() {
+      self.model_shapes.attach(object.as_mut());
+    }
+    self.statics.rebuild_grid();
+  }
+  
+  // Only sections loaded from now on are affected
+  pub fn set_layout(&mut self, general_layout: SectionLayout) {
+    self.general_layout = general_layout;
+  }
+  
+  pub fn model_shapes(&self) -> &ModelShapes {
+    &self.model_shapes
+  }
+  
+  pub fn query_set(&self) -> QuerySet<'_> {
+    QuerySet::new(Categories::STATIC, self.statics.objects(), Some(self.statics.grid()))
+  }
+  
+  // Static objects only, see TwinstickGame for queries that include things that move
+  pub fn raycast(&self, origin: &Vector3, direction: &Vector3, max_distance: f64, filter: Categories) -> Option<QueryHit> {
+    query::raycast(&[self.query_set()], origin, direction, max_distance, filter)
+  }
+  
+  pub fn overlap_sphere(&self, centre: &Vector3, radius: f64, filter: Categories) -> Vec<QueryHit> {
+    query::overlap(&[self.query_set()], &CollisionInfo::Sphere(Vector4::new(centre.x, centre.y, centre.z, radius)), filter)
+  }
+  
+  pub fn overlap_aabb(&self, pos: &Vector3, size: &Vector3, filter: Categories) -> Vec<QueryHit> {
+    query::overlap(&[self.query_set()], &CollisionInfo::AABB(pos.clone(), size.clone(), narrow_phase::identity()), filter)
+  }
+  
+  // Ordered so saves come out the same every time
+  pub fn sections(&self) -> Vec<Section> {
+    let mut sections = self.sections.values().cloned().collect::<Vec<Section>>();
+    sections.sort_by_key(|s| s.location());
+    sections
+  }
+  
+  pub fn loaded_sections(&self) -> usize {
+    self.sections.len()
+  }
+  
+  pub fn locations(&self) -> Vec<(i32, i32, i32)> {
+    self.statics.locations()
+  }
+  
+  // A loaded section's geometry as it goes over the wire, None if it isn't loaded
+  pub fn send_section(&self, x: i32, level: i32, z: i32) -> Option<Vec<SendSection>> {
+    if !self.statics.contains((x, level, z)) {
+      return None;
     }
+    
+    let objects = self.statics.section_objects((x, level, z)).iter().map(|o| o.send_static_object()).collect();
+    Some(SendSection::fragments((x, level, z), objects))
+  }
+  
+  pub fn is_loaded(&self, x: i32, level: i32, z: i32) -> bool {
+    self.sections.contains_key(&(x, level, z))
+  }
+  
+  pub fn section_containing(&self, pos: &Vector3) -> (i32, i32, i32) {
+    ((pos.x/self.section_size).round() as i32, Section::floor_at(pos.y), (pos.z/self.section_size).round() as i32)
+  }
+  
+  pub fn section_bounds(&self, x: i32, level: i32, z: i32) -> (Vector3, Vector3) {
+    Section::bounds(x, level, z, self.section_size)
+  }
+  
+  pub fn section_size(&self) -> f64 {
+    self.section_size
   }
   
   pub fn xz_from_grid_index(&self, x: i32, z: i32) -> (f64, f64) {
@@ -77,7 +160,7 @@ impl World {
     let mut grid_indexs = Vec::new();
     
     let (center_x, center_z) = self.calculate_grid_index(x, z);
-    println!("Center x {} z {}", center_x, center_z);
+    log::trace!("grid area center x={} z={} range={}", center_x, center_z, range);
     for i in 0..range {
       for j in 0..range {
         let e_x = center_x+i as i32-(range as f32*0.5).floor() as i32;
@@ -104,12 +187,15 @@ impl World {
     (grid_x as i32, grid_z as i32)
   }
   
-  pub fn load_area(&mut self, x: i32, z: i32, range: u32) -> Vec<Section> {
+  // Only the floors within FLOOR_RANGE of the given one
+  pub fn load_area(&mut self, x: i32, level: i32, z: i32, range: u32) -> Vec<Section> {
     let mut new_sections = Vec::new();
-    for i in 0..range {
-      for j in 0..range {
-        if let Some(new_section) = self.load_section(x+i as i32-(range as f32*0.5).floor() as i32, z+j as i32-(range as f32*0.5).floor() as i32) {
-          new_sections.push(new_section);
+    for l in level-FLOOR_RANGE..=level+FLOOR_RANGE {
+      for i in 0..range {
+        for j in 0..range {
+          if let Some(new_section) = self.load_section(x+i as i32-(range as f32*0.5).floor() as i32, l, z+j as i32-(range as f32*0.5).floor() as i32) {
+            new_sections.push(new_section);
+          }
         }
       }
     }
@@ -117,49 +203,92 @@ impl World {
     new_sections
   }
   
-  pub fn load_section(&mut self, x: i32, y: i32) -> Option<Section> {
-    let mut new_section = None;
-    
-    let location_loaded = self.locations.clone().into_iter().filter(|(x1, y1)| x == *x1 && y == *y1).map(|x| true).collect::<Vec<bool>>().len() > 0;
-    if !location_loaded {
-      let mut section = self.general_layout.get_section(x,y);
-      self.locations.push((x, y));
-      section.set_pos(x,y);
-      self.static_objects.append(&mut section.clone().static_objects());
-      self.sections.push(section.clone());
-      new_section = Some(section);
+  pub fn load_section(&mut self, x: i32, level: i32, z: i32) -> Option<Section> {
+    if self.sections.contains_key(&(x, level, z)) {
+      return None;
     }
     
-    new_section
+    let mut section = self.general_layout.get_section(x, level, z);
+    section.set_pos(x, level, z);
+    self.add_section(section.clone());
+    
+    Some(section)
   }
   
-  pub fn section_at_xz(&mut self, x: i32, z: i32) -> Option<Section> {
-    let mut section = None;
+  // Swaps in a changed map without dropping what is loaded. Loaded sections that come out
+  // different are rebuilt and returned, along with any the map now lists. The section size
+  // has to stay the same, see TwinstickGame::change_map.
+  pub fn apply_map(&mut self, map: &Map) -> Vec<(i32, i32, i32)> {
+    self.general_layout = map.layout();
+    let mut listed = map.sections().into_iter().map(|s| (s.location(), s)).collect::<HashMap<(i32, i32, i32), Section>>();
+    self.kept_sections = listed.keys().cloned().collect();
     
-    let mut index: i32 = -1;
-    for i in 0..self.locations.len() {
-      if self.locations[i] == (x, z) {
-        index = i as i32;
+    let mut locations = self.sections.keys().chain(listed.keys()).cloned().collect::<Vec<(i32, i32, i32)>>();
+    locations.sort();
+    locations.dedup();
+    
+    let mut changed = Vec::new();
+    for (x, level, z) in locations {
+      let section = listed.remove(&(x, level, z)).unwrap_or_else(|| {
+        let mut section = self.general_layout.get_section(x, level, z);
+        section.set_pos(x, level, z);
+        section
+      });
+      if self.sections.get(&(x, level, z)) != Some(&section) {
+        self.add_section(section);
+        changed.push((x, level, z));
       }
     }
     
-    if index != -1 {
-      section = Some(self.sections[index as usize].clone());
+    changed
+  }
+  
+  pub fn section_at(&self, x: i32, level: i32, z: i32) -> Option<Section> {
+    self.sections.get(&(x, level, z)).cloned()
+  }
+  
+  // Each area is a centre section and how many sections either side of it to keep, see
+  // interest::in_range. Returns the sections that were unloaded.
+  pub fn unload_outside(&mut self, areas: &[((i32, i32, i32), i32)]) -> Vec<(i32, i32, i32)> {
+    let kept_sections = &self.kept_sections;
+    let unloaded = self.sections.keys().filter(|(x, level, z)| {
+      !kept_sections.contains(&(*x, *level, *z)) && !areas.iter().any(|(centre, radius)| {
+        interest::in_range(*centre, (*x, *level, *z), *radius)
+      })
+    }).cloned().collect::<HashSet<(i32, i32, i32)>>();
+    
+    if unloaded.is_empty() {
+      return Vec::new();
+    }
+    
+    let mut unloaded = unloaded.into_iter().collect::<Vec<(i32, i32, i32)>>();
+    unloaded.sort();
+    for location in &unloaded {
+      self.sections.remove(location);
     }
+    self.statics.remove(&unloaded);
     
-    section
+    unloaded
   }
   
   pub fn objects(&self) -> &Vec<Box<dyn GenericObject>> {
-    &self.static_objects
+    self.statics.objects()
   }
   
   pub fn mut_objects(&mut self) -> &mut Vec<Box<dyn GenericObject>> {
-    &mut self.static_objects
+    self.statics.mut_objects()
+  }
+  
+  pub fn static_grid(&self) -> &CollisionGrid {
+    self.statics.grid()
+  }
+  
+  pub fn objects_and_grid(&mut self) -> (&mut Vec<Box<dyn GenericObject>>, &CollisionGrid) {
+    self.statics.objects_and_grid()
   }
   
   pub fn draw(&self, draw_calls: &mut Vec<DrawCall>) {
-    for object in &self.static_objects {
+    for object in self.statics.objects() {
       object.draw(true, draw_calls);
     }
   }
@@ -0,0 +1,277 @@
+use std::env;
+use std::io::{self, BufRead};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc;
+use std::thread;
+
+use log::{info, warn};
+
+use twinstick_logic::{AdminCommand, DataType, Features, GenericObject, Vector3};
+
+use crate::Server;
+
+pub const ADMIN_TOKEN_ENV: &str = "TWINSTICK_ADMIN_TOKEN";
+
+const MIN_TICK_RATE: u32 = 1;
+const MAX_TICK_RATE: u32 = 240;
+
+// Remote admin is disabled unless a token is configured
+pub fn admin_token_from_env() -> Option<String> {
+  match env::var(ADMIN_TOKEN_ENV) {
+    Ok(token) if !token.is_empty() => Some(token),
+    _ => None,
+  }
+}
+
+// Reads operator commands off stdin without blocking the game loop
+pub fn spawn_console() -> mpsc::Receiver<String> {
+  let (sender, receiver) = mpsc::channel();
+
+  thread::spawn(move || {
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+      match line {
+        Ok(line) => {
+          if sender.send(line).is_err() {
+            break;
+          }
+        },
+        Err(_) => break,
+      }
+    }
+  });
+
+  receiver
+}
+
+fn tokens_match(expected: &str, given: &str) -> bool {
+  if expected.len() != given.len() {
+    return false;
+  }
+
+  // Compare every byte so the time taken doesn't leak how much matched
+  expected.bytes().zip(given.bytes()).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+impl Server {
+  pub fn poll_console(&mut self) {
+    let mut lines = Vec::new();
+    if let Some(console) = &self.console {
+      while let Ok(line) = console.try_recv() {
+        lines.push(line);
+      }
+    }
+
+    for line in lines {
+      if line.trim().is_empty() {
+        continue;
+      }
+
+      match AdminCommand::parse(&line) {
+        Ok(command) => {
+          let response = self.run_admin_command(command);
+          println!("{}", response);
+        },
+        Err(e) => println!("{}", e),
+      }
+    }
+  }
+
+  pub fn handle_remote_admin(&mut self, src_addr: SocketAddr, token: String, command: AdminCommand) {
+    let authorised = match &self.admin_token {
+      Some(expected) => tokens_match(expected, &token),
+      None => false,
+    };
+
+    if !authorised {
+      warn!("rejected admin command addr={} command={:?}", src_addr, command);
+      self.send_or_log(src_addr, &DataType::AdminResponse("Not authorised".to_string()));
+      return;
+    }
+
+    info!("remote admin command addr={} command={:?}", src_addr, command);
+    let response = self.run_admin_command(command);
+    self.send_or_log(src_addr, &DataType::AdminResponse(response));
+  }
+
+  pub fn run_admin_command(&mut self, command: AdminCommand) -> String {
+    match command {
+      AdminCommand::ListPlayers => {
+        let players = (0..self.clients.len()).map(|i| self.player_index(i).map(|p| self.game.players()[p].as_ref())).collect::<Vec<_>>();
+        list_players(&self.clients, &players)
+      },
+      AdminCommand::Kick(target) => {
+        match self.find_clients(&target) {
+          Ok(indexs) => {
+            let count = indexs.len();
+            for i in indexs.into_iter().rev() {
+              self.kick(i, "Kicked by an admin");
+            }
+            format!("Kicked {} client(s)", count)
+          },
+          Err(e) => e,
+        }
+      },
+      AdminCommand::Ban(target) => {
+        let ip = match target.parse::<SocketAddr>() {
+          Ok(addr) => addr.ip(),
+          Err(_) => {
+            match target.parse::<IpAddr>() {
+              Ok(ip) => ip,
+              Err(_) => return format!("'{}' is not an address", target),
+            }
+          }
+        };
+
+        if !self.banned.contains(&ip) {
+          self.banned.push(ip);
+        }
+
+        let indexs = self.find_clients(&ip.to_string()).unwrap_or_default();
+        for i in indexs.into_iter().rev() {
+          self.kick(i, "Banned by an admin");
+        }
+        format!("Banned {}", ip)
+      },
+      AdminCommand::Unban(target) => {
+        match target.parse::<IpAddr>() {
+          Ok(ip) => {
+            let before = self.banned.len();
+            self.banned.retain(|banned| *banned != ip);
+            if self.banned.len() < before {
+              format!("Unbanned {}", ip)
+            } else {
+              format!("{} was not banned", ip)
+            }
+          },
+          Err(_) => format!("'{}' is not an ip address", target),
+        }
+      },
+      AdminCommand::TickRate(rate) => {
+        if !(MIN_TICK_RATE..=MAX_TICK_RATE).contains(&rate) {
+          return format!("Tick rate must be between {} and {}", MIN_TICK_RATE, MAX_TICK_RATE);
+        }
+
+        self.tick_rate = rate;
+        format!("Tick rate set to {}hz", rate)
+      },
+      AdminCommand::SpawnEnemy(x, y, z) => {
+        let enemy = self.game.spawn_enemy(Vector3::new(x, y, z));
+        self.send_data_to_all_clients(&DataType::AddEnemy(enemy.send_dyn_obj()));
+        format!("Spawned enemy at ({}, {}, {})", x, y, z)
+      },
+      AdminCommand::ReloadMap => {
+        match self.reload_map() {
+          Ok(Some(changed)) => format!("Reloaded map, {} sections changed", changed),
+          Ok(None) => "Reloaded map, the section size changed so everything was resent".to_string(),
+          Err(e) => format!("Map not reloaded, keeping the old one: {}", e),
+        }
+      },
+      AdminCommand::Broadcast(message) => {
+        self.send_to_clients_with(Features::CHAT, &DataType::Chat(message.to_string()));
+        format!("Sent '{}'", message)
+      },
+      AdminCommand::Shutdown(reason) => {
+        self.shutdown(&reason);
+        format!("Shutting down: {}", reason)
+      },
+    }
+  }
+
+  // Accepts either a full ip:port or just an ip, which matches every client from it
+  fn find_clients(&self, target: &str) -> Result<Vec<usize>, String> {
+    let indexs = if let Ok(addr) = target.parse::<SocketAddr>() {
+      self.clients.iter().enumerate().filter(|(_, c)| **c == addr).map(|(i, _)| i).collect::<Vec<usize>>()
+    } else if let Ok(ip) = target.parse::<IpAddr>() {
+      self.clients.iter().enumerate().filter(|(_, c)| c.ip() == ip).map(|(i, _)| i).collect::<Vec<usize>>()
+    } else {
+      return Err(format!("'{}' is not an address", target));
+    };
+
+    if indexs.is_empty() {
+      Err(format!("No client connected from {}", target))
+    } else {
+      Ok(indexs)
+    }
+  }
+
+  pub fn kick(&mut self, index: usize, reason: &str) {
+    if index >= self.clients.len() {
+      return;
+    }
+
+    let addr = self.clients[index];
+    info!("kicking client addr={} reason=\"{}\"", addr, reason);
+    if self.client_features[index].contains(Features::SERVER_NOTICES) {
+      self.send_or_log(addr, &DataType::Kicked(reason.to_string()));
+    }
+    self.remove_player(index);
+  }
+
+  pub fn is_banned(&self, addr: &SocketAddr) -> bool {
+    self.banned.contains(&addr.ip())
+  }
+
+  pub fn shutdown(&mut self, reason: &str) {
+    info!("shutting down reason=\"{}\"", reason);
+    self.send_to_clients_with(Features::SERVER_NOTICES, &DataType::ServerShutdown(reason.to_string()));
+    self.running = false;
+  }
+}
+
+// Each client's player, None if it died. Those are taken out of the game but their
+// clients stay connected.
+fn list_players(clients: &[SocketAddr], players: &[Option<&dyn GenericObject>]) -> String {
+  let mut lines = vec!(format!("{} player(s) connected", clients.len()));
+  for (i, client) in clients.iter().enumerate() {
+    match players[i] {
+      Some(player) => {
+        let pos = player.position();
+        lines.push(format!("  {} {} pos=({:.1}, {:.1}, {:.1}) life={}",
+                           i, client, pos.x, pos.y, pos.z, player.data().life));
+      },
+      None => lines.push(format!("  {} {} dead/respawning", i, client)),
+    }
+  }
+  lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use twinstick_logic::{TwinstickGame, Character};
+
+  #[test]
+  fn players_are_listed_against_their_own_clients_after_one_dies() {
+    let mut game = TwinstickGame::new();
+    let ids = (0..3).map(|_| game.add_player()).collect::<Vec<u64>>();
+    for i in 0..3 {
+      let mut player = Character::new(Vector3::new(i as f64*10.0, 9.0, 0.0), Vector3::new_same(1.0));
+      player.mut_data().life = 5 + i as i32;
+      game.set_player(i, player);
+    }
+    // The first to join dies, everyone after moves down a place
+    let mut dead = Character::new(Vector3::new_same(0.0), Vector3::new_same(1.0));
+    dead.take_damage(1000);
+    game.set_player(0, dead);
+    game.update_server(1.0/60.0);
+    assert_eq!(game.players().len(), 2);
+
+    let clients = vec!("127.0.0.1:9001".parse().unwrap(), "127.0.0.1:9002".parse().unwrap(), "127.0.0.1:9003".parse().unwrap());
+    let players = ids.iter().map(|id| game.player_index(*id).map(|p| game.players()[p].as_ref())).collect::<Vec<_>>();
+    let lines = list_players(&clients, &players).lines().map(|l| l.to_string()).collect::<Vec<String>>();
+    assert_eq!(lines[1], "  0 127.0.0.1:9001 dead/respawning");
+    assert!(lines[2].starts_with("  1 127.0.0.1:9002 pos=(10."), "{}", lines[2]);
+    assert!(lines[2].ends_with("life=6"), "{}", lines[2]);
+    assert!(lines[3].starts_with("  2 127.0.0.1:9003 pos=(20."), "{}", lines[3]);
+    assert!(lines[3].ends_with("life=7"), "{}", lines[3]);
+  }
+
+  #[test]
+  fn tokens_must_match_exactly() {
+    assert!(tokens_match("secret", "secret"));
+    assert!(!tokens_match("secret", "secreT"));
+    assert!(!tokens_match("secret", "secret2"));
+    assert!(!tokens_match("secret", ""));
+  }
+}
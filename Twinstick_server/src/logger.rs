@@ -0,0 +1,119 @@
+use std::env;
+
+use log::{Log, Metadata, Record, LevelFilter};
+
+use chrono::Local;
+
+pub const LOG_ENV: &str = "TWINSTICK_LOG";
+
+// Filters look like RUST_LOG: "info,twinstick_logic::world=debug,twinstick_server=trace"
+pub struct ServerLogger {
+  default: LevelFilter,
+  filters: Vec<(String, LevelFilter)>,
+}
+
+impl ServerLogger {
+  pub fn new(default: LevelFilter) -> ServerLogger {
+    ServerLogger {
+      default,
+      filters: Vec::new(),
+    }
+  }
+
+  pub fn parse(spec: &str) -> ServerLogger {
+    let mut logger = ServerLogger::new(LevelFilter::Info);
+
+    for directive in spec.split(',').map(|d| d.trim()).filter(|d| !d.is_empty()) {
+      let mut parts = directive.splitn(2, '=');
+      let first = parts.next().unwrap_or("");
+      match parts.next() {
+        Some(level) => {
+          if let Ok(level) = level.trim().parse::<LevelFilter>() {
+            logger.filters.push((first.trim().to_string(), level));
+          }
+        },
+        None => {
+          match first.parse::<LevelFilter>() {
+            Ok(level) => logger.default = level,
+            Err(_) => logger.filters.push((first.to_string(), LevelFilter::Trace)),
+          }
+        }
+      }
+    }
+
+    // Most specific module wins
+    logger.filters.sort_by_key(|f| std::cmp::Reverse(f.0.len()));
+    logger
+  }
+
+  pub fn from_env() -> ServerLogger {
+    match env::var(LOG_ENV) {
+      Ok(spec) => ServerLogger::parse(&spec),
+      Err(_) => ServerLogger::new(LevelFilter::Info),
+    }
+  }
+
+  pub fn level_for(&self, target: &str) -> LevelFilter {
+    for (module, level) in &self.filters {
+      if target == module || target.starts_with(&format!("{}::", module)) {
+        return *level;
+      }
+    }
+
+    self.default
+  }
+
+  pub fn max_level(&self) -> LevelFilter {
+    self.filters.iter().map(|(_, level)| *level).fold(self.default, |a, b| a.max(b))
+  }
+
+  pub fn init(self) -> Result<(), log::SetLoggerError> {
+    let max_level = self.max_level();
+    log::set_boxed_logger(Box::new(self))?;
+    log::set_max_level(max_level);
+
+    Ok(())
+  }
+}
+
+impl Log for ServerLogger {
+  fn enabled(&self, metadata: &Metadata) -> bool {
+    metadata.level() <= self.level_for(metadata.target())
+  }
+
+  fn log(&self, record: &Record) {
+    if !self.enabled(record.metadata()) {
+      return;
+    }
+
+    let date = Local::now();
+    println!("{} {:<5} {}: {}", date.format("[%Y-%m-%d]%H:%M:%S"), record.level(), record.target(), record.args());
+  }
+
+  fn flush(&self) {
+
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn most_specific_filter_wins() {
+    let logger = ServerLogger::parse("warn,twinstick_logic=info,twinstick_logic::world=trace");
+
+    assert_eq!(logger.level_for("twinstick_server"), LevelFilter::Warn);
+    assert_eq!(logger.level_for("twinstick_logic::game"), LevelFilter::Info);
+    assert_eq!(logger.level_for("twinstick_logic::world"), LevelFilter::Trace);
+    assert_eq!(logger.level_for("twinstick_logic_other"), LevelFilter::Warn);
+    assert_eq!(logger.max_level(), LevelFilter::Trace);
+  }
+
+  #[test]
+  fn bad_directives_are_ignored() {
+    let logger = ServerLogger::parse("debug,twinstick_server=loud,,");
+
+    assert_eq!(logger.level_for("twinstick_server"), LevelFilter::Debug);
+  }
+}
@@ -1,48 +1,267 @@
 use std::net::UdpSocket;
 use std::io;
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::PathBuf;
+use std::env;
+use std::fs;
 
 use std::time;
 use std::str;
 
-use twinstick_logic::{BUFFER_SIZE, VERSION, FPS_60, FPS_120, DataType, GenericObject, TwinstickGame};
-
-use chrono::Local;
+use twinstick_logic::{BUFFER_SIZE, FPS_120, DataType, TwinstickGame,
+                      Handshake, Features, ProtocolRange, ConnectionRejection, TwinstickError, SaveGame, ModelShapes, Map,
+                      InterestSet, MAP_ENV, DEFAULT_MAP_PATH};
 
 pub extern crate serde_derive;
 pub extern crate bincode;
 
 pub use bincode::{deserialize, serialize};
 
+use log::{info, warn, error, debug};
+
 use threadpool::ThreadPool;
+use logger::ServerLogger;
+use metrics::{Metrics, MetricsEndpoint, METRICS_ADDR};
+use admin::ADMIN_TOKEN_ENV;
+use sent_sections::SentSections;
 
-mod threadpool;
+pub const SAVE_ENV: &str = "TWINSTICK_SAVE";
+const DEFAULT_SAVE_PATH: &str = "twinstick_save.bin";
+pub const MODELS_ENV: &str = "TWINSTICK_MODELS";
+const DEFAULT_MODELS_PATH: &str = "resources/models";
+// How often the map file is checked for changes
+const MAP_POLL_INTERVAL: time::Duration = time::Duration::from_secs(1);
 
-fn log(msg: String) {
-  let date = Local::now();
-  println!("{}: {}", date.format("[%Y-%m-%d]%H:%M:%S"), msg);
-}
+mod threadpool;
+mod logger;
+mod metrics;
+mod admin;
+mod sent_sections;
 
 pub struct Server {
   udp: UdpSocket,
   clients: Vec<SocketAddr>,
   static_objects_sent: Vec<Option<u32>>,
+  // Sections each client has been sent, for clients streaming whole sections
+  sections_sent: Vec<SentSections>,
   client_last_connection: Vec<time::Instant>,
+  client_features: Vec<Features>,
+  // The game's id for each client's player, its index changes as other players die or leave
+  client_players: Vec<u64>,
+  handshake: Handshake,
   game: TwinstickGame,
+  metrics: Metrics,
+  metrics_endpoint: Option<MetricsEndpoint>,
+  console: Option<mpsc::Receiver<String>>,
+  admin_token: Option<String>,
+  banned: Vec<IpAddr>,
+  tick_rate: u32,
+  running: bool,
+  save_path: PathBuf,
+  map_path: PathBuf,
+  map_modified: Option<time::SystemTime>,
+  last_map_poll: time::Instant,
 }
 
 impl Server {
-  pub fn new(ip: &str) -> Server {
-    log(format!("listening on udp port {}", ip));
-    let udp = UdpSocket::bind(ip).unwrap();
-    udp.set_nonblocking(true).unwrap();
+  pub fn new(ip: &str) -> Result<Server, TwinstickError> {
+    info!("listening on udp port addr={}", ip);
+    let udp = UdpSocket::bind(ip)?;
+    udp.set_nonblocking(true)?;
     
-    Server {
+    let metrics_endpoint = match MetricsEndpoint::bind(METRICS_ADDR) {
+      Ok(endpoint) => {
+        info!("serving metrics addr={}", endpoint.addr());
+        Some(endpoint)
+      },
+      Err(e) => {
+        warn!("metrics endpoint disabled addr={} error={}", METRICS_ADDR, e);
+        None
+      }
+    };
+    
+    let admin_token = admin::admin_token_from_env();
+    if admin_token.is_none() {
+      info!("remote admin disabled, set {} to enable it", ADMIN_TOKEN_ENV);
+    }
+    
+    let save_path = PathBuf::from(env::var(SAVE_ENV).unwrap_or(DEFAULT_SAVE_PATH.to_string()));
+    let map_path = PathBuf::from(env::var(MAP_ENV).unwrap_or(DEFAULT_MAP_PATH.to_string()));
+    let mut game = Server::load_game(&save_path, Server::load_map(&map_path));
+    
+    let models_path = PathBuf::from(env::var(MODELS_ENV).unwrap_or(DEFAULT_MODELS_PATH.to_string()));
+    match ModelShapes::load(&models_path) {
+      Ok(shapes) => {
+        info!("loaded model shapes path={} models={}", models_path.display(), shapes.len());
+        game.set_model_shapes(shapes);
+      },
+      Err(e) => {
+        warn!("static objects will use their whole hitbox, failed to load model shapes path={} error={}", models_path.display(), e);
+      }
+    }
+    
+    Ok(Server {
       udp,
       clients: Vec::new(),
       static_objects_sent: Vec::new(),
+      sections_sent: Vec::new(),
       client_last_connection: Vec::new(),
-      game: TwinstickGame::new(),
+      client_features: Vec::new(),
+      client_players: Vec::new(),
+      handshake: Handshake::new(),
+      game,
+      metrics: Metrics::new(),
+      metrics_endpoint,
+      console: Some(admin::spawn_console()),
+      admin_token,
+      banned: Vec::new(),
+      tick_rate: 60,
+      running: true,
+      save_path,
+      map_modified: Server::map_modified(&map_path),
+      map_path,
+      last_map_poll: time::Instant::now(),
+    })
+  }
+  
+  // Falls back to the map built into the game rather than refusing to start
+  fn load_map(map_path: &PathBuf) -> Map {
+    match Map::load(map_path) {
+      Ok(map) => {
+        info!("loaded map path={} sections={} spawners={}", map_path.display(), map.sections.len(), map.enemy_spawners.len());
+        map
+      },
+      Err(e) => {
+        warn!("using the default map, failed to load map path={} error={}", map_path.display(), e);
+        Map::default_map()
+      }
+    }
+  }
+  
+  fn load_game(save_path: &PathBuf, map: Map) -> TwinstickGame {
+    match SaveGame::read_from(save_path) {
+      Ok(save) => {
+        info!("restored world path={} sections={} enemies={} players={}", save_path.display(),
+              save.sections.len(), save.enemies.len(), save.players.len());
+        let mut game = TwinstickGame::from_save(save);
+        game.set_map(map);
+        game
+      },
+      Err(TwinstickError::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+        info!("no save found, starting a new world path={}", save_path.display());
+        TwinstickGame::from_map(map)
+      },
+      Err(e) => {
+        warn!("failed to restore world, starting a new one path={} error={}", save_path.display(), e);
+        TwinstickGame::from_map(map)
+      }
+    }
+  }
+  
+  fn map_modified(map_path: &PathBuf) -> Option<time::SystemTime> {
+    fs::metadata(map_path).and_then(|metadata| metadata.modified()).ok()
+  }
+  
+  // Saving the map from an editor shows up in game without anyone reconnecting
+  pub fn poll_map(&mut self) {
+    if self.last_map_poll.elapsed() < MAP_POLL_INTERVAL {
+      return;
+    }
+    self.last_map_poll = time::Instant::now();
+    
+    let modified = Server::map_modified(&self.map_path);
+    if modified.is_none() || modified == self.map_modified {
+      return;
+    }
+    
+    match self.reload_map() {
+      Ok(Some(changed)) => info!("map changed path={} sections_changed={}", self.map_path.display(), changed),
+      Ok(None) => info!("map changed size, resending everything path={}", self.map_path.display()),
+      Err(e) => warn!("map changed but failed to load, keeping the old one path={} error={}", self.map_path.display(), e),
+    }
+  }
+  
+  // Reads the map file again. Clients are sent only the sections that changed, or everything
+  // if the section size did, which is when None is returned.
+  pub fn reload_map(&mut self) -> Result<Option<usize>, TwinstickError> {
+    let modified = Server::map_modified(&self.map_path);
+    let map = Map::load(&self.map_path)?;
+    // A map that fails to load is tried again at the next poll, it may have been half written
+    self.map_modified = modified;
+    
+    // Clients are only sent them again if some changed, unchanged ones keep their state
+    let interactives_changed = self.game.map().interactives != map.interactives;
+    let platforms_changed = self.game.map().platforms != map.platforms;
+    let changed = self.game.change_map(map);
+    for i in 0..self.clients.len() {
+      if interactives_changed && self.client_features[i].contains(Features::INTERACTIVES) {
+        self.send_or_log(self.clients[i], &DataType::ClearInteractives);
+        self.send_interactives(self.clients[i]);
+      }
+      if platforms_changed && self.client_features[i].contains(Features::MOVING_PLATFORMS) {
+        self.send_or_log(self.clients[i], &DataType::ClearPlatforms);
+        self.send_platforms(self.clients[i]);
+      }
+    }
+    
+    match changed {
+      Some(changed) => {
+        if !changed.is_empty() {
+          for i in 0..self.clients.len() {
+            if self.client_features[i].contains(Features::SECTION_STREAMING) {
+              // Sent again by send_static_objects, replacing what the client has
+              for location in &changed {
+                self.sections_sent[i].forget(location);
+              }
+            } else {
+              self.resend_static_objects(i);
+            }
+          }
+        }
+        Ok(Some(changed.len()))
+      },
+      None => {
+        for i in 0..self.clients.len() {
+          self.resend_static_objects(i);
+        }
+        Ok(None)
+      },
+    }
+  }
+  
+  pub fn send_interactives(&mut self, addr: SocketAddr) {
+    let interactives = self.game.interactives().iter().filter_map(|i| i.interactive()).map(|i| i.send_interactive()).collect::<Vec<_>>();
+    for interactive in interactives {
+      self.send_or_log(addr, &DataType::AddInteractive(interactive));
+    }
+  }
+  
+  pub fn send_platforms(&mut self, addr: SocketAddr) {
+    let platforms = self.game.platforms().iter().filter_map(|p| p.platform()).map(|p| p.send_platform()).collect::<Vec<_>>();
+    for platform in platforms {
+      self.send_or_log(addr, &DataType::AddPlatform(platform));
+    }
+  }
+  
+  // The client throws away its static objects and is sent them all again
+  pub fn resend_static_objects(&mut self, i: usize) {
+    if self.client_features[i].contains(Features::SERVER_NOTICES) {
+      self.send_or_log(self.clients[i], &DataType::ClearStaticObjects);
+    }
+    if self.client_features[i].contains(Features::SECTION_STREAMING) {
+      self.sections_sent[i].clear();
+    } else {
+      self.static_objects_sent[i] = Some(0);
+    }
+  }
+  
+  pub fn save_game(&self) {
+    match self.game.save().write_to(&self.save_path) {
+      Ok(()) => info!("saved world path={}", self.save_path.display()),
+      Err(e) => error!("failed to save world path={} error={}", self.save_path.display(), e),
     }
   }
   
@@ -50,79 +269,216 @@ impl Server {
     &self.game
   }
   
+  pub fn is_running(&self) -> bool {
+    self.running
+  }
+  
+  pub fn tick_length(&self) -> f64 {
+    1.0 / self.tick_rate as f64
+  }
+  
+  pub fn publish_metrics(&mut self) {
+    if let Some(endpoint) = &self.metrics_endpoint {
+      endpoint.publish(&self.metrics);
+    }
+  }
+  
   pub fn update(&mut self, delta_time: f64) {
+    let tick_start = time::Instant::now();
     let (static_objects, enemies) = self.game.update_server(delta_time);
-    self.send_player_data_to_all_clients();
-    self.send_dynamic_objects_to_all_clients(self.game.enemies().clone());
+    let interests = (0..self.clients.len()).map(|i| self.interest_set(i)).collect::<Vec<Option<InterestSet>>>();
+    self.send_entity_updates(&interests);
     
+    // Clients streaming sections pick new ones up in send_static_objects
     for obj in static_objects {
-      self.send_data_to_all_clients(&DataType::StaticObject(obj.send_static_object()).serialise());
-    }
-    for i in 0..self.game().enemies().len() {
-      self.send_data_to_all_clients(&DataType::Enemy(self.game().enemies()[i].send_dyn_obj_update(), i).serialise());
+      self.send_to_clients_without(Features::SECTION_STREAMING, &DataType::StaticObject(obj.send_static_object()));
     }
+    self.drop_unloaded_sections();
+    self.drop_uninteresting_sections(&interests);
     
     for enemy in enemies {
-      self.send_data_to_all_clients(&DataType::AddEnemy(enemy.send_dyn_obj()).serialise());
+      self.send_data_to_all_clients(&DataType::AddEnemy(enemy.send_dyn_obj()));
     }
+    
+    self.metrics.record_tick(tick_start.elapsed(), &self.game);
   }
   
-  pub fn add_player(&mut self, src_addr: SocketAddr) {
-    let index = self.clients.len();
+  fn drop_unloaded_sections(&mut self) {
+    let unloaded = self.game.unloaded_sections().clone();
+    if unloaded.is_empty() {
+      return;
+    }
+    
+    for (x, level, z) in unloaded {
+      let (min, max) = self.game.world().section_bounds(x, level, z);
+      for i in 0..self.clients.len() {
+        let features = self.client_features[i];
+        if features.contains(Features::SECTION_UNLOADING) && !features.contains(Features::SECTION_STREAMING) {
+          self.send_or_log(self.clients[i], &DataType::DropStaticObjects(min.clone(), max.clone()));
+        }
+      }
+    }
+    
+    // Objects after the unloaded ones moved down, clients part way through start again
+    // rather than miss any, the ones they get twice are harmless
+    for sent in &mut self.static_objects_sent {
+      if sent.is_some() {
+        *sent = Some(0);
+      }
+    }
+  }
+  
+  // Streaming clients let go of sections once their player has moved away from them,
+  // clients without a player keep whatever is still loaded
+  fn drop_uninteresting_sections(&mut self, interests: &[Option<InterestSet>]) {
+    for (i, interest) in interests.iter().enumerate() {
+      if !self.client_features[i].contains(Features::SECTION_STREAMING) {
+        continue;
+      }
+      
+      let world = self.game.world();
+      let dropped = self.sections_sent[i].locations().into_iter().filter(|(x, level, z)| {
+        match interest {
+          Some(interest) => !interest.contains_section((*x, *level, *z)),
+          None => !world.is_loaded(*x, *level, *z),
+        }
+      }).collect::<Vec<(i32, i32, i32)>>();
+      
+      for (x, level, z) in dropped {
+        self.sections_sent[i].forget(&(x, level, z));
+        self.send_or_log(self.clients[i], &DataType::UnloadSection(x, level, z));
+      }
+    }
+  }
+  
+  // Each client only hears about the players, enemies, interactives and platforms near its own player,
+  // one without a player in the game hears about everyone
+  fn send_entity_updates(&mut self, interests: &[Option<InterestSet>]) {
+    let players = self.game.players().iter().map(|p| p.send_player_update()).collect::<Vec<_>>();
+    let enemies = self.game.enemies().iter().map(|e| e.send_dyn_obj_update()).collect::<Vec<_>>();
+    // Indexed as the game lists them, which is how clients were told about them
+    let interactives = self.game.interactives().iter().enumerate().filter_map(|(j, i)| {
+      i.interactive().map(|interactive| (j, self.game.world().section_containing(i.position()), interactive.is_active()))
+    }).collect::<Vec<_>>();
+    let platforms = self.game.platforms().iter().enumerate().filter_map(|(j, p)| {
+      p.platform().map(|platform| (j, self.game.world().section_containing(p.position()), p.position().clone(), platform.next_waypoint()))
+    }).collect::<Vec<_>>();
+    
+    for (i, interest) in interests.iter().enumerate() {
+      for (j, player) in players.iter().enumerate() {
+        if interest.as_ref().is_none_or(|interest| interest.contains_player(j)) {
+          self.send_or_log(self.clients[i], &DataType::Player(player.clone(), j));
+        }
+      }
+      for (j, enemy) in enemies.iter().enumerate() {
+        if interest.as_ref().is_none_or(|interest| interest.contains_enemy(j)) {
+          self.send_or_log(self.clients[i], &DataType::Enemy(enemy.clone(), j));
+        }
+      }
+      if self.client_features[i].contains(Features::INTERACTIVES) {
+        for &(j, location, active) in &interactives {
+          if interest.as_ref().is_none_or(|interest| interest.contains_section(location)) {
+            self.send_or_log(self.clients[i], &DataType::InteractiveState(j, active));
+          }
+        }
+      }
+      if self.client_features[i].contains(Features::MOVING_PLATFORMS) {
+        for (j, location, pos, next) in &platforms {
+          if interest.as_ref().is_none_or(|interest| interest.contains_section(*location)) {
+            self.send_or_log(self.clients[i], &DataType::PlatformState(*j, pos.clone(), *next));
+          }
+        }
+      }
+      if let Some(player) = self.player_index(i) {
+        self.send_or_log(self.clients[i], &DataType::PlayerNum(player));
+      }
+    }
+  }
+  
+  // Where a client's player is in the game's players, None once it has died
+  pub fn player_index(&self, client: usize) -> Option<usize> {
+    self.game.player_index(self.client_players[client])
+  }
+  
+  // Around the client's own player, None once it has died
+  fn interest_set(&self, client: usize) -> Option<InterestSet> {
+    self.game.interest_set(self.player_index(client)?)
+  }
+  
+  pub fn add_player(&mut self, src_addr: SocketAddr, features: Features) {
     self.clients.push(src_addr);
     self.client_last_connection.push(time::Instant::now());
-    self.game.add_player();
+    self.client_features.push(features);
+    let id = self.game.add_player();
+    self.client_players.push(id);
     
-    self.send_data_to_all_clients(&DataType::AddPlayer(self.game.players()[index].clone().send_dyn_obj()).serialise());
-    self.send_data_to_client(src_addr, &DataType::PlayerNum(index).serialise());
+    let index = self.game.player_index(id).unwrap();
+    self.send_data_to_all_clients(&DataType::AddPlayer(self.game.players()[index].clone().send_dyn_obj()));
+    self.send_or_log(src_addr, &DataType::PlayerNum(index));
   }
   
   pub fn remove_player(&mut self, index: usize) {
     let src_addr = self.clients.remove(index);
-    log(format!("Removing client: {}", src_addr));
+    info!("removing client addr={}", src_addr);
     self.client_last_connection.remove(index);
-    self.game.remove_player(index);
+    self.client_features.remove(index);
     self.static_objects_sent.remove(index);
-    self.send_data_to_all_clients(&DataType::RemovePlayer(index).serialise());
+    self.sections_sent.remove(index);
+    
+    let id = self.client_players.remove(index);
+    if let Some(player) = self.game.player_index(id) {
+      self.game.remove_player(id);
+      self.send_data_to_all_clients(&DataType::RemovePlayer(player));
+    }
   }
   
   pub fn remove_player_from_addr(&mut self, src_addr: SocketAddr) {
-    match self.clients.binary_search(&src_addr) {
-      Ok(i) => {
-        self.remove_player(i);
-      },
-      Err(_e) => {
-       // println!("Error: {}", e);
-       // self.remove_all_players();
-      }
+    if let Some(i) = self.clients.iter().position(|c| *c == src_addr) {
+      self.remove_player(i);
     }
   }
   
-  pub fn send_data_to_all_clients(&mut self, buffer: &[u8]) {
+  pub fn send_data_to_all_clients(&mut self, data: &DataType) {
     if self.clients.len() == 0 {
       return;
     }
     
+    let buffer = match data.serialise() {
+      Ok(buffer) => buffer,
+      Err(e) => {
+        error!("failed to serialise message={} error={}", data.name(), e);
+        return;
+      }
+    };
+    
     for i in 0..self.clients.len() {
-      self.send_data_to_client(self.clients[i], buffer);
+      if let Err(e) = self.send_bytes_to_client(self.clients[i], data.name(), &buffer) {
+        warn!("send failed addr={} message={} error={}", self.clients[i], data.name(), e);
+      }
     }
   }
   
-  pub fn send_static_objects_to_client(&mut self, src_addr: SocketAddr) {
-    for j in 0..self.game.static_objects().len() {
-      let object = self.game.static_objects()[j].clone().send_static_object();
-      self.send_data_to_client(src_addr, &DataType::StaticObject(object).serialise());
+  // Only clients that negotiated the feature know how to decode the message
+  pub fn send_to_clients_with(&mut self, feature: Features, data: &DataType) {
+    for i in 0..self.clients.len() {
+      if self.client_features[i].contains(feature) {
+        self.send_or_log(self.clients[i], data);
+      }
     }
   }
   
-  pub fn send_dynamic_objects_to_all_clients(&mut self, dyn_objects: Vec<Box<dyn GenericObject>>) {
-    if self.clients.len() == 0 {
-      return;
+  pub fn send_to_clients_without(&mut self, feature: Features, data: &DataType) {
+    for i in 0..self.clients.len() {
+      if !self.client_features[i].contains(feature) {
+        self.send_or_log(self.clients[i], data);
+      }
     }
-    
-    for j in 0..dyn_objects.len() {
-      let object = dyn_objects[j].clone().send_dyn_obj_update();
-      self.send_data_to_all_clients(&DataType::Enemy(object, j).serialise());
+  }
+  
+  pub fn send_static_objects_to_client(&mut self, src_addr: SocketAddr) {
+    for j in 0..self.game.static_objects().len() {
+      let object = self.game.static_objects()[j].clone().send_static_object();
+      self.send_or_log(src_addr, &DataType::StaticObject(object));
     }
   }
   
@@ -133,117 +489,185 @@ impl Server {
     
     for j in 0..self.game.static_objects().len() {
       let object = self.game.static_objects()[j].clone().send_static_object();
-      self.send_data_to_all_clients(&DataType::StaticObject(object).serialise());
+      self.send_data_to_all_clients(&DataType::StaticObject(object));
     }
   }
   
-  pub fn send_player_data_to_all_clients(&mut self) {
-    if self.clients.len() == 0 {
-      return;
+  pub fn send_data_to_client(&mut self, addr: SocketAddr, data: &DataType) -> Result<(), TwinstickError> {
+    let buffer = data.serialise()?;
+    self.send_bytes_to_client(addr, data.name(), &buffer)
+  }
+  
+  fn send_bytes_to_client(&mut self, addr: SocketAddr, name: &'static str, buffer: &[u8]) -> Result<(), TwinstickError> {
+    if let Err(e) = self.udp.send_to(buffer, addr) {
+      self.metrics.record_dropped();
+      return Err(e.into());
     }
     
-    for j in 0..self.game.players().len() {
-      self.send_data_to_all_clients(&DataType::Player(self.game.players()[j].clone().send_player_update(), j).serialise());
-    }
+    self.metrics.record_out(name, buffer.len());
     
-    for i in 0..self.clients.len() {
-      self.send_data_to_client(self.clients[i], &DataType::PlayerNum(i).serialise());
+    Ok(())
+  }
+  
+  // A client that can't be reached shouldn't take the server down with it
+  fn send_or_log(&mut self, addr: SocketAddr, data: &DataType) {
+    if let Err(e) = self.send_data_to_client(addr, data) {
+      warn!("send failed addr={} message={} error={}", addr, data.name(), e);
     }
   }
   
-  pub fn send_data_to_client(&mut self, addr: SocketAddr, buffer: &[u8]) {
-    self.udp.send_to(&buffer, addr).unwrap();
+  // One section at a time, the closest one in the client's interest set that it doesn't have yet
+  fn send_next_section(&mut self, i: usize) {
+    let interest = match self.interest_set(i) {
+      Some(interest) => interest,
+      None => return,
+    };
+    
+    let centre = interest.centre;
+    let now = time::Instant::now();
+    let next = interest.sections.into_iter()
+                   .filter(|location| self.sections_sent[i].needs(location, now))
+                   .min_by_key(|(x, level, z)| (x - centre.0).abs().max((z - centre.2).abs()) + (level - centre.1).abs());
+    
+    if let Some((x, level, z)) = next {
+      if let Some(fragments) = self.game.world().send_section(x, level, z) {
+        for fragment in fragments {
+          self.send_or_log(self.clients[i], &DataType::LoadSection(fragment));
+        }
+      }
+      self.sections_sent[i].sent((x, level, z), now);
+    }
   }
   
   pub fn send_static_objects(&mut self) {
+    for i in 0..self.clients.len() {
+      if self.client_features[i].contains(Features::SECTION_STREAMING) {
+        self.send_next_section(i);
+      }
+    }
+    
     for i in 0..self.static_objects_sent.len() {
-      let mut is_done = false;
-      if self.static_objects_sent[i].is_some() {
-        let objs_sent = self.static_objects_sent[i].unwrap();
-        
+      if let Some(objs_sent) = self.static_objects_sent[i] {
         if objs_sent < self.game.static_objects().len() as u32 {
-          self.send_data_to_client(self.clients[i], &DataType::StaticObject(self.game.static_objects()[objs_sent as usize].send_static_object()).serialise());
+          self.send_or_log(self.clients[i], &DataType::StaticObject(self.game.static_objects()[objs_sent as usize].send_static_object()));
           self.static_objects_sent[i] = Some(objs_sent+1);
         } else {
           self.static_objects_sent[i] = None;
         }
       }
     }
-      /*
-      if let Some(objs_sent) = &mut self.static_objects_sent[i] {
-        if *objs_sent < self.game.static_objects().len() as u32 {
-          self.send_data_to_client(self.clients[i], &DataType::StaticObject(self.game.static_objects()[*objs_sent as usize].send_static_object()).serialise());
-          *objs_sent += 1;
-        } else {
-          is_done = true;
-        }
-      }
-      
-      if is_done {
-        self.static_objects_sent[i] = None;
-      }
-    }*/
   }
   
-  pub fn listen(&mut self) {
+  pub fn accept_client(&mut self, src_addr: SocketAddr, features: Features) {
+    let players = self.game.players().iter().map(|p| p.clone().send_dyn_obj()).collect::<Vec<_>>();
+    for player in players {
+      self.send_or_log(src_addr, &DataType::AddPlayer(player));
+    }
+    self.add_player(src_addr, features);
+    if features.contains(Features::SECTION_STREAMING) {
+      self.static_objects_sent.push(None);
+    } else {
+      self.static_objects_sent.push(Some(0));
+    }
+    self.sections_sent.push(SentSections::new());
+    for j in 0..self.game.enemies().len() {
+      let object = self.game.enemies()[j].clone().send_dyn_obj();
+      self.send_or_log(src_addr, &DataType::AddEnemy(object));
+    }
+    if features.contains(Features::INTERACTIVES) {
+      self.send_interactives(src_addr);
+    }
+    if features.contains(Features::MOVING_PLATFORMS) {
+      self.send_platforms(src_addr);
+    }
+  }
+  
+  pub fn listen(&mut self) -> Result<(), TwinstickError> {
     let mut buffer = [0; BUFFER_SIZE];
     
     match self.udp.recv_from(&mut buffer) {
       Ok((number_of_bytes, src_addr)) => {
         let filled_buf = &mut buffer[..number_of_bytes];
         
+        let data_type = match DataType::deserialise(filled_buf) {
+          Ok(data_type) => data_type,
+          Err(e) => {
+            self.metrics.record_dropped();
+            return Err(e);
+          }
+        };
+        self.metrics.record_in(data_type.name(), number_of_bytes);
+        
         if !self.clients.contains(&src_addr) {
-          match DataType::deserialise(filled_buf) {
-            Some(DataType::TryConnect(v)) => {
-              if v == VERSION {
-                self.send_data_to_client(src_addr, &DataType::ConfirmConnect(VERSION).serialise());
-                log(format!("New client connected: {}", src_addr));
-                for i in 0..self.clients.len() {
-                  self.send_data_to_client(src_addr, &DataType::AddPlayer(self.game.players()[i].clone().send_dyn_obj()).serialise());
-                }
-                self.add_player(src_addr);
-               // self.send_static_objects_to_client(src_addr);
-               self.static_objects_sent.push(Some(0));
-                for j in 0..self.game.enemies().len() {
-                  let object = self.game.enemies()[j].clone().send_dyn_obj();
-                  self.send_data_to_client(src_addr, &DataType::AddEnemy(object).serialise());
+          match data_type {
+            DataType::Handshake(_) if self.is_banned(&src_addr) => {
+              info!("banned client rejected addr={}", src_addr);
+              self.send_or_log(src_addr, &DataType::Rejected(ConnectionRejection::Banned));
+            },
+            DataType::Handshake(client_handshake) => {
+              match self.handshake.respond(&client_handshake) {
+                Ok(accepted) => {
+                  info!("client connected addr={} protocol={} features={}", src_addr, accepted.version, accepted.features.0);
+                  let features = accepted.features;
+                  self.send_or_log(src_addr, &DataType::HandshakeAccepted(accepted));
+                  self.accept_client(src_addr, features);
+                },
+                Err(rejection) => {
+                  info!("client rejected addr={} reason=\"{}\"", src_addr, rejection);
+                  self.send_or_log(src_addr, &DataType::Rejected(rejection));
                 }
-              } else {
-                self.send_data_to_client(src_addr, &DataType::Err(format!("Outdated version (Expected: {}, Actual: {})", VERSION, v)).serialise());
               }
             },
-            _ => {},
+            DataType::TryConnect(v) => {
+              // Clients from before the handshake only understand Err
+              let rejection = ConnectionRejection::VersionMismatch {
+                server: self.handshake.versions,
+                client: ProtocolRange::single(v),
+              };
+              info!("legacy client rejected addr={} reason=\"{}\"", src_addr, rejection);
+              self.send_or_log(src_addr, &DataType::Err(rejection.to_string()));
+            },
+            // Admin tools don't need to join the game to run commands
+            DataType::Admin(token, command) => {
+              self.handle_remote_admin(src_addr, token, command);
+            },
+            other => {
+              debug!("message from unknown client addr={} message={}", src_addr, other.name());
+              self.metrics.record_dropped();
+            },
           }
         } else {
           let mut client_id = 0;
-          match self.clients.binary_search(&src_addr) {
-            Ok(i) => {
-              self.client_last_connection[i] = time::Instant::now();
-              client_id = i;
-            },
-            _ => {}
+          if let Some(i) = self.clients.iter().position(|c| *c == src_addr) {
+            self.client_last_connection[i] = time::Instant::now();
+            client_id = i;
           }
           
-          match DataType::deserialise(filled_buf) {
-            Some(data_type) => {
-              match data_type {
-                DataType::PlayerRotation(rot, idx) => {
-                  self.game.set_player_rotation(idx, rot);
-                },
-                DataType::Input(input) => {
-                  self.game.add_input(client_id, input);
-                },
-                DataType::Exit => {
-                  self.remove_player_from_addr(src_addr);
-                },
-                _ => {
-                  
-                },
+          match data_type {
+            // Only ever for the client's own player, whatever index it says
+            DataType::PlayerRotation(rot, _) => {
+              if let Some(player) = self.player_index(client_id) {
+                self.game.set_player_rotation(player, rot);
               }
             },
-            None => {
+            DataType::Input(input) => {
+              if let Some(player) = self.player_index(client_id) {
+                self.game.add_input(player, input);
+              }
+            },
+            // Finished arriving after it was dropped
+            DataType::SectionLoaded(x, level, z) if !self.sections_sent[client_id].acked((x, level, z)) => {
+              self.send_or_log(src_addr, &DataType::UnloadSection(x, level, z));
+            },
+            DataType::Exit => {
+              self.remove_player_from_addr(src_addr);
+            },
+            DataType::Admin(token, command) => {
+              self.handle_remote_admin(src_addr, token, command);
+            },
+            _ => {
               
-            }
+            },
           }
         }
       },
@@ -251,19 +675,38 @@ impl Server {
         // wait until network socket is ready, typically implemented
         // via platform-specific APIs such as epoll or IOCP
         //wait_for_fd();
-        for i in (0..(self.clients.len() as i32 - 1).max(0) as usize).rev() {
+        for i in (0..self.clients.len()).rev() {
           if self.client_last_connection[i].elapsed() > time::Duration::from_secs(5) {
             self.remove_player(i);
           }
         }
       },
-      Err(e) => panic!("encountered IO error: {}", e),
+      Err(e) => return Err(e.into()),
     }
+    
+    Ok(())
   }
 }
 
 fn main() {
-  let mut server = Server::new("0.0.0.0:8008");
+  if let Err(e) = ServerLogger::from_env().init() {
+    println!("Failed to set up logging: {}", e);
+  }
+  
+  let mut server = match Server::new("0.0.0.0:8008") {
+    Ok(server) => server,
+    Err(e) => {
+      error!("failed to start server error={}", e);
+      return;
+    }
+  };
+  
+  // Ctrl-C and SIGTERM go through the same shutdown as the admin command
+  let interrupted = Arc::new(AtomicBool::new(false));
+  let handler_flag = interrupted.clone();
+  if let Err(e) = ctrlc::set_handler(move || handler_flag.store(true, Ordering::SeqCst)) {
+    warn!("failed to install signal handler error={}", e);
+  }
   
   let mut delta_time: f64;
   let mut last_time = time::Instant::now();
@@ -271,22 +714,36 @@ fn main() {
   let mut tick = 0.0;
   let mut tick_120 = 0.0;
   
-  loop {
+  while server.is_running() {
+    if interrupted.swap(false, Ordering::SeqCst) {
+      server.shutdown("Server shutting down");
+      break;
+    }
+    
     delta_time = last_time.elapsed().subsec_nanos() as f64 / 1000000000.0 as f64;
     last_time = time::Instant::now();
     
-    server.listen();
+    if let Err(e) = server.listen() {
+      // Bad packets and unreachable clients are expected on udp, keep serving
+      warn!("{}", e);
+    }
     
     tick += delta_time;
     tick_120 += delta_time;
     
-    if tick >= FPS_60 {
+    let tick_length = server.tick_length();
+    if tick >= tick_length {
       tick = 0.0;
-      server.update(FPS_60);
+      server.update(tick_length);
+      server.publish_metrics();
+      server.poll_console();
+      server.poll_map();
     }
     if tick_120 >= FPS_120 {
       tick_120 = 0.0;
       server.send_static_objects();
     }
   }
+  
+  server.save_game();
 }
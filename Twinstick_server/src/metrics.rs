@@ -0,0 +1,215 @@
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+use twinstick_logic::TwinstickGame;
+
+pub const METRICS_ADDR: &str = "127.0.0.1:8009";
+
+// A scraper that stops reading or writing part way through gives up its connection after this
+const METRICS_IO_TIMEOUT: time::Duration = time::Duration::from_millis(500);
+
+#[derive(Default, Clone, Copy)]
+pub struct Counter {
+  pub packets: u64,
+  pub bytes: u64,
+}
+
+impl Counter {
+  pub fn record(&mut self, bytes: usize) {
+    self.packets += 1;
+    self.bytes += bytes as u64;
+  }
+}
+
+pub struct Metrics {
+  started: time::Instant,
+  ticks: u64,
+  last_tick: time::Duration,
+  max_tick: time::Duration,
+  total_tick: time::Duration,
+  players: usize,
+  enemies: usize,
+  player_bullets: usize,
+  enemy_bullets: usize,
+  static_objects: usize,
+  packets_in: BTreeMap<&'static str, Counter>,
+  packets_out: BTreeMap<&'static str, Counter>,
+  dropped_packets: u64,
+}
+
+impl Metrics {
+  pub fn new() -> Metrics {
+    Metrics {
+      started: time::Instant::now(),
+      ticks: 0,
+      last_tick: time::Duration::from_secs(0),
+      max_tick: time::Duration::from_secs(0),
+      total_tick: time::Duration::from_secs(0),
+      players: 0,
+      enemies: 0,
+      player_bullets: 0,
+      enemy_bullets: 0,
+      static_objects: 0,
+      packets_in: BTreeMap::new(),
+      packets_out: BTreeMap::new(),
+      dropped_packets: 0,
+    }
+  }
+
+  pub fn record_tick(&mut self, duration: time::Duration, game: &TwinstickGame) {
+    self.ticks += 1;
+    self.last_tick = duration;
+    self.max_tick = self.max_tick.max(duration);
+    self.total_tick += duration;
+
+    self.players = game.players().len();
+    self.enemies = game.enemies().len();
+    self.player_bullets = game.player_bullets().len();
+    self.enemy_bullets = game.enemy_bullets().len();
+    self.static_objects = game.static_objects().len();
+  }
+
+  pub fn record_in(&mut self, message: &'static str, bytes: usize) {
+    self.packets_in.entry(message).or_default().record(bytes);
+  }
+
+  pub fn record_out(&mut self, message: &'static str, bytes: usize) {
+    self.packets_out.entry(message).or_default().record(bytes);
+  }
+
+  pub fn record_dropped(&mut self) {
+    self.dropped_packets += 1;
+  }
+
+  // Plain text, one metric per line, in the prometheus exposition format
+  pub fn render(&self) -> String {
+    let mut out = String::new();
+
+    let average_tick = if self.ticks > 0 {
+      self.total_tick.as_secs_f64() / self.ticks as f64
+    } else {
+      0.0
+    };
+
+    out += &format!("twinstick_uptime_seconds {}\n", self.started.elapsed().as_secs_f64());
+    out += &format!("twinstick_ticks_total {}\n", self.ticks);
+    out += &format!("twinstick_tick_seconds{{stat=\"last\"}} {}\n", self.last_tick.as_secs_f64());
+    out += &format!("twinstick_tick_seconds{{stat=\"max\"}} {}\n", self.max_tick.as_secs_f64());
+    out += &format!("twinstick_tick_seconds{{stat=\"avg\"}} {}\n", average_tick);
+    out += &format!("twinstick_players {}\n", self.players);
+    out += &format!("twinstick_enemies {}\n", self.enemies);
+    out += &format!("twinstick_bullets{{owner=\"player\"}} {}\n", self.player_bullets);
+    out += &format!("twinstick_bullets{{owner=\"enemy\"}} {}\n", self.enemy_bullets);
+    out += &format!("twinstick_static_objects {}\n", self.static_objects);
+
+    for (direction, counters) in [("in", &self.packets_in), ("out", &self.packets_out)].iter() {
+      for (message, counter) in counters.iter() {
+        out += &format!("twinstick_packets_total{{direction=\"{}\",message=\"{}\"}} {}\n", direction, message, counter.packets);
+        out += &format!("twinstick_bytes_total{{direction=\"{}\",message=\"{}\"}} {}\n", direction, message, counter.bytes);
+      }
+    }
+
+    out += &format!("twinstick_dropped_packets_total {}\n", self.dropped_packets);
+
+    out
+  }
+}
+
+// Answers every tcp connection with the last metrics published from the main loop. Requests
+// are served on their own thread so a slow scraper never holds up a tick.
+pub struct MetricsEndpoint {
+  addr: SocketAddr,
+  snapshot: Arc<Mutex<String>>,
+}
+
+impl MetricsEndpoint {
+  pub fn bind(addr: &str) -> std::io::Result<MetricsEndpoint> {
+    let listener = TcpListener::bind(addr)?;
+    let addr = listener.local_addr()?;
+    let snapshot = Arc::new(Mutex::new(String::new()));
+
+    let served = snapshot.clone();
+    thread::spawn(move || {
+      for stream in listener.incoming() {
+        match stream {
+          Ok(stream) => {
+            let body = served.lock().unwrap().clone();
+            if let Err(e) = MetricsEndpoint::respond(stream, &body) {
+              log::debug!("metrics request failed error={}", e);
+            }
+          },
+          Err(e) => log::debug!("metrics connection failed error={}", e),
+        }
+      }
+    });
+
+    Ok(MetricsEndpoint {
+      addr,
+      snapshot,
+    })
+  }
+
+  pub fn addr(&self) -> SocketAddr {
+    self.addr
+  }
+
+  pub fn publish(&self, metrics: &Metrics) {
+    let body = metrics.render();
+    *self.snapshot.lock().unwrap() = body;
+  }
+
+  fn respond(mut stream: TcpStream, body: &str) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(METRICS_IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(METRICS_IO_TIMEOUT))?;
+
+    // Whatever the request was, the answer is the same
+    let mut request = [0; 512];
+    let _ = stream.read(&mut request);
+
+    let response = format!("HTTP/1.0 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\r\n{}",
+                           body.len(), body);
+    stream.write_all(response.as_bytes())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn counters_are_rendered_per_message() {
+    let mut metrics = Metrics::new();
+    metrics.record_out("Player", 45);
+    metrics.record_out("Player", 45);
+    metrics.record_in("Input", 8);
+    metrics.record_dropped();
+
+    let text = metrics.render();
+    assert!(text.contains("twinstick_packets_total{direction=\"out\",message=\"Player\"} 2\n"));
+    assert!(text.contains("twinstick_bytes_total{direction=\"out\",message=\"Player\"} 90\n"));
+    assert!(text.contains("twinstick_packets_total{direction=\"in\",message=\"Input\"} 1\n"));
+    assert!(text.contains("twinstick_dropped_packets_total 1\n"));
+  }
+
+  #[test]
+  fn published_metrics_are_served_off_the_game_loop() {
+    let endpoint = MetricsEndpoint::bind("127.0.0.1:0").unwrap();
+    let mut metrics = Metrics::new();
+    metrics.record_dropped();
+    endpoint.publish(&metrics);
+
+    // Connected but never sends a request, the next scrape is still answered
+    let _idle = TcpStream::connect(endpoint.addr()).unwrap();
+
+    let mut stream = TcpStream::connect(endpoint.addr()).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.0\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+    assert!(response.ends_with("twinstick_dropped_packets_total 1\n"));
+  }
+}
@@ -0,0 +1,118 @@
+use std::collections::{HashMap, HashSet};
+use std::time;
+
+// How long a client has to say a section arrived before it is sent again, one lost
+// fragment is enough for the whole section to never be put together
+pub const SECTION_ACK_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+// Which sections a streaming client has, or might have. A section only counts as
+// loaded once the client acks it with SectionLoaded.
+#[derive(Default)]
+pub struct SentSections {
+  loaded: HashSet<(i32, i32, i32)>,
+  // When each section still waiting on an ack was last sent
+  waiting: HashMap<(i32, i32, i32), time::Instant>,
+}
+
+impl SentSections {
+  pub fn new() -> SentSections {
+    SentSections {
+      loaded: HashSet::new(),
+      waiting: HashMap::new(),
+    }
+  }
+
+  // Not loaded, and not sent recently enough that the ack could still be on its way
+  pub fn needs(&self, location: &(i32, i32, i32), now: time::Instant) -> bool {
+    if self.loaded.contains(location) {
+      return false;
+    }
+
+    match self.waiting.get(location) {
+      Some(sent) => now.duration_since(*sent) >= SECTION_ACK_TIMEOUT,
+      None => true,
+    }
+  }
+
+  pub fn sent(&mut self, location: (i32, i32, i32), now: time::Instant) {
+    self.waiting.insert(location, now);
+  }
+
+  // Returns false for sections that were dropped since they were sent, the last pieces
+  // can still turn up after the UnloadSection and leave the client holding one
+  pub fn acked(&mut self, location: (i32, i32, i32)) -> bool {
+    if self.waiting.remove(&location).is_some() {
+      self.loaded.insert(location);
+    }
+    self.loaded.contains(&location)
+  }
+
+  // Unloaded or changed, it will be sent again if it is needed
+  pub fn forget(&mut self, location: &(i32, i32, i32)) {
+    self.loaded.remove(location);
+    self.waiting.remove(location);
+  }
+
+  pub fn clear(&mut self) {
+    self.loaded.clear();
+    self.waiting.clear();
+  }
+
+  // Everything the client may be holding, whether or not it has acked it yet
+  pub fn locations(&self) -> Vec<(i32, i32, i32)> {
+    self.loaded.iter().chain(self.waiting.keys()).cloned().collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn sections_are_sent_again_until_they_are_acked() {
+    let mut sections = SentSections::new();
+    let start = time::Instant::now();
+    assert!(sections.needs(&(0, 0, 0), start));
+
+    sections.sent((0, 0, 0), start);
+    assert!(!sections.needs(&(0, 0, 0), start));
+    // A fragment went missing, nothing came back
+    assert!(sections.needs(&(0, 0, 0), start + SECTION_ACK_TIMEOUT));
+
+    sections.sent((0, 0, 0), start + SECTION_ACK_TIMEOUT);
+    assert!(sections.acked((0, 0, 0)));
+    // The resent copy arriving too is fine
+    assert!(sections.acked((0, 0, 0)));
+    assert!(!sections.needs(&(0, 0, 0), start + SECTION_ACK_TIMEOUT*10));
+    assert_eq!(sections.locations(), vec!((0, 0, 0)));
+
+    // Never sent, so the ack is stale
+    assert!(!sections.acked((1, 0, 0)));
+    assert!(sections.needs(&(1, 0, 0), start));
+  }
+
+  #[test]
+  fn sections_left_and_reentered_are_sent_and_acked_again() {
+    let mut sections = SentSections::new();
+    let start = time::Instant::now();
+    sections.sent((0, 0, 0), start);
+    sections.acked((0, 0, 0));
+    sections.sent((1, 0, 0), start);
+
+    // The player walked away before (1, 0, 0) was acked, both get unloaded
+    let mut dropped = sections.locations();
+    dropped.sort();
+    assert_eq!(dropped, vec!((0, 0, 0), (1, 0, 0)));
+    for location in &dropped {
+      sections.forget(location);
+    }
+    assert!(!sections.acked((1, 0, 0)));
+
+    // Back again straight away, nothing waits on the old sends
+    assert!(sections.needs(&(0, 0, 0), start));
+    assert!(sections.needs(&(1, 0, 0), start));
+    sections.sent((1, 0, 0), start);
+    assert!(!sections.needs(&(1, 0, 0), start));
+    assert!(sections.acked((1, 0, 0)));
+  }
+}
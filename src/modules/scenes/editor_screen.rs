@@ -0,0 +1,322 @@
+use maat_graphics::math;
+use maat_graphics::DrawCall;
+use maat_graphics::ModelData;
+use maat_graphics::camera::PerspectiveCamera;
+
+use crate::modules::scenes::Scene;
+use crate::modules::scenes::SceneData;
+use crate::modules::scenes::PlayScreen;
+use crate::cgmath::{Vector2, Vector3 as cgVector3, Vector4};
+
+use std::env;
+use std::path::PathBuf;
+
+use twinstick_logic::{Map, MapStaticObject, GenericObject, Section, Wall, Vector3, MAP_ENV, DEFAULT_MAP_PATH};
+
+const CAMERA_ZOOM_SPEED: f32 = 0.05; // percentage per second
+const MIN_ZOOM: f32 = 10.0;
+const MAX_ZOOM: f32 = 400.0;
+
+// Sections either side of the cursor the grid is drawn for
+const GRID_RADIUS: i32 = 4;
+const GRID_LINE: f64 = 0.3;
+const MARKER_SIZE: f64 = 2.0;
+const ROTATION_STEP: f64 = 90.0;
+const SCALE_STEP: f64 = 0.5;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Tool {
+  Floor,
+  Wall(Wall),
+  Model,
+  SpawnPoint,
+}
+
+impl Tool {
+  // In the order of the number keys that pick them
+  const ALL: [Tool; 7] = [Tool::Floor, Tool::Wall(Wall::Left), Tool::Wall(Wall::Right), Tool::Wall(Wall::Front),
+                          Tool::Wall(Wall::Back), Tool::Model, Tool::SpawnPoint];
+  
+  fn name(&self) -> String {
+    match self {
+      Tool::Floor => "Floor".to_string(),
+      Tool::Wall(wall) => format!("{:?} wall", wall),
+      Tool::Model => "Model".to_string(),
+      Tool::SpawnPoint => "Spawn point".to_string(),
+    }
+  }
+}
+
+pub struct EditorScreen {
+  data: SceneData,
+  camera: PerspectiveCamera,
+  zoom: f32,
+  map: Map,
+  map_path: PathBuf,
+  // What the map's sections look like, rebuilt after every edit
+  objects: Vec<Box<dyn GenericObject>>,
+  cursor: (i32, i32, i32),
+  tool: Tool,
+  model_idx: usize,
+  model_rotation: f64,
+  model_scale: f64,
+  last_left_mouse: bool,
+  last_right_mouse: bool,
+  status: String,
+}
+
+impl EditorScreen {
+  pub fn new(window_size: Vector2<f32>, model_data: Vec<ModelData>) -> EditorScreen {
+    let map_path = PathBuf::from(env::var(MAP_ENV).unwrap_or_else(|_| DEFAULT_MAP_PATH.to_string()));
+    let (map, status) = match Map::load(&map_path) {
+      Ok(map) => (map, format!("Loaded {}", map_path.display())),
+      Err(e) => (Map::default_map(), format!("Starting from the default map, {}: {}", map_path.display(), e)),
+    };
+    
+    let mut camera = PerspectiveCamera::default_vk();
+    camera.set_target(cgVector3::new(0.0, 0.0, 0.0));
+    
+    let mut editor = EditorScreen {
+      data: SceneData::new(window_size, model_data),
+      camera,
+      zoom: 120.0,
+      map,
+      map_path,
+      objects: Vec::new(),
+      cursor: (0, 0, 0),
+      tool: Tool::Floor,
+      model_idx: 0,
+      model_rotation: 0.0,
+      model_scale: 1.0,
+      last_left_mouse: false,
+      last_right_mouse: false,
+      status,
+    };
+    editor.rebuild_objects();
+    
+    editor
+  }
+  
+  fn rebuild_objects(&mut self) {
+    self.objects = self.map.sections().into_iter().flat_map(|mut section| section.static_objects()).collect();
+  }
+  
+  fn model_name(&self) -> Option<String> {
+    self.data.model_data.get(self.model_idx).map(|model| model.name().to_string())
+  }
+  
+  // The selected model as it would be placed under the cursor, sat on the floor
+  fn model_at_cursor(&self) -> Option<MapStaticObject> {
+    let model = self.data.model_data.get(self.model_idx)?;
+    let (x, level, z) = self.cursor;
+    let mut pos = self.map.section_centre(x, level, z);
+    pos.y += model.size().y as f64*self.model_scale*0.5;
+    
+    Some(MapStaticObject {
+      pos,
+      size: Vector3::new_same(self.model_scale),
+      rotation: Vector3::new(0.0, self.model_rotation, 0.0),
+      model: model.name().to_string(),
+    })
+  }
+  
+  fn place(&mut self) {
+    let (x, level, z) = self.cursor;
+    match self.tool {
+      Tool::Floor => self.map.set_floor(x, level, z, true),
+      Tool::Wall(wall) => self.map.set_wall(x, level, z, wall, true),
+      Tool::Model => {
+        match self.model_at_cursor() {
+          Some(object) => self.map.add_static_object(object),
+          None => self.status = "No models are loaded yet".to_string(),
+        }
+      },
+      Tool::SpawnPoint => self.map.add_spawn_point(x, level, z),
+    }
+    self.rebuild_objects();
+  }
+  
+  fn remove(&mut self) {
+    let (x, level, z) = self.cursor;
+    match self.tool {
+      Tool::Floor => self.map.set_floor(x, level, z, false),
+      Tool::Wall(wall) => self.map.set_wall(x, level, z, wall, false),
+      Tool::Model => {
+        let removed = self.map.remove_static_objects(x, level, z);
+        self.status = format!("Removed {} models", removed);
+      },
+      Tool::SpawnPoint => {
+        if self.map.remove_spawn_points(x, level, z) == 0 {
+          self.status = "Nothing removed, the map needs a spawn point".to_string();
+        }
+      },
+    }
+    self.rebuild_objects();
+  }
+  
+  fn save(&mut self) {
+    self.status = match self.map.validate().and_then(|_| self.map.write_to(&self.map_path)) {
+      Ok(()) => format!("Saved {}", self.map_path.display()),
+      Err(e) => format!("Not saved: {}", e),
+    };
+  }
+  
+  fn load(&mut self) {
+    match Map::load(&self.map_path) {
+      Ok(map) => {
+        self.map = map;
+        self.rebuild_objects();
+        self.status = format!("Loaded {}", self.map_path.display());
+      },
+      Err(e) => self.status = format!("Not loaded: {}", e),
+    }
+  }
+  
+  fn handle_key(&mut self, key: &str) {
+    let (x, level, z) = self.cursor;
+    match key {
+      // -x is right and -z is away from the camera
+      "w" => self.cursor = (x, level, z - 1),
+      "s" => self.cursor = (x, level, z + 1),
+      "a" => self.cursor = (x + 1, level, z),
+      "d" => self.cursor = (x - 1, level, z),
+      "e" => self.cursor = (x, level + 1, z),
+      "q" => self.cursor = (x, level - 1, z),
+      "[" | "]" => {
+        let models = self.data.model_data.len();
+        if models > 0 {
+          let step = if key == "]" { 1 } else { models - 1 };
+          self.model_idx = (self.model_idx + step) % models;
+        }
+      },
+      "r" => self.model_rotation = (self.model_rotation + ROTATION_STEP) % 360.0,
+      "=" => self.model_scale += SCALE_STEP,
+      "-" => self.model_scale = (self.model_scale - SCALE_STEP).max(SCALE_STEP),
+      // Keys are the characters typed, so these are with shift held
+      "S" => self.save(),
+      "L" => self.load(),
+      _ => {
+        if let Ok(n) = key.parse::<usize>() {
+          if n >= 1 && n <= Tool::ALL.len() {
+            self.tool = Tool::ALL[n-1];
+          }
+        }
+      },
+    }
+  }
+  
+  fn draw_box(&self, pos: Vector3, size: Vector3, model: &str, draw_calls: &mut Vec<DrawCall>) {
+    draw_calls.push(DrawCall::draw_model(pos.to_cgmath(), size.to_cgmath(), cgVector3::new(0.0, 0.0, 0.0), model.to_string()));
+  }
+  
+  // Lines along the section edges on the cursor's floor
+  fn draw_grid(&self, draw_calls: &mut Vec<DrawCall>) {
+    let (cx, level, cz) = self.cursor;
+    let size = self.map.section_size;
+    let y = Section::floor_top(level);
+    let length = size*(GRID_RADIUS*2 + 1) as f64;
+    for i in -GRID_RADIUS..=GRID_RADIUS+1 {
+      let edge = i as f64 - 0.5;
+      let x = (cx as f64 + edge)*size;
+      let z = (cz as f64 + edge)*size;
+      self.draw_box(Vector3::new(x, y, cz as f64*size), Vector3::new(GRID_LINE, GRID_LINE, length), "unit_floor", draw_calls);
+      self.draw_box(Vector3::new(cx as f64*size, y, z), Vector3::new(length, GRID_LINE, GRID_LINE), "unit_floor", draw_calls);
+    }
+  }
+}
+
+impl Scene for EditorScreen {
+  fn data(&self) -> &SceneData {
+    &self.data
+  }
+  
+  fn mut_data(&mut self) -> &mut SceneData {
+    &mut self.data
+  }
+  
+  fn future_scene(&mut self, _window_size: Vector2<f32>) -> Box<dyn Scene> {
+    let dim = self.data().window_dim;
+    Box::new(PlayScreen::new(dim, self.data.model_data.clone()))
+  }
+  
+  fn update(&mut self, delta_time: f32) {
+    for key in self.get_keys_pressed_this_frame() {
+      self.handle_key(&key);
+    }
+    
+    // Only on the click, not every frame it is held
+    let left_mouse = self.data().left_mouse;
+    let right_mouse = self.data().right_mouse;
+    if left_mouse && !self.last_left_mouse {
+      self.place();
+    }
+    if right_mouse && !self.last_right_mouse {
+      self.remove();
+    }
+    self.last_left_mouse = left_mouse;
+    self.last_right_mouse = right_mouse;
+    
+    if self.data().scroll_delta < 0.0 {
+      self.zoom = (self.zoom + CAMERA_ZOOM_SPEED*self.zoom*self.zoom*delta_time + 0.01).min(MAX_ZOOM);
+    }
+    if self.data().scroll_delta > 0.0 {
+      self.zoom = (self.zoom - CAMERA_ZOOM_SPEED*self.zoom*self.zoom*delta_time - 0.01).max(MIN_ZOOM);
+    }
+    
+    // Looking down on the cursor from behind it, the way the game's camera does
+    let (x, level, z) = self.cursor;
+    let target = self.map.section_centre(x, level, z).to_cgmath();
+    self.camera.set_target(target);
+    self.camera.set_position(target + cgVector3::new(0.0, self.zoom, self.zoom));
+    self.camera.set_up(cgVector3::new(0.0, -1.0, 0.0));
+    self.camera.set_front(math::normalise_vector3(target - self.camera.get_position()));
+  }
+  
+  fn draw(&self, draw_calls: &mut Vec<DrawCall>) {
+    let dim = self.data().window_dim;
+    let height = dim.y as f32;
+    let (x, level, z) = self.cursor;
+    
+    draw_calls.push(DrawCall::set_camera(self.camera.clone()));
+    
+    // Floors above the cursor's would hide it
+    for object in &self.objects {
+      if Section::floor_at(object.position().y) <= level {
+        object.draw(false, draw_calls);
+      }
+    }
+    
+    self.draw_grid(draw_calls);
+    
+    let size = self.map.section_size;
+    let mut cursor_pos = self.map.section_centre(x, level, z);
+    cursor_pos.y += GRID_LINE;
+    self.draw_box(cursor_pos, Vector3::new(size*0.9, GRID_LINE, size*0.9), "debug_cube", draw_calls);
+    
+    for point in &self.map.spawn_points {
+      if Section::floor_at(point.y) == level {
+        self.draw_box(point.clone(), Vector3::new_same(MARKER_SIZE), "hexagon", draw_calls);
+      }
+    }
+    
+    if self.tool == Tool::Model {
+      if let Some(object) = self.model_at_cursor() {
+        object.to_object().draw(false, draw_calls);
+      }
+    }
+    
+    let model = self.model_name().unwrap_or_else(|| "none".to_string());
+    let lines = vec!(
+      format!("Tool: {} (1-7)  Model: {} ([ ])  Rotation: {} (r)  Scale: {} (- =)", self.tool.name(), model, self.model_rotation, self.model_scale),
+      format!("Section: ({}, {}, {}) (wasd, q e for floors)  Left click places, right click removes", x, level, z),
+      format!("{} (shift+S saves, shift+L loads)  {}", self.map_path.display(), self.status),
+    );
+    for i in 0..lines.len() {
+      draw_calls.push(DrawCall::draw_text_basic(Vector2::new(10.0, height - 32.0 - 32.0*i as f32),
+                                                Vector2::new(96.0, 96.0),
+                                                Vector4::new(1.0, 1.0, 1.0, 1.0),
+                                                lines[i].to_string(),
+                                                String::from("Arial")));
+    }
+  }
+}
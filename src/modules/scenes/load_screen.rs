@@ -2,9 +2,11 @@ use maat_graphics::DrawCall;
 
 use crate::modules::scenes::Scene;
 use crate::modules::scenes::SceneData;
-use crate::modules::scenes::{PlayScreen};
+use crate::modules::scenes::{PlayScreen, EditorScreen};
 use crate::cgmath::{Vector2, Vector4};
 
+use std::env;
+
 const LOGO_TIMER: f32 = 1.5;
 
 pub struct LoadScreen {
@@ -38,6 +40,10 @@ impl Scene for LoadScreen {
   
   fn future_scene(&mut self, _window_size: Vector2<f32>) -> Box<dyn Scene> {
     let dim = self.data().window_dim;
+    // Started with --editor to work on the map instead of playing
+    if env::args().any(|arg| arg == "--editor") {
+      return Box::new(EditorScreen::new(dim, self.data.model_data.clone()));
+    }
     Box::new(PlayScreen::new(dim, self.data.model_data.clone()))
   }
   
@@ -14,9 +14,11 @@ use maat_graphics::cgmath::Vector2;
 
 pub use self::load_screen::LoadScreen;
 pub use self::play_screen::PlayScreen;
+pub use self::editor_screen::EditorScreen;
 
 mod load_screen;
 mod play_screen;
+mod editor_screen;
 
 pub struct SceneData {
   pub should_close: bool,
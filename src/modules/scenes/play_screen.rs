@@ -7,14 +7,17 @@ use crate::modules::scenes::Scene;
 use crate::modules::scenes::SceneData;
 use crate::cgmath::{Vector2, Vector3 as cgVector3, Vector4};
 
-//use crate::modules::objects::{Character, StaticObject, GenericObject, MovingPlatform};
+//use crate::modules::objects::{Character, StaticObject, GenericObject};
 //use crate::modules::collisions;
 use rand::prelude::ThreadRng;
 use rand::thread_rng;
 
-use twinstick_logic::{TwinstickGame, Character, Enemy, Input, DataType, GenericObject, 
+use std::path::Path;
+
+use twinstick_logic::{TwinstickGame, TickObjects, Character, Enemy, Input, DataType, GenericObject, 
                       Vector3, collisions, SendDynamicObject, SendDynamicObjectUpdate,
-                      SendPlayerObjectUpdate};
+                      SendPlayerObjectUpdate, CollisionMatrix, ModelShapes, Section, SECTION_SIZE,
+                      StaticSections, SectionAssembler, Features};
 use twinstick_client::{TwinstickClient};
 
 const CAMERA_DEFAULT_X: f32 = 83.93359;
@@ -26,6 +29,8 @@ const CAMERA_DEFAULT_SPEED: f32 = 50.0;
 
 const CAMERA_ZOOM_SPEED: f32 = 0.05; // percentage per second
 
+const CHAT_LINES: usize = 5;
+
 pub struct PlayScreen {
   data: SceneData,
   _rng: ThreadRng,
@@ -33,14 +38,21 @@ pub struct PlayScreen {
   last_mouse_pos: Vector2<f32>,
   players: Vec<Box<dyn GenericObject>>,
   enemies: Vec<Box<dyn GenericObject>>,
-  static_objects: Vec<Box<dyn GenericObject>>,
+  static_sections: StaticSections,
+  section_assembler: SectionAssembler,
+  collision_matrix: CollisionMatrix,
+  model_shapes: ModelShapes,
   player_bullets: Vec<Box<dyn GenericObject>>,
   enemy_bullets: Vec<Box<dyn GenericObject>>,
   dynamic_objects: Vec<Box<dyn GenericObject>>,
+  interactives: Vec<Box<dyn GenericObject>>,
+  // Moved along their paths here too and eased towards where the server says they are
+  platforms: Vec<Box<dyn GenericObject>>,
   //decorative_objects: Vec<Box<dyn GenericObject>>,
   character_idx: Option<usize>,
   zoom: f32,
   client: TwinstickClient,
+  chat: Vec<String>,
 }
 
 impl PlayScreen {
@@ -56,9 +68,10 @@ impl PlayScreen {
     camera.set_move_speed(CAMERA_DEFAULT_SPEED);
     camera.set_target(cgVector3::new(0.0, 0.0, 0.0));
     
-    let mut client = TwinstickClient::new("127.0.0.1:8008");//"45.77.234.65:8008");//"127.0.0.1:8008");
-    client.connect();
-    client.send();
+    let mut client = TwinstickClient::new("127.0.0.1:8008").expect("Failed to bind a local udp port");//"45.77.234.65:8008");//"127.0.0.1:8008");
+    if let Err(e) = client.connect() {
+      println!("{}", e);
+    }
     
     PlayScreen {
       data: SceneData::new(window_size, model_data),
@@ -67,14 +80,33 @@ impl PlayScreen {
       last_mouse_pos: Vector2::new(-1.0, -1.0),
       players: Vec::new(),
       enemies: Vec::new(),
-      static_objects: Vec::new(),
+      static_sections: StaticSections::new(SECTION_SIZE),
+      section_assembler: SectionAssembler::new(),
+      collision_matrix: CollisionMatrix::new(),
+      model_shapes: ModelShapes::load(Path::new("./resources/models")).unwrap_or_else(|e| {
+        println!("Static objects will use their whole hitbox: {}", e);
+        ModelShapes::new()
+      }),
       player_bullets: Vec::new(),
       enemy_bullets: Vec::new(),
       dynamic_objects: Vec::new(),
+      interactives: Vec::new(),
+      platforms: Vec::new(),
      // decorative_objects,
       character_idx: None,
       zoom: 22.0,
       client,
+      chat: Vec::new(),
+    }
+  }
+  
+  pub fn send(&mut self, data_type: DataType) {
+    if self.client.disconnected() {
+      return;
+    }
+    
+    if let Err(e) = self.client.send_datatype(data_type) {
+      println!("{}", e);
     }
   }
   
@@ -143,23 +175,28 @@ impl PlayScreen {
   
   pub fn process_player_input(&mut self, char_idx: i32) {
     if self.data().keys.w_pressed() {
-      self.client.send_datatype(DataType::Input(Input::W));
+      self.send(DataType::Input(Input::W));
     } else if self.data().keys.s_pressed() {
-      self.client.send_datatype(DataType::Input(Input::S));
+      self.send(DataType::Input(Input::S));
     }
     
     if self.data().keys.d_pressed() {
-      self.client.send_datatype(DataType::Input(Input::D));
+      self.send(DataType::Input(Input::D));
     } else if self.data().keys.a_pressed() {
-      self.client.send_datatype(DataType::Input(Input::A));
+      self.send(DataType::Input(Input::A));
     }
     
     if self.data().keys.space_pressed() {
-      self.client.send_datatype(DataType::Input(Input::Space));
+      self.send(DataType::Input(Input::Space));
     }
     
     if self.data().left_mouse {
-      self.client.send_datatype(DataType::Input(Input::LeftClick));
+      self.send(DataType::Input(Input::LeftClick));
+    }
+    
+    // Older servers don't know the input, only the server flips switches
+    if self.client.features().contains(Features::INTERACTIVES) && self.get_keys_pressed_this_frame().contains(&"e".to_string()) {
+      self.send(DataType::Input(Input::Interact));
     }
     
     if char_idx != -1 {
@@ -206,8 +243,9 @@ impl Scene for PlayScreen {
     
     let mouse = self.data().mouse_pos;
     
-    if self.client.disconnected() {
-      self.client.connect();
+    if self.client.disconnected() && self.client.rejection().is_none() && self.client.closed_reason().is_none() {
+      // Keeps failing until the server is up, just try again next update
+      let _ = self.client.connect();
     }
     
     let mut char_idx: i32 = -1;
@@ -220,18 +258,39 @@ impl Scene for PlayScreen {
     
     if char_idx != -1 {
       let rot = self.players[char_idx as usize].rotation().y;
-      self.client.send_datatype(DataType::PlayerRotation(rot, char_idx as usize));
+      self.send(DataType::PlayerRotation(rot, char_idx as usize));
     }
     
     match self.client.recieve() {
-      Some(d_type) => {
+      Ok(Some(d_type)) => {
         match d_type {
           DataType::PlayerNum(i) => {
             self.character_idx = Some(i);
           },
           DataType::StaticObject(object) => {
-            let object = object.to_static_object();
-            self.static_objects.push(Box::new(object));
+            // Servers that don't stream sections only say where each object is
+            let mut object = object.to_static_object();
+            self.model_shapes.attach(&mut object);
+            let location = ((object.position().x/SECTION_SIZE).round() as i32,
+                            Section::floor_at(object.position().y),
+                            (object.position().z/SECTION_SIZE).round() as i32);
+            self.static_sections.push(location, Box::new(object));
+          },
+          DataType::LoadSection(fragment) => {
+            if let Some((location, objects)) = self.section_assembler.add(fragment) {
+              let objects = objects.iter().map(|object| {
+                let mut object = object.to_static_object();
+                self.model_shapes.attach(&mut object);
+                Box::new(object) as Box<dyn GenericObject>
+              }).collect();
+              self.static_sections.insert(location, objects);
+              // Otherwise the server sends it again, thinking a piece went missing
+              self.send(DataType::SectionLoaded(location.0, location.1, location.2));
+            }
+          },
+          DataType::UnloadSection(x, level, z) => {
+            self.section_assembler.forget((x, level, z));
+            self.static_sections.remove(&vec!((x, level, z)));
           },
           DataType::Player(p, idx) => {
             self.update_player(p, idx);
@@ -258,25 +317,74 @@ impl Scene for PlayScreen {
           DataType::Enemy(e, idx) => {
             self.update_enemy(e, idx);
           },
+          DataType::Chat(message) => {
+            self.chat.push(message);
+            if self.chat.len() > CHAT_LINES {
+              self.chat.remove(0);
+            }
+          },
+          DataType::ClearStaticObjects => {
+            // Map was reloaded, the new objects stream in after this
+            self.static_sections.clear();
+            self.section_assembler.clear();
+          },
+          DataType::AddInteractive(interactive) => {
+            self.interactives.push(Box::new(interactive.to_interactive()));
+          },
+          DataType::InteractiveState(idx, active) => {
+            if let Some(interactive) = self.interactives.get_mut(idx) {
+              interactive.mut_interactive().unwrap().set_active(active);
+            }
+          },
+          DataType::ClearInteractives => {
+            self.interactives.clear();
+          },
+          DataType::AddPlatform(platform) => {
+            match platform.to_platform() {
+              Ok(platform) => self.platforms.push(Box::new(platform)),
+              Err(e) => println!("{}", e),
+            }
+          },
+          DataType::PlatformState(idx, pos, next) => {
+            if let Some(platform) = self.platforms.get_mut(idx) {
+              platform.mut_platform().unwrap().sync(pos, next);
+            }
+          },
+          DataType::ClearPlatforms => {
+            self.platforms.clear();
+          },
+          DataType::DropStaticObjects(min, max) => {
+            // The server unloaded a section nobody is near any more
+            let bounds = (min, max);
+            self.static_sections.retain(|object| !Section::contains(&bounds, object.position()));
+          },
           _ => {},
         }
       },
-      None => {
+      Ok(None) => {
         
+      },
+      Err(e) => {
+        println!("{}", e);
       }
     }
     
     self.process_player_input(char_idx);
     self.update_player_rotation(char_idx, width, height, mouse);
     
-    TwinstickGame::update(&mut self.players,
-                          &mut self.enemies,
-                          &mut self.player_bullets,
-                          &mut self.enemy_bullets,
-                          &mut self.static_objects,
-                          &mut self.dynamic_objects,
-                          self.character_idx,
-                          delta_time as f64);
+    let (static_objects, static_grid) = self.static_sections.objects_and_grid();
+    let objects = TickObjects {
+      players: &mut self.players,
+      enemies: &mut self.enemies,
+      player_bullets: &mut self.player_bullets,
+      enemy_bullets: &mut self.enemy_bullets,
+      static_objects,
+      static_grid,
+      interactives: &mut self.interactives,
+      platforms: &mut self.platforms,
+      dynamic_objects: &mut self.dynamic_objects,
+    };
+    TwinstickGame::update(objects, &self.collision_matrix, self.character_idx, delta_time as f64);
     /*
     if self.data().scroll_delta < 0.0 {
       self.zoom += CAMERA_ZOOM_SPEED*self.zoom*self.zoom *delta_time + 0.01;
@@ -338,7 +446,24 @@ impl Scene for PlayScreen {
       enemy.draw(true, draw_calls);
     }
     
-    for object in &self.static_objects {
+    // Floors above the player's own would hide them from the camera
+    let level = self.character_idx.filter(|idx| *idx < self.players.len())
+                                  .map(|idx| Section::floor_at(self.players[idx].position().y));
+    for object in self.static_sections.objects() {
+      if let Some(level) = level {
+        if Section::floor_at(object.position().y) > level {
+          continue;
+        }
+      }
+      object.draw(true, draw_calls);
+    }
+    
+    for object in self.interactives.iter().chain(self.platforms.iter()) {
+      if let Some(level) = level {
+        if Section::floor_at(object.position().y) > level {
+          continue;
+        }
+      }
       object.draw(true, draw_calls);
     }
     
@@ -354,12 +479,30 @@ impl Scene for PlayScreen {
       bullets.draw(true, draw_calls);
     }
     
+    for i in 0..self.chat.len() {
+      draw_calls.push(DrawCall::draw_text_basic(Vector2::new(10.0, 64.0 + 32.0*(self.chat.len()-i) as f32),
+                                                Vector2::new(96.0, 96.0),
+                                                Vector4::new(1.0, 1.0, 0.6, 1.0),
+                                                self.chat[i].to_string(),
+                                                String::from("Arial")));
+    }
+
     if self.client.disconnected() {
+      let message = if let Some(rejection) = self.client.rejection() {
+        rejection.to_string()
+      } else if let Some(reason) = self.client.closed_reason() {
+        reason.to_string()
+      } else if let Some(error) = self.client.server_error() {
+        error.to_string()
+      } else {
+        String::from("Attempting to connect to server...")
+      };
+      
       draw_calls.push(
         DrawCall::draw_text_basic_centered(Vector2::new(width*0.5, height*0.5), 
                                 Vector2::new(128.0, 128.0),
                                 Vector4::new(1.0, 1.0, 1.0, 1.0),
-                                String::from("Attempting to connect to server..."),
+                                message,
                                 String::from("Arial"))
       );
     }